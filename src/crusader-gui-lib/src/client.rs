@@ -1,12 +1,11 @@
 use crate::{Tab, Tester};
 use crusader_lib::{
     file_format::RawResult,
-    protocol,
-    test::{self},
+    test::{self, ProgressSample},
     with_time, Config,
 };
 use eframe::{
-    egui::{self, vec2, Grid, ScrollArea, TextEdit, Ui},
+    egui::{self, vec2, Grid, ProgressBar, ScrollArea, TextEdit, Ui},
     emath::Align,
 };
 use serde::{Deserialize, Serialize};
@@ -37,18 +36,66 @@ pub struct ClientSettings {
 }
 
 impl ClientSettings {
+    /// Checks for settings which would make the test fail to start or run.
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.idle_test {
+            // A zero interval disables latency measurement, which the idle test only does
+            if self.latency_sample_interval == 0 {
+                return Err("The latency sample interval must be at least 1 millisecond");
+            }
+            if self.idle_duration <= 0.0 {
+                return Err("The duration must be greater than zero");
+            }
+            return Ok(());
+        }
+        if !self.download && !self.upload && !self.bidirectional {
+            return Err("At least one of download, upload or bidirectional must be selected");
+        }
+        if self.streams == 0 {
+            return Err("At least one stream is required");
+        }
+        if self.load_duration <= 0.0 {
+            return Err("The load duration must be greater than zero");
+        }
+        if self.grace_duration < 0.0 || self.stream_stagger < 0.0 {
+            return Err("The grace duration and stream stagger can not be negative");
+        }
+        if self.throughput_sample_interval == 0 {
+            return Err("The throughput sample interval must be at least 1 millisecond");
+        }
+        Ok(())
+    }
+
     fn config(&self) -> Config {
-        Config {
-            port: protocol::PORT,
-            streams: self.streams,
-            grace_duration: Duration::from_secs_f64(self.grace_duration),
-            load_duration: Duration::from_secs_f64(self.load_duration),
-            stream_stagger: Duration::from_secs_f64(self.stream_stagger),
-            download: self.download,
-            upload: self.upload,
-            bidirectional: self.bidirectional,
-            ping_interval: Duration::from_millis(self.latency_sample_interval),
-            throughput_interval: Duration::from_millis(self.throughput_sample_interval),
+        Config::builder()
+            .streams(self.streams)
+            .grace_duration(Duration::from_secs_f64(self.grace_duration))
+            .load_duration(Duration::from_secs_f64(self.load_duration))
+            .stream_stagger(Duration::from_secs_f64(self.stream_stagger))
+            .download(self.download)
+            .upload(self.upload)
+            .bidirectional(self.bidirectional)
+            .ping_interval(Duration::from_millis(self.latency_sample_interval))
+            .throughput_interval(Duration::from_millis(self.throughput_sample_interval))
+            .build()
+    }
+
+    /// The comma separated latency peers. A local peer is located if none are given.
+    fn latency_peers(&self) -> Vec<Option<&str>> {
+        if !self.latency_peer {
+            return Vec::new();
+        }
+        let peers: Vec<_> = self
+            .latency_peer_server
+            .split(',')
+            .map(str::trim)
+            .filter(|peer| !peer.is_empty())
+            .map(Some)
+            .collect();
+        if peers.is_empty() {
+            vec![None]
+        } else {
+            peers
         }
     }
 }
@@ -77,6 +124,8 @@ impl Default for ClientSettings {
 
 pub struct Client {
     rx: mpsc::UnboundedReceiver<String>,
+    progress_rx: mpsc::UnboundedReceiver<ProgressSample>,
+    progress: Option<ProgressSample>,
     pub done: Option<oneshot::Receiver<Option<Result<RawResult, String>>>>,
     pub abort: Option<oneshot::Sender<()>>,
 }
@@ -89,18 +138,31 @@ pub enum ClientState {
 }
 
 impl Tester {
-    fn start_client(&mut self, ctx: &egui::Context) {
+    fn start_client(&mut self, ctx: &egui::Context, quick: bool) {
         self.save_settings();
+
+        self.client_error = None;
+        if !quick {
+            if let Err(error) = self.settings.client.validate() {
+                self.client_error = Some(error.to_owned());
+                return;
+            }
+        }
+
         self.msgs.clear();
         self.msg_scrolled = 0;
 
         let (signal_done, done) = oneshot::channel();
         let (tx, rx) = mpsc::unbounded_channel();
+        let (progress_tx, progress_rx) = mpsc::unbounded_channel();
 
         let ctx = ctx.clone();
         let ctx_ = ctx.clone();
+        let ctx__ = ctx.clone();
 
-        let config = if self.settings.client.idle_test {
+        let config = if quick {
+            Config::fast()
+        } else if self.settings.client.idle_test {
             let mut config = ClientSettings::default().config();
             config.grace_duration = Duration::from_secs_f64(self.settings.client.idle_duration);
             config.ping_interval =
@@ -117,14 +179,15 @@ impl Tester {
             config,
             (!self.settings.client.server.trim().is_empty())
                 .then_some(&self.settings.client.server),
-            self.settings.client.latency_peer.then_some(
-                (!self.settings.client.latency_peer_server.trim().is_empty())
-                    .then_some(&self.settings.client.latency_peer_server),
-            ),
+            &self.settings.client.latency_peers(),
             Arc::new(move |msg| {
                 tx.send(with_time(msg)).unwrap();
                 ctx.request_repaint();
             }),
+            Some(Arc::new(move |sample| {
+                progress_tx.send(sample).ok();
+                ctx__.request_repaint();
+            })),
             Box::new(move |result| {
                 signal_done.send(result).map_err(|_| ()).unwrap();
                 ctx_.request_repaint();
@@ -134,6 +197,8 @@ impl Tester {
         self.client = Some(Client {
             done: Some(done),
             rx,
+            progress_rx,
+            progress: None,
             abort: Some(abort),
         });
         self.client_state = ClientState::Running;
@@ -165,7 +230,7 @@ impl Tester {
             ui.separator();
 
             ui.horizontal_wrapped(|ui| {
-                ui.checkbox(&mut self.settings.client.latency_peer, "Latency peer:").on_hover_text("Specifies another server (peer) which will also measure the latency to the server independently of the client");
+                ui.checkbox(&mut self.settings.client.latency_peer, "Latency peer:").on_hover_text("Specifies another server (peer) which will also measure the latency to the server independently of the client. Separate several peers with commas");
                 ui.add_enabled_ui(self.settings.client.latency_peer, |ui| {
                     ui.add(
                         TextEdit::singleline(&mut self.settings.client.latency_peer_server)
@@ -256,7 +321,7 @@ impl Tester {
                     ui.label("Latency sample interval:");
                     ui.add(
                         egui::DragValue::new(&mut self.settings.client.latency_sample_interval)
-                            .range(1..=1000)
+                            .range(0..=1000)
                             .speed(0.05),
                     );
                     ui.label("milliseconds");
@@ -313,7 +378,7 @@ impl Tester {
                 ui.label("Latency sample interval: ");
                 ui.add(
                     egui::DragValue::new(&mut self.settings.client.latency_sample_interval)
-                        .range(1..=1000)
+                        .range(0..=1000)
                         .speed(0.05),
                 );
                 ui.label("milliseconds");
@@ -346,7 +411,7 @@ impl Tester {
             ui.separator();
 
             ui.horizontal_wrapped(|ui| {
-                ui.checkbox(&mut self.settings.client.latency_peer, "Latency peer:").on_hover_text("Specifies another server (peer) which will also measure the latency to the server independently of the client");
+                ui.checkbox(&mut self.settings.client.latency_peer, "Latency peer:").on_hover_text("Specifies another server (peer) which will also measure the latency to the server independently of the client. Separate several peers with commas");
                 ui.add_enabled_ui(self.settings.client.latency_peer, |ui| {
                     ui.add(
                         TextEdit::singleline(&mut self.settings.client.latency_peer_server)
@@ -422,7 +487,10 @@ impl Tester {
                     && response.lost_focus()
                     && ui.input(|i| i.key_pressed(egui::Key::Enter))
                 {
-                    self.start_client(ctx)
+                    self.start_client(ctx, false)
+                }
+                if let Some(address) = self.server_finder(ctx, ui) {
+                    self.settings.client.server = address;
                 }
             });
 
@@ -441,7 +509,16 @@ impl Tester {
                 }
                 ClientState::Stopped => {
                     if ui.button("Start test").clicked() {
-                        self.start_client(ctx)
+                        self.start_client(ctx, false)
+                    }
+                    if ui
+                        .button("Quick test")
+                        .on_hover_text(
+                            "Run a 5 second download test using a single stream, ignoring the settings below",
+                        )
+                        .clicked()
+                    {
+                        self.start_client(ctx, true)
                     }
                 }
             }
@@ -449,6 +526,11 @@ impl Tester {
 
         ui.separator();
 
+        if let Some(error) = self.client_error.as_ref() {
+            ui.label(format!("Error: {}", error));
+            ui.separator();
+        }
+
         ScrollArea::vertical()
             .auto_shrink([false; 2])
             .show(ui, |ui| {
@@ -505,6 +587,15 @@ impl Tester {
                         self.msgs.push(msg);
                     }
 
+                    while let Ok(sample) = client.progress_rx.try_recv() {
+                        client.progress = Some(sample);
+                    }
+
+                    if let Some(progress) = client.progress {
+                        ui.separator();
+                        progress_bar(ui, progress);
+                    }
+
                     if let Ok(result) = client.done.as_mut().unwrap().try_recv() {
                         match result {
                             Some(Ok(result)) => {
@@ -541,3 +632,13 @@ impl Tester {
             });
     }
 }
+
+fn progress_bar(ui: &mut Ui, progress: ProgressSample) {
+    let phase = progress.load.map(|load| load.name()).unwrap_or("Idle");
+    let remaining = progress.duration.saturating_sub(progress.elapsed);
+    let fraction = progress.elapsed.as_secs_f64() / progress.duration.as_secs_f64().max(0.001);
+    ui.add(ProgressBar::new(fraction.min(1.0) as f32).text(format!(
+        "{phase} - {:.0} s remaining",
+        remaining.as_secs_f64().ceil()
+    )));
+}
@@ -8,6 +8,7 @@
 use std::ffi::OsStr;
 use std::hash::Hash;
 use std::{
+    collections::VecDeque,
     fs, mem,
     path::{Path, PathBuf},
     sync::Arc,
@@ -15,17 +16,18 @@ use std::{
 };
 
 use client::{Client, ClientSettings, ClientState};
-use crusader_lib::plot::{smooth, LatencySummary};
+use crusader_lib::plot::{smooth, BufferbloatGrade, LatencySummary, SmoothingMode};
 use crusader_lib::test::timed;
 use crusader_lib::{
-    file_format::{RawPing, RawResult, TestKind},
+    discovery,
+    file_format::{Compression, RawPing, RawResult, TestKind},
     latency,
-    plot::{self, float_max, to_rates},
+    plot::{self, float_max, stream_bytes, to_rates, MIN_PLOT_DURATION},
     protocol, remote, serve,
     test::{self, PlotConfig},
     with_time,
 };
-use eframe::egui::{AboveOrBelow, Label, Layout, TextWrapMode};
+use eframe::egui::{AboveOrBelow, Key, Label, Layout, TextWrapMode};
 use eframe::{
     egui::{
         self, Grid, Id, PopupCloseBehavior, RichText, ScrollArea, TextEdit, TextStyle, Ui, Vec2b,
@@ -34,7 +36,9 @@ use eframe::{
     epaint::Color32,
 };
 use egui_extras::{Size, Strip, StripBuilder};
-use egui_plot::{ColorConflictHandling, Legend, Line, Plot, PlotPoints};
+use egui_plot::{
+    ColorConflictHandling, HLine, Legend, Line, LineStyle, Plot, PlotPoints, PlotResponse,
+};
 
 #[cfg(not(target_os = "android"))]
 use rfd::FileDialog;
@@ -49,12 +53,22 @@ mod client;
 
 struct Server {
     done: Option<oneshot::Receiver<()>>,
-    msgs: Vec<String>,
+    msgs: VecDeque<String>,
     rx: mpsc::UnboundedReceiver<String>,
     stop: Option<oneshot::Sender<()>>,
     started: oneshot::Receiver<Result<(), String>>,
 }
 
+impl Server {
+    /// Adds a message to the log, dropping the oldest messages beyond `max`.
+    fn push(&mut self, msg: String, max: usize) {
+        self.msgs.push_back(msg);
+        while self.msgs.len() > max {
+            self.msgs.pop_front();
+        }
+    }
+}
+
 enum ServerState {
     Stopped(Option<String>),
     Starting,
@@ -81,7 +95,14 @@ enum Tab {
 pub struct LatencyMonitorSettings {
     pub server: String,
     pub history: f64,
+    pub visible_window: f64,
     pub latency_sample_interval: u64,
+    pub reconnect: bool,
+    pub log: String,
+    pub alert: bool,
+    pub alert_latency: u64,
+    pub alert_loss: f64,
+    pub alert_notify: bool,
 }
 
 impl Default for LatencyMonitorSettings {
@@ -89,16 +110,179 @@ impl Default for LatencyMonitorSettings {
         Self {
             server: "".to_owned(),
             history: 60.0,
+            visible_window: 60.0,
             latency_sample_interval: 5,
+            reconnect: true,
+            log: "".to_owned(),
+            alert: false,
+            alert_latency: 100,
+            alert_loss: 5.0,
+            alert_notify: false,
+        }
+    }
+}
+
+/// The default width of plot lines in `egui_plot`.
+const LINE_WIDTH: f32 = 1.5;
+
+/// How far back packet loss is considered for monitor alerts, in seconds.
+const ALERT_LOSS_WINDOW: f64 = 5.0;
+
+/// Asks the window manager to draw attention to the window, such as by flashing its taskbar entry.
+fn request_attention(ctx: &egui::Context) {
+    ctx.send_viewport_cmd(egui::ViewportCommand::RequestUserAttention(
+        egui::viewport::UserAttentionType::Critical,
+    ));
+}
+
+fn grade_color(grade: BufferbloatGrade) -> Color32 {
+    match grade {
+        BufferbloatGrade::APlus | BufferbloatGrade::A => Color32::from_rgb(60, 140, 60),
+        BufferbloatGrade::B => Color32::from_rgb(130, 150, 40),
+        BufferbloatGrade::C => Color32::from_rgb(210, 140, 30),
+        BufferbloatGrade::D | BufferbloatGrade::F => Color32::from_rgb(190, 60, 50),
+    }
+}
+
+/// Checks for the reset zoom shortcut, ignoring it while a text field has focus.
+fn reset_zoom_key(ui: &Ui) -> bool {
+    ui.memory(|memory| memory.focused().is_none()) && ui.input(|i| i.key_pressed(egui::Key::R))
+}
+
+#[derive(Clone, Copy)]
+pub struct PlotColors {
+    pub download: Color32,
+    pub upload: Color32,
+    pub bidirectional: Color32,
+    pub roundtrip: Color32,
+    pub up: Color32,
+    pub down: Color32,
+    pub loss: Color32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlotTheme {
+    #[default]
+    Default,
+    Colorblind,
+}
+
+impl PlotTheme {
+    const ALL: [PlotTheme; 2] = [PlotTheme::Default, PlotTheme::Colorblind];
+
+    fn name(self) -> &'static str {
+        match self {
+            PlotTheme::Default => "Default",
+            PlotTheme::Colorblind => "Colorblind safe",
+        }
+    }
+
+    pub fn colors(self) -> PlotColors {
+        match self {
+            PlotTheme::Default => PlotColors {
+                download: Color32::from_rgb(95, 145, 62),
+                upload: Color32::from_rgb(37, 83, 169),
+                bidirectional: Color32::from_rgb(149, 96, 153),
+                roundtrip: Color32::from_rgb(50, 50, 50),
+                up: Color32::from_rgb(37, 83, 169),
+                down: Color32::from_rgb(95, 145, 62),
+                loss: Color32::from_rgb(193, 85, 85),
+            },
+            // Based on the Okabe-Ito palette
+            PlotTheme::Colorblind => PlotColors {
+                download: Color32::from_rgb(230, 159, 0),
+                upload: Color32::from_rgb(0, 114, 178),
+                bidirectional: Color32::from_rgb(204, 121, 167),
+                roundtrip: Color32::from_rgb(50, 50, 50),
+                up: Color32::from_rgb(0, 114, 178),
+                down: Color32::from_rgb(230, 159, 0),
+                loss: Color32::from_rgb(213, 94, 0),
+            },
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Default)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThroughputUnit {
+    #[default]
+    Mbps,
+    Gbps,
+    MiBps,
+}
+
+impl ThroughputUnit {
+    const ALL: [ThroughputUnit; 3] = [
+        ThroughputUnit::Mbps,
+        ThroughputUnit::Gbps,
+        ThroughputUnit::MiBps,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ThroughputUnit::Mbps => "Mbps",
+            ThroughputUnit::Gbps => "Gbps",
+            ThroughputUnit::MiBps => "MiB/s",
+        }
+    }
+
+    /// The factor to convert a throughput in Mbps to this unit.
+    fn scale(self) -> f64 {
+        match self {
+            ThroughputUnit::Mbps => 1.0,
+            ThroughputUnit::Gbps => 1.0 / 1000.0,
+            ThroughputUnit::MiBps => 1000.0 * 1000.0 / 8.0 / (1024.0 * 1024.0),
+        }
+    }
+
+    fn format(self, mbps: f64) -> String {
+        format!("{:.02} {}", mbps * self.scale(), self.name())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 #[serde(default)]
 pub struct Settings {
     pub client: ClientSettings,
     pub latency_monitor: LatencyMonitorSettings,
+    pub results_dir: String,
+    pub plot_theme: PlotTheme,
+    pub throughput_unit: ThroughputUnit,
+    /// The directory last used to open or save a file.
+    pub last_dir: Option<String>,
+    /// Draws the idle latency as a reference line on the latency plot.
+    pub idle_latency_line: bool,
+    /// Draws the throughput of each stream beneath the aggregate throughput.
+    pub individual_streams: bool,
+    /// Shows the download share of the bidirectional throughput over time.
+    pub fairness_plot: bool,
+    /// Scales the width of plot lines.
+    pub line_scale: f32,
+    /// The TCP port of the remote web server.
+    pub remote_port: u16,
+    /// The token required by the remote web server to run tests. Not required if empty.
+    pub remote_token: String,
+    /// The number of messages kept in the server logs.
+    pub log_lines: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            client: Default::default(),
+            latency_monitor: Default::default(),
+            results_dir: "crusader-results".to_owned(),
+            plot_theme: PlotTheme::default(),
+            throughput_unit: ThroughputUnit::default(),
+            last_dir: None,
+            idle_latency_line: true,
+            individual_streams: false,
+            fairness_plot: false,
+            line_scale: 1.0,
+            remote_port: remote::REMOTE_PORT,
+            remote_token: String::new(),
+            log_lines: 10_000,
+        }
+    }
 }
 
 impl Settings {
@@ -108,6 +292,22 @@ impl Settings {
             .and_then(|data| toml::from_str(&data).ok())
             .unwrap_or_default()
     }
+
+    /// A file dialog starting in the last used directory.
+    #[cfg(not(target_os = "android"))]
+    fn file_dialog(&self) -> FileDialog {
+        match self.last_dir.as_ref() {
+            Some(dir) => FileDialog::new().set_directory(dir),
+            None => FileDialog::new(),
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn remember_dir(&mut self, file: &Path) {
+        if let Some(dir) = file.parent() {
+            self.last_dir = Some(dir.to_string_lossy().into_owned());
+        }
+    }
 }
 
 pub struct Tester {
@@ -121,11 +321,23 @@ pub struct Tester {
     remote_server: Option<Server>,
     client_state: ClientState,
     client: Option<Client>,
+    client_error: Option<String>,
+    server_search: Option<oneshot::Receiver<Result<Vec<discovery::Server>, String>>>,
+    found_servers: Option<Vec<discovery::Server>>,
     result_plot_reset: bool,
+    /// The time range shown in the result plots.
+    result_view: Option<(f64, f64)>,
+    /// The time at the pointer when hovering over the result plots.
+    result_cursor: Option<f64>,
     result: Option<TestResult>,
+    result_comparisons: Vec<Comparison>,
+    result_ewma: bool,
     raw_result_saved: Option<PathBuf>,
-    open_result: Vec<PathBuf>,
+    open_result: Vec<(PathBuf, Option<String>)>,
+    /// The results being listed for `open_result`, as their labels are loaded in the background.
+    open_result_rx: Option<mpsc::UnboundedReceiver<(PathBuf, Option<String>)>>,
     result_name: String,
+    result_label: String,
     msgs: Vec<String>,
     msg_scrolled: usize,
     pub file_loader: Option<Box<dyn Fn(&mut Tester)>>,
@@ -138,6 +350,18 @@ pub struct Tester {
     latency_stop: Duration,
     latency_error: Option<String>,
     latency_plot_reset: bool,
+    latency_alert: bool,
+}
+
+struct Comparison {
+    name: String,
+    result: TestResult,
+    shown: bool,
+}
+
+/// The letter used to tell a comparison apart from the primary result (A) in legends.
+fn comparison_tag(index: usize) -> char {
+    (b'B' + (index % 25) as u8) as char
 }
 
 pub struct LatencyResult {
@@ -192,8 +416,10 @@ impl LatencyResult {
             .filter(|p| p.sent >= result.start)
             .filter_map(|ping| {
                 if ping.latency.and_then(|latency| latency.total).is_none() {
-                    let down_loss =
-                        (result.raw_result.version >= 2).then_some(ping.latency.is_some());
+                    let down_loss = result
+                        .raw_result
+                        .has_loss_direction()
+                        .then_some(ping.latency.is_some());
                     Some((ping.sent.as_secs_f64() - start, down_loss))
                 } else {
                     None
@@ -223,16 +449,35 @@ pub struct TestResult {
     both_upload_avg: Option<Vec<(f64, f64)>>,
     both: Option<Vec<(f64, f64)>>,
     both_avg: Option<Vec<(f64, f64)>>,
+    /// The throughput of each stream, along with whether it's a download stream.
+    streams: Vec<(bool, Vec<(f64, f64)>)>,
+    fairness: Option<f64>,
+    /// The download share of the bidirectional throughput over time, in percent.
+    fairness_over_time: Option<Vec<(f64, f64)>>,
+    /// The estimated bytes buffered in the path during the download and upload tests.
+    excess_buffering: Vec<(TestKind, f64)>,
+    grades: Vec<(TestKind, BufferbloatGrade)>,
     local_latency: LatencyResult,
-    peer_latency: Option<LatencyResult>,
+    peer_latencies: Vec<LatencyResult>,
     throughput_max: f64,
 }
 
 impl TestResult {
-    fn new(result: plot::TestResult) -> Self {
-        let smooth_interval =
-            Duration::from_secs_f64(1.0).min(result.raw_result.config.grace_duration);
+    fn new(result: plot::TestResult, ewma: bool) -> Self {
         let interval = result.raw_result.config.bandwidth_interval;
+        // Smooth over at least one sample, as the grace duration may be zero
+        let smooth_interval = Duration::from_secs_f64(1.0)
+            .min(result.raw_result.config.grace_duration)
+            .max(interval);
+
+        let smoothing = if ewma {
+            // Use a time constant matching the window
+            SmoothingMode::Ewma(
+                1.0 - (-interval.as_secs_f64() / smooth_interval.as_secs_f64()).exp(),
+            )
+        } else {
+            SmoothingMode::Window(smooth_interval)
+        };
 
         let start = result.start.as_secs_f64();
 
@@ -243,7 +488,7 @@ impl TestResult {
         let download_avg = result
             .download_bytes
             .as_ref()
-            .map(|bytes| smooth_bytes(bytes, start, interval, smooth_interval));
+            .map(|bytes| smooth_bytes(bytes, start, interval, smoothing));
 
         let upload = result
             .upload_bytes
@@ -252,7 +497,7 @@ impl TestResult {
         let upload_avg = result
             .upload_bytes
             .as_ref()
-            .map(|bytes| smooth_bytes(bytes, start, interval, smooth_interval));
+            .map(|bytes| smooth_bytes(bytes, start, interval, smoothing));
 
         let both_upload = result
             .both_upload_bytes
@@ -261,7 +506,7 @@ impl TestResult {
         let both_upload_avg = result
             .both_upload_bytes
             .as_ref()
-            .map(|bytes| smooth_bytes(bytes, start, interval, smooth_interval));
+            .map(|bytes| smooth_bytes(bytes, start, interval, smoothing));
 
         let both_download = result
             .both_download_bytes
@@ -270,7 +515,7 @@ impl TestResult {
         let both_download_avg = result
             .both_download_bytes
             .as_ref()
-            .map(|bytes| smooth_bytes(bytes, start, interval, smooth_interval));
+            .map(|bytes| smooth_bytes(bytes, start, interval, smoothing));
 
         let both = result
             .both_bytes
@@ -279,7 +524,18 @@ impl TestResult {
         let both_avg = result
             .both_bytes
             .as_ref()
-            .map(|bytes| smooth_bytes(bytes, start, interval, smooth_interval));
+            .map(|bytes| smooth_bytes(bytes, start, interval, smoothing));
+
+        let streams = result
+            .raw_result
+            .stream_groups
+            .iter()
+            .flat_map(|group| {
+                stream_bytes(group, interval)
+                    .into_iter()
+                    .map(|bytes| (group.download, handle_bytes(&bytes, start)))
+            })
+            .collect();
 
         let download_max = download
             .as_ref()
@@ -308,6 +564,11 @@ impl TestResult {
             .flatten(),
         );
 
+        let fairness_over_time = both_download
+            .as_deref()
+            .zip(both_upload.as_deref())
+            .map(|(download, upload)| fairness_over_time(download, upload));
+
         TestResult {
             download,
             download_avg,
@@ -319,18 +580,56 @@ impl TestResult {
             both_upload_avg,
             both,
             both_avg,
+            streams,
+            fairness: result.bidirectional_fairness(),
+            fairness_over_time,
+            excess_buffering: [TestKind::Download, TestKind::Upload]
+                .into_iter()
+                .filter_map(|kind| excess_buffering(&result, kind).map(|bytes| (kind, bytes)))
+                .collect(),
+            grades: [
+                TestKind::Download,
+                TestKind::Upload,
+                TestKind::Bidirectional,
+            ]
+            .into_iter()
+            .filter_map(|kind| result.bufferbloat_grade(kind).map(|grade| (kind, grade)))
+            .collect(),
             throughput_max,
             local_latency: LatencyResult::new(&result, &result.pings),
-            peer_latency: result
+            peer_latencies: result
                 .raw_result
                 .peer_pings
-                .as_ref()
-                .map(|pings| LatencyResult::new(&result, pings)),
+                .iter()
+                .map(|(_, pings)| LatencyResult::new(&result, pings))
+                .collect(),
             result,
         }
     }
 }
 
+/// Estimates the bytes buffered in the path during a load as the throughput
+/// multiplied by the latency increase over the idle latency.
+fn excess_buffering(result: &plot::TestResult, kind: TestKind) -> Option<f64> {
+    let throughput = result.throughputs.get(&(kind, kind))?;
+    let latency = result.latencies.latencies.get(&Some(kind))?;
+    let added = latency
+        .total
+        .saturating_sub(result.raw_result.server_latency);
+    Some(throughput * 1000.0 * 1000.0 / 8.0 * added.as_secs_f64())
+}
+
+/// The share of the throughput which was download at each download sample, in percent.
+fn fairness_over_time(download: &[(f64, f64)], upload: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    download
+        .iter()
+        .filter_map(|&(time, down)| {
+            let total = down + sample_at(upload, time)?;
+            (total > 0.0).then(|| (time, down / total * 100.0))
+        })
+        .collect()
+}
+
 pub fn handle_bytes(data: &[(u64, f64)], start: f64) -> Vec<(f64, f64)> {
     to_rates(data)
         .into_iter()
@@ -342,14 +641,97 @@ pub fn smooth_bytes(
     data: &[(u64, f64)],
     start: f64,
     interval: Duration,
-    smoothing_interval: Duration,
+    smoothing: SmoothingMode,
 ) -> Vec<(f64, f64)> {
-    smooth(data, interval, smoothing_interval)
+    smooth(data, interval, smoothing)
         .into_iter()
         .map(|(time, speed)| (Duration::from_micros(time).as_secs_f64() - start, speed))
         .collect()
 }
 
+/// Returns the time at the pointer if it hovers over the plot.
+fn hovered_time<R>(response: &PlotResponse<R>) -> Option<f64> {
+    response
+        .response
+        .hover_pos()
+        .map(|pos| response.transform.value_from_position(pos).x)
+}
+
+/// Returns the value of the sample nearest to `time`, if `time` is within the samples.
+fn sample_at(data: &[(f64, f64)], time: f64) -> Option<f64> {
+    if time < data.first()?.0 || time > data.last()?.0 {
+        return None;
+    }
+    let i = data.partition_point(|v| v.0 < time);
+    let after = data.get(i);
+    let before = i.checked_sub(1).and_then(|i| data.get(i));
+    match (before, after) {
+        (Some(before), Some(after)) if time - before.0 < after.0 - time => Some(before.1),
+        _ => after.or(before).map(|v| v.1),
+    }
+}
+
+/// Shows the throughput and latency of `result` at the hovered time.
+fn cursor_readout(
+    ui: &mut Ui,
+    result: &TestResult,
+    time: Option<f64>,
+    colors: PlotColors,
+    unit: ThroughputUnit,
+) {
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+
+        let Some(time) = time else {
+            ui.label(RichText::new("Hover over a plot to show the values at that time").weak());
+            return;
+        };
+
+        ui.label(format!("Time: {:.2} s", time));
+
+        let throughput = |data: [&Option<Vec<(f64, f64)>>; 2]| {
+            data.into_iter()
+                .filter_map(|data| data.as_deref())
+                .find_map(|data| sample_at(data, time))
+        };
+
+        if let Some(down) = throughput([&result.download, &result.both_download]) {
+            ui.add_space(15.0);
+            ui.label(RichText::new("Download: ").color(colors.download));
+            ui.label(unit.format(down));
+        }
+
+        if let Some(up) = throughput([&result.upload, &result.both_upload]) {
+            ui.add_space(15.0);
+            ui.label(RichText::new("Upload: ").color(colors.upload));
+            ui.label(unit.format(up));
+        }
+
+        let mut latency = |name: &str, latency: &LatencyResult| {
+            if let Some(total) = sample_at(&latency.total, time) {
+                ui.add_space(15.0);
+                ui.label(format!("{}: {:.1} ms", name, total));
+                let down = sample_at(&latency.down, time);
+                let up = sample_at(&latency.up, time);
+                if let (Some(down), Some(up)) = (down, up) {
+                    ui.label(format!("  ({:.1} ", down));
+                    ui.label(RichText::new("down").color(colors.down));
+                    ui.label(format!(", {:.1} ", up));
+                    ui.label(RichText::new("up").color(colors.up));
+                    ui.label(")");
+                }
+            }
+        };
+
+        latency("Latency", &result.local_latency);
+
+        for (index, peer_latency) in result.peer_latencies.iter().enumerate() {
+            let label = result.result.raw_result.peer_label(index);
+            latency(&format!("{label} latency"), peer_latency);
+        }
+    });
+}
+
 fn hover_popup(
     ui: &mut Ui,
     id_source: impl Hash,
@@ -361,6 +743,7 @@ fn hover_popup(
 
         ui.spacing_mut().interact_size.y = 18.0;
         let active = id.with("active");
+        let dismissed = id.with("dismissed");
 
         let active_value = ui.memory_mut(|mem| {
             let active = mem.data.get_temp_mut_or_default(active);
@@ -389,7 +772,14 @@ fn hover_popup(
                 false
             };
 
-        if stats.hovered() && contains_pointer {
+        // Keep a popup closed with Escape from reopening until the pointer leaves the button
+        let dismissed_value = ui.memory_mut(|mem| {
+            let dismissed: &mut bool = mem.data.get_temp_mut_or_default(dismissed);
+            *dismissed &= contains_pointer;
+            *dismissed
+        });
+
+        if stats.hovered() && contains_pointer && !dismissed_value {
             ui.memory_mut(|mem| {
                 if !mem.any_popup_open() {
                     mem.open_popup(popup_id);
@@ -415,6 +805,17 @@ fn hover_popup(
             });
         }
 
+        if ui.memory(|mem| mem.is_popup_open(popup_id))
+            && ui.input(|input| input.key_pressed(Key::Escape))
+        {
+            ui.memory_mut(|mem| {
+                mem.close_popup();
+                mem.data.insert_temp(active, false);
+                mem.data.insert_temp(dismissed, true);
+            });
+            stats.request_focus();
+        }
+
         egui::popup::popup_above_or_below_widget(
             ui,
             popup_id,
@@ -486,11 +887,20 @@ impl Tester {
             settings_path,
             client_state: ClientState::Stopped,
             client: None,
+            client_error: None,
+            server_search: None,
+            found_servers: None,
             result: None,
+            result_comparisons: Vec::new(),
+            result_ewma: false,
             result_plot_reset: false,
+            result_view: None,
+            result_cursor: None,
             raw_result_saved: None,
             result_name: "".to_string(),
+            result_label: "".to_string(),
             open_result: Vec::new(),
+            open_result_rx: None,
             msgs: Vec::new(),
             msg_scrolled: 0,
             server_state: ServerState::Stopped(None),
@@ -506,12 +916,18 @@ impl Tester {
             latency_stop: Duration::from_secs(0),
             latency_error: None,
             latency_plot_reset: false,
+            latency_alert: false,
         }
     }
 
     pub fn set_result(&mut self, result: plot::TestResult) {
-        self.result = Some(TestResult::new(result));
+        self.result = Some(TestResult::new(result, self.result_ewma));
         self.result_name = "test".to_owned();
+        self.result_label = self
+            .result
+            .as_ref()
+            .and_then(|result| result.result.raw_result.label.clone())
+            .unwrap_or_default();
         self.result_plot_reset = true;
         self.raw_result_saved = None;
     }
@@ -536,14 +952,25 @@ impl Tester {
         }
     }
 
+    /// Returns the time range shown in the result plots if they are zoomed in.
+    #[cfg(not(target_os = "android"))]
+    fn zoomed_range(&self, result: &plot::TestResult) -> Option<(f64, f64)> {
+        let (from, to) = self.result_view?;
+        let duration = result.duration.as_secs_f64();
+        let (from, to) = (from.max(0.0), to.min(duration));
+        (from < to && (from > 0.0 || to < duration)).then_some((from, to))
+    }
+
     fn load_result(&mut self) {
         #[cfg(not(target_os = "android"))]
         {
-            FileDialog::new()
-                .add_filter("Crusader Raw Result", &["crr"])
+            self.settings
+                .file_dialog()
+                .add_filter("Crusader Raw Result", &["crr", "json"])
                 .add_filter("All files", &["*"])
                 .pick_file()
                 .map(|file| {
+                    self.settings.remember_dir(&file);
                     RawResult::load(&file).map(|raw| {
                         self.load_file(file, raw);
                     })
@@ -554,39 +981,186 @@ impl Tester {
         self.file_loader = file_loader;
     }
 
+    fn start_server_search(&mut self, ctx: &egui::Context) {
+        let (tx, rx) = oneshot::channel();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let result =
+                discovery::find_servers(Duration::from_secs(2)).map_err(|error| error.to_string());
+            tx.send(result).ok();
+            ctx.request_repaint();
+        });
+        self.server_search = Some(rx);
+    }
+
+    /// Shows a button to search for local servers and a menu of the ones found.
+    /// Returns the address of the server picked by the user.
+    fn server_finder(&mut self, ctx: &egui::Context, ui: &mut Ui) -> Option<String> {
+        if let Some(search) = self.server_search.as_mut() {
+            match search.try_recv() {
+                Ok(result) => {
+                    self.server_search = None;
+                    self.found_servers = Some(result.unwrap_or_default());
+                }
+                Err(oneshot::error::TryRecvError::Empty) => {
+                    ui.add_enabled_ui(false, |ui| {
+                        let _ = ui.button("Searching..");
+                    });
+                    return None;
+                }
+                Err(oneshot::error::TryRecvError::Closed) => self.server_search = None,
+            }
+        }
+
+        let mut selected = None;
+        let mut search = false;
+
+        match self.found_servers.as_ref() {
+            Some(servers) if !servers.is_empty() => {
+                ui.menu_button("Found servers", |ui| {
+                    for server in servers {
+                        if ui
+                            .button(&server.at)
+                            .on_hover_text(format!("Version {}", server.software_version))
+                            .clicked()
+                        {
+                            selected = Some(server.address());
+                            ui.close_menu();
+                        }
+                    }
+                    ui.separator();
+                    if ui.button("Search again").clicked() {
+                        search = true;
+                        ui.close_menu();
+                    }
+                });
+            }
+            found => {
+                search = ui
+                    .button("Find servers")
+                    .on_hover_text("Search for servers on the local network")
+                    .clicked();
+                if found.is_some() {
+                    ui.label("None found");
+                }
+            }
+        }
+
+        if search {
+            self.start_server_search(ctx);
+        }
+
+        selected
+    }
+
+    fn load_comparisons(&mut self) {
+        #[cfg(not(target_os = "android"))]
+        {
+            let files = self
+                .settings
+                .file_dialog()
+                .add_filter("Crusader Raw Result", &["crr", "json"])
+                .add_filter("All files", &["*"])
+                .pick_files()
+                .unwrap_or_default();
+            for file in files {
+                self.settings.remember_dir(&file);
+                if let Some(raw) = RawResult::load(&file) {
+                    let name = file
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .unwrap_or_default()
+                        .to_owned();
+                    self.result_comparisons.push(Comparison {
+                        name,
+                        result: TestResult::new(raw.to_test_result(), self.result_ewma),
+                        shown: true,
+                    });
+                    self.result_plot_reset = true;
+                }
+            }
+        }
+    }
+
+    fn shown_comparisons(&self) -> Vec<(char, &TestResult)> {
+        self.result_comparisons
+            .iter()
+            .enumerate()
+            .filter(|(_, comparison)| comparison.shown)
+            .map(|(i, comparison)| (comparison_tag(i), &comparison.result))
+            .collect()
+    }
+
     fn latency_and_loss(
         &mut self,
         strip: &mut Strip<'_, '_>,
         link: Id,
         reset: bool,
-        peer: bool,
+        peer: Option<usize>,
         y_axis_size: f32,
     ) {
+        let colors = self.settings.plot_theme.colors();
+        let line_scale = self.settings.line_scale;
+        let idle_latency_line = self.settings.idle_latency_line && peer.is_none();
+
         let result = self.result.as_ref().unwrap();
 
-        let data = if peer {
-            result.peer_latency.as_ref().unwrap()
-        } else {
-            &result.local_latency
+        let data = match peer {
+            Some(peer) => &result.peer_latencies[peer],
+            None => &result.local_latency,
+        };
+        let label = |name: &str| match peer {
+            Some(peer) => format!(
+                "{} {}",
+                result.result.raw_result.peer_label(peer),
+                name.to_lowercase()
+            ),
+            None => name.to_owned(),
         };
 
-        let latencies = if peer {
-            &result.result.peer_latencies
-        } else {
-            &result.result.latencies
+        let comparisons = self.shown_comparisons();
+        let overlays: Vec<_> = comparisons
+            .iter()
+            .filter_map(|&(tag, comparison)| {
+                match peer {
+                    Some(peer) => comparison.peer_latencies.get(peer),
+                    None => Some(&comparison.local_latency),
+                }
+                .map(|data| (tag, data))
+            })
+            .collect();
+        let name = |name: &str| {
+            if !comparisons.is_empty() {
+                format!("{name} (A)")
+            } else {
+                name.to_owned()
+            }
         };
 
-        let duration = result.result.duration.as_secs_f64() * 1.1;
+        let latencies = match peer {
+            Some(peer) => &result.result.peer_latencies[peer],
+            None => &result.result.latencies,
+        };
+
+        let duration = (comparisons
+            .iter()
+            .map(|(_, comparison)| comparison.result.duration.as_secs_f64())
+            .fold(result.result.duration.as_secs_f64(), f64::max)
+            * 1.1)
+            .max(MIN_PLOT_DURATION);
+
+        let mut view = None;
+        let mut hovered = None;
 
         strip.cell(|ui| {
             ui.horizontal(|ui| {
-                let label = if peer { "Peer latency" } else { "Latency" };
-                ui.label(label);
+                let label = label("Latency");
+                ui.label(&label);
 
                 hover_popup(
                     ui,
                     (label, "Popup"),
-                    if !peer && result.result.raw_result.idle() {
+                    if peer.is_none() && result.result.raw_result.idle() {
                         AboveOrBelow::Below
                     } else {
                         AboveOrBelow::Above
@@ -595,7 +1169,11 @@ impl Tester {
                         ui.spacing_mut().item_spacing.x = 0.0;
                         ui.spacing_mut().interact_size.y = 10.0;
 
-                        let stats = |ui: &mut Ui, name, color, latency: &LatencySummary| {
+                        let stats = |ui: &mut Ui,
+                                     name,
+                                     color,
+                                     latency: &LatencySummary,
+                                     kind: Option<TestKind>| {
                             ui.vertical(|ui| {
                                 ui.add_space(5.0);
                                 ui.horizontal(|ui| {
@@ -610,28 +1188,72 @@ impl Tester {
                                         "\t\t{:.01} ms ",
                                         latency.down.as_secs_f64() * 1000.0
                                     ));
-                                    ui.label(
-                                        RichText::new("down").color(Color32::from_rgb(95, 145, 62)),
-                                    );
+                                    ui.label(RichText::new("down").color(colors.down));
                                 });
                                 ui.horizontal(|ui| {
                                     ui.label(format!(
                                         "\t\t{:.01} ms ",
                                         latency.up.as_secs_f64() * 1000.0
                                     ));
-                                    ui.label(
-                                        RichText::new("up").color(Color32::from_rgb(37, 83, 169)),
-                                    );
+                                    ui.label(RichText::new("up").color(colors.up));
                                 });
+                                if kind.is_some() {
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!(
+                                            "\t\t{:+.01} ms asymmetry (up minus down)",
+                                            latency.asymmetry_ms()
+                                        ));
+                                    });
+                                }
+                                if let Some(percentiles) = latencies.percentiles.get(&kind) {
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!(
+                                            "\t\t{:.01} / {:.01} / {:.01} ms p50 / p95 / p99",
+                                            percentiles.p50.as_secs_f64() * 1000.0,
+                                            percentiles.p95.as_secs_f64() * 1000.0,
+                                            percentiles.p99.as_secs_f64() * 1000.0
+                                        ));
+                                    });
+                                }
+                                if let Some(jitter) = latencies.jitter.get(&kind) {
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!(
+                                            "\t\t{:.01} ms jitter",
+                                            jitter.as_secs_f64() * 1000.0
+                                        ));
+                                    });
+                                }
+                                if let Some((_, bytes)) = result
+                                    .excess_buffering
+                                    .iter()
+                                    .find(|(buffered, _)| peer.is_none() && Some(*buffered) == kind)
+                                {
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!(
+                                            "\t\t~{} excess buffering",
+                                            plot::format_bytes(*bytes as u64)
+                                        ))
+                                        .on_hover_text(
+                                            "Estimated data queued in the path, \
+                                            from the throughput and the latency increase over idle",
+                                        );
+                                    });
+                                }
                             });
                         };
 
                         if let Some(latency) = latencies.latencies.get(&Some(TestKind::Download)) {
-                            stats(ui, "Download", Color32::from_rgb(95, 145, 62), latency);
+                            stats(
+                                ui,
+                                "Download",
+                                colors.download,
+                                latency,
+                                Some(TestKind::Download),
+                            );
                         }
 
                         if let Some(latency) = latencies.latencies.get(&Some(TestKind::Upload)) {
-                            stats(ui, "Upload", Color32::from_rgb(37, 83, 169), latency);
+                            stats(ui, "Upload", colors.upload, latency, Some(TestKind::Upload));
                         }
 
                         if let Some(latency) =
@@ -640,13 +1262,14 @@ impl Tester {
                             stats(
                                 ui,
                                 "Bidirectional",
-                                Color32::from_rgb(149, 96, 153),
+                                colors.bidirectional,
                                 latency,
+                                Some(TestKind::Bidirectional),
                             );
                         }
 
                         if let Some(latency) = latencies.latencies.get(&None) {
-                            stats(ui, "Latency", Color32::from_rgb(0, 0, 0), latency);
+                            stats(ui, "Latency", Color32::from_rgb(0, 0, 0), latency, None);
                         }
 
                         ui.vertical(|ui| {
@@ -682,6 +1305,11 @@ impl Tester {
             });
 
             // Latency
+            let overlay_max = overlays
+                .iter()
+                .map(|(_, data)| data.max)
+                .fold(0.0, f64::max);
+
             let mut plot = Plot::new((peer, "ping"))
                 .legend(Legend::default().insertion_order(true))
                 .y_axis_min_width(y_axis_size)
@@ -691,6 +1319,7 @@ impl Tester {
                 .include_x(duration)
                 .include_y(0.0)
                 .include_y(data.max * 1.1)
+                .include_y(overlay_max * 1.1)
                 .label_formatter(|_, value| {
                     format!("Latency = {:.2} ms\nTime = {:.2} s", value.y, value.x)
                 });
@@ -699,40 +1328,69 @@ impl Tester {
                 plot = plot.reset();
             }
 
-            plot.show(ui, |plot_ui| {
-                if result.result.raw_result.version >= 1 {
+            let response = plot.show(ui, |plot_ui| {
+                if idle_latency_line {
+                    plot_ui.hline(
+                        HLine::new(result.result.raw_result.server_latency.as_secs_f64() * 1000.0)
+                            .color(Color32::from_rgb(128, 128, 128))
+                            .style(LineStyle::dashed_dense())
+                            .width(LINE_WIDTH * line_scale)
+                            .name("Idle latency"),
+                    );
+                }
+
+                if result.result.raw_result.has_one_way_latency() {
                     let latency = data.up.iter().map(|v| [v.0, v.1]);
                     let latency = Line::new(PlotPoints::from_iter(latency))
-                        .color(Color32::from_rgb(37, 83, 169))
-                        .name("Up");
+                        .color(colors.up)
+                        .width(LINE_WIDTH * line_scale)
+                        .name(name("Up"));
 
                     plot_ui.line(latency);
 
                     let latency = data.down.iter().map(|v| [v.0, v.1]);
                     let latency = Line::new(PlotPoints::from_iter(latency))
-                        .color(Color32::from_rgb(95, 145, 62))
-                        .name("Down");
+                        .color(colors.down)
+                        .width(LINE_WIDTH * line_scale)
+                        .name(name("Down"));
 
                     plot_ui.line(latency);
                 }
 
                 let latency = data.total.iter().map(|v| [v.0, v.1]);
                 let latency = Line::new(PlotPoints::from_iter(latency))
-                    .color(Color32::from_rgb(50, 50, 50))
-                    .name("Round-trip");
+                    .color(colors.roundtrip)
+                    .width(LINE_WIDTH * line_scale)
+                    .name(name("Round-trip"));
 
                 plot_ui.line(latency);
+
+                // Draw the comparisons dashed and faded
+                for (tag, overlay) in &overlays {
+                    let latency = overlay.total.iter().map(|v| [v.0, v.1]);
+                    let latency = Line::new(PlotPoints::from_iter(latency))
+                        .color(colors.roundtrip.gamma_multiply(0.5))
+                        .style(LineStyle::dashed_loose())
+                        .width(LINE_WIDTH * line_scale)
+                        .name(format!("Round-trip ({tag})"));
+
+                    plot_ui.line(latency);
+                }
             });
+
+            let bounds = response.transform.bounds();
+            view = Some((bounds.min()[0], bounds.max()[0]));
+            hovered = hovered_time(&response);
         });
 
+        if peer.is_none() {
+            self.result_view = view;
+        }
+
         strip.cell(|ui| {
             ui.horizontal(|ui| {
-                let label = if peer {
-                    "Peer packet loss"
-                } else {
-                    "Packet loss"
-                };
-                ui.label(label);
+                let label = label("Packet loss");
+                ui.label(&label);
 
                 hover_popup(ui, (label, "Popup"), AboveOrBelow::Above, |ui| {
                     ui.spacing_mut().item_spacing.x = 0.0;
@@ -751,32 +1409,28 @@ impl Tester {
                                         down * 100.0,
                                         if down == 0.0 { 0 } else { 2 }
                                     ));
-                                    ui.label(
-                                        RichText::new("down").color(Color32::from_rgb(95, 145, 62)),
-                                    );
+                                    ui.label(RichText::new("down").color(colors.down));
                                     ui.label(format!(
                                         ", {:.1$}% ",
                                         up * 100.0,
                                         if up == 0.0 { 0 } else { 2 }
                                     ));
-                                    ui.label(
-                                        RichText::new("up").color(Color32::from_rgb(37, 83, 169)),
-                                    );
+                                    ui.label(RichText::new("up").color(colors.up));
                                 }
                             });
                         });
                     };
 
                     if let Some(loss) = latencies.loss.get(&Some(TestKind::Download)) {
-                        stats(ui, "Download", Color32::from_rgb(95, 145, 62), *loss);
+                        stats(ui, "Download", colors.download, *loss);
                     }
 
                     if let Some(loss) = latencies.loss.get(&Some(TestKind::Upload)) {
-                        stats(ui, "Upload", Color32::from_rgb(37, 83, 169), *loss);
+                        stats(ui, "Upload", colors.upload, *loss);
                     }
 
                     if let Some(loss) = latencies.loss.get(&Some(TestKind::Bidirectional)) {
-                        stats(ui, "Bidirectional", Color32::from_rgb(149, 96, 153), *loss);
+                        stats(ui, "Bidirectional", colors.bidirectional, *loss);
                     }
 
                     if let Some(loss) = latencies.loss.get(&None) {
@@ -787,7 +1441,7 @@ impl Tester {
 
             // Packet loss
             let mut plot = Plot::new((peer, "loss"))
-                .legend(Legend::default())
+                .legend(Legend::default().text_style(TextStyle::Small))
                 .show_axes([false, true])
                 .show_grid(Vec2b::new(true, false))
                 .y_axis_min_width(y_axis_size)
@@ -808,23 +1462,25 @@ impl Tester {
                 plot = plot.reset();
             }
 
-            plot.show(ui, |plot_ui| {
+            let response = plot.show(ui, |plot_ui| {
                 for &(loss, down_loss) in &data.loss {
-                    let (color, s, e) = down_loss
+                    let (color, s, e, name) = down_loss
                         .map(|down_loss| {
                             if down_loss {
-                                (Color32::from_rgb(95, 145, 62), 1.0, 0.0)
+                                (colors.down, 1.0, 0.0, "Download loss")
                             } else {
-                                (Color32::from_rgb(37, 83, 169), -1.0, 0.0)
+                                (colors.up, -1.0, 0.0, "Upload loss")
                             }
                         })
-                        .unwrap_or((Color32::from_rgb(193, 85, 85), -1.0, 1.0));
+                        .unwrap_or((colors.loss, -1.0, 1.0, "Loss (unknown direction)"));
 
                     plot_ui.line(
                         Line::new(PlotPoints::from_iter(
                             [[loss, s], [loss, e]].iter().copied(),
                         ))
-                        .color(color),
+                        .width(LINE_WIDTH * line_scale)
+                        .color(color)
+                        .name(name),
                     );
 
                     if down_loss.is_some() {
@@ -832,13 +1488,55 @@ impl Tester {
                             Line::new(PlotPoints::from_iter(
                                 [[loss, s], [loss, s - s / 5.0]].iter().copied(),
                             ))
-                            .width(3.0)
+                            .width(3.0 * line_scale)
                             .color(color),
                         );
                     }
                 }
             });
+
+            hovered = hovered.or(hovered_time(&response));
+        });
+
+        if hovered.is_some() {
+            self.result_cursor = hovered;
+        }
+    }
+
+    /// Lists the results in the results folder along with their labels. Finding the labels
+    /// requires decoding each result, so it's done on a separate thread.
+    fn start_result_listing(&mut self, ctx: &egui::Context) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let dir = PathBuf::from(&self.settings.results_dir);
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let Ok(dir) = fs::read_dir(dir) else {
+                return;
+            };
+            let paths = dir.filter_map(|file| {
+                file.ok().map(|file| file.path()).filter(|path| {
+                    path.extension() == Some(OsStr::new("crr"))
+                        || path.extension() == Some(OsStr::new("json"))
+                })
+            });
+            for path in paths {
+                let result = RawResult::load(&path);
+                // Skip JSON files which aren't results
+                if result.is_none() && path.extension() == Some(OsStr::new("json")) {
+                    continue;
+                }
+                // Stop once the listing is no longer wanted
+                if tx
+                    .send((path, result.and_then(|result| result.label)))
+                    .is_err()
+                {
+                    return;
+                }
+                ctx.request_repaint();
+            }
         });
+        self.open_result = Vec::new();
+        self.open_result_rx = Some(rx);
     }
 
     fn load_popup(&mut self, ui: &mut Ui) {
@@ -850,22 +1548,25 @@ impl Tester {
             let button = ui.button("Open from results");
 
             if button.clicked() {
-                ui.memory_mut(|mem| {
-                    mem.toggle_popup(popup_id);
-                    if mem.is_popup_open(popup_id) {
-                        self.open_result = fs::read_dir("crusader-results")
-                            .ok()
-                            .map(|dir| {
-                                dir.filter_map(|file| {
-                                    file.ok()
-                                        .map(|file| file.path())
-                                        .filter(|path| path.extension() == Some(OsStr::new("crr")))
-                                })
-                                .collect()
-                            })
-                            .unwrap_or_default();
+                ui.memory_mut(|mem| mem.toggle_popup(popup_id));
+                if ui.memory(|mem| mem.is_popup_open(popup_id)) {
+                    self.start_result_listing(ui.ctx());
+                } else {
+                    self.open_result_rx = None;
+                }
+            }
+
+            if let Some(rx) = self.open_result_rx.as_mut() {
+                loop {
+                    match rx.try_recv() {
+                        Ok(result) => self.open_result.push(result),
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => {
+                            self.open_result_rx = None;
+                            break;
+                        }
                     }
-                });
+                }
             }
 
             egui::popup::popup_below_widget(
@@ -877,19 +1578,37 @@ impl Tester {
                     ui.set_min_width(300.0);
                     ui.horizontal_wrapped(|ui| {
                         ui.label("Results available in the");
-                        if ui.link("crusader-results").clicked() {
-                            open::that("crusader-results").ok();
+                        if ui.link(&self.settings.results_dir).clicked() {
+                            open::that(&self.settings.results_dir).ok();
                         }
                         ui.label("folder:");
                     });
 
+                    #[cfg(not(target_os = "android"))]
+                    {
+                        if ui.button("Change folder").clicked() {
+                            if let Some(dir) = FileDialog::new()
+                                .set_directory(&self.settings.results_dir)
+                                .pick_folder()
+                            {
+                                self.settings.results_dir = dir.to_string_lossy().into_owned();
+                                self.save_settings();
+                                ui.memory_mut(|mem| mem.close_popup());
+                            }
+                        }
+                    }
+
                     ScrollArea::vertical().show(ui, |ui| {
                         ui.with_layout(Layout::top_down_justified(Align::LEFT), |ui| {
-                            for file in self.open_result.clone() {
+                            for (file, label) in self.open_result.clone() {
                                 if let Some(prefix) =
                                     file.file_name().and_then(|stem| stem.to_str())
                                 {
-                                    if ui.toggle_value(&mut false, prefix).clicked() {
+                                    let text = match label {
+                                        Some(label) => format!("{prefix} ({label})"),
+                                        None => prefix.to_owned(),
+                                    };
+                                    if ui.toggle_value(&mut false, text).clicked() {
                                         ui.memory_mut(|mem| mem.close_popup());
                                         RawResult::load(&file).map(|raw| {
                                             self.load_file(file, raw);
@@ -897,6 +1616,9 @@ impl Tester {
                                     }
                                 }
                             }
+                            if self.open_result_rx.is_some() {
+                                ui.spinner();
+                            }
                         });
                     });
                 },
@@ -905,6 +1627,9 @@ impl Tester {
     }
 
     fn result(&mut self, _ctx: &egui::Context, ui: &mut Ui) {
+        let colors = self.settings.plot_theme.colors();
+        let line_scale = self.settings.line_scale;
+
         if self.result.is_none() {
             ui.horizontal_wrapped(|ui| {
                 if ui.button("Open").clicked() {
@@ -930,12 +1655,15 @@ impl Tester {
                     None => {
                         #[cfg(not(target_os = "android"))]
                         {
-                            FileDialog::new()
+                            self.settings
+                                .file_dialog()
                                 .add_filter("Crusader Raw Result", &["crr"])
+                                .add_filter("Crusader Raw Result (JSON)", &["json"])
                                 .add_filter("All files", &["*"])
                                 .set_file_name(&format!("{}.crr", timed("test")))
                                 .save_file()
                                 .map(|file| {
+                                    self.settings.remember_dir(&file);
                                     if self
                                         .result
                                         .as_ref()
@@ -975,11 +1703,19 @@ impl Tester {
                         ui.set_min_width(250.0);
                         ui.horizontal_wrapped(|ui| {
                             ui.label("This saves both the data and plot in the");
-                            if ui.link("crusader-results").clicked() {
-                                open::that("crusader-results").ok();
+                            if ui.link(&self.settings.results_dir).clicked() {
+                                open::that(&self.settings.results_dir).ok();
                             }
                             ui.label("folder.");
                         });
+                        ui.horizontal(|ui| {
+                            ui.label("Label: ");
+                            ui.add(
+                                TextEdit::singleline(&mut self.result_label)
+                                    .hint_text("Optional")
+                                    .desired_width(175.0),
+                            );
+                        });
                         ui.horizontal(|ui| {
                             ui.label("Name: ");
                             let mut click = ui
@@ -991,18 +1727,22 @@ impl Tester {
                                 && ui.input(|i| i.key_pressed(egui::Key::Enter));
                             click |= ui.button("Save").clicked();
                             if click {
+                                let label = self.result_label.trim();
+                                self.result.as_mut().unwrap().result.raw_result.label =
+                                    (!label.is_empty()).then(|| label.to_owned());
                                 let name = timed(&self.result_name);
                                 self.raw_result_saved = test::save_raw(
                                     &self.result.as_ref().unwrap().result.raw_result,
                                     &name,
-                                    Path::new("crusader-results"),
+                                    Path::new(&self.settings.results_dir),
+                                    Compression::default(),
                                 )
                                 .ok();
                                 plot::save_graph(
                                     &PlotConfig::default(),
                                     &self.result.as_ref().unwrap().result,
                                     &name,
-                                    Path::new("crusader-results"),
+                                    Path::new(&self.settings.results_dir),
                                 )
                                 .ok();
                                 ui.memory_mut(|mem| {
@@ -1035,8 +1775,11 @@ impl Tester {
                                 })
                                 .unwrap_or(timed("test"));
 
-                            let mut dialog = FileDialog::new()
+                            let mut dialog = self
+                                .settings
+                                .file_dialog()
                                 .add_filter("Portable Network Graphics", &["png"])
+                                .add_filter("Scalable Vector Graphics", &["svg"])
                                 .add_filter("All files", &["*"])
                                 .set_file_name(&format!("{}.png", name));
 
@@ -1047,13 +1790,13 @@ impl Tester {
                             }
 
                             dialog.save_file().map(|file| {
-                                if plot::save_graph_to_path(
-                                    &file,
-                                    &PlotConfig::default(),
-                                    &self.result.as_ref().unwrap().result,
-                                )
-                                .is_ok()
-                                {
+                                self.settings.remember_dir(&file);
+                                let result = &self.result.as_ref().unwrap().result;
+                                let config = PlotConfig {
+                                    time_range: self.zoomed_range(result),
+                                    ..PlotConfig::default()
+                                };
+                                if plot::save_graph_to_path(&file, &config, result).is_ok() {
                                     file.file_name()
                                         .unwrap_or_default()
                                         .to_str()
@@ -1064,7 +1807,138 @@ impl Tester {
                     }
                 }
             }
+
+            ui.add_space(10.0);
+
+            if ui
+                .checkbox(&mut self.result_ewma, "EWMA smoothing")
+                .on_hover_text(
+                    "Smooth the throughput averages using an exponentially weighted moving average",
+                )
+                .changed()
+            {
+                let result = self.result.take().unwrap();
+                self.result = Some(TestResult::new(result.result, self.result_ewma));
+                self.result_comparisons = mem::take(&mut self.result_comparisons)
+                    .into_iter()
+                    .map(|comparison| Comparison {
+                        result: TestResult::new(comparison.result.result, self.result_ewma),
+                        ..comparison
+                    })
+                    .collect();
+            }
+
+            ui.add_space(10.0);
+
+            ui.checkbox(&mut self.settings.idle_latency_line, "Idle latency line")
+                .on_hover_text("Draw the idle latency on the latency plot");
+
+            ui.add_space(10.0);
+
+            ui.checkbox(&mut self.settings.individual_streams, "Individual streams")
+                .on_hover_text("Draw the throughput of each stream beneath the aggregate");
+
+            ui.add_space(10.0);
+
+            ui.checkbox(&mut self.settings.fairness_plot, "Fairness plot")
+                .on_hover_text(
+                    "Plot the download share of the throughput during the bidirectional test",
+                );
+
+            ui.add_space(10.0);
+
+            ui.label("Line width:");
+            ui.add(
+                egui::DragValue::new(&mut self.settings.line_scale)
+                    .range(0.5..=4.0)
+                    .speed(0.01)
+                    .suffix("x"),
+            )
+            .on_hover_text("Scale the width of plot lines, for high resolution displays");
+
+            ui.add_space(10.0);
+
+            let theme = self.settings.plot_theme;
+            egui::ComboBox::from_label("Colors")
+                .selected_text(theme.name())
+                .show_ui(ui, |ui| {
+                    for option in PlotTheme::ALL {
+                        ui.selectable_value(&mut self.settings.plot_theme, option, option.name());
+                    }
+                });
+            if self.settings.plot_theme != theme {
+                self.save_settings();
+            }
+
+            ui.add_space(10.0);
+
+            let unit = self.settings.throughput_unit;
+            egui::ComboBox::from_label("Units")
+                .selected_text(unit.name())
+                .show_ui(ui, |ui| {
+                    for option in ThroughputUnit::ALL {
+                        ui.selectable_value(
+                            &mut self.settings.throughput_unit,
+                            option,
+                            option.name(),
+                        );
+                    }
+                });
+            if self.settings.throughput_unit != unit {
+                self.result_plot_reset = true;
+                self.save_settings();
+            }
+
+            if cfg!(not(target_os = "android")) {
+                ui.add_space(10.0);
+
+                if ui
+                    .button("Compare")
+                    .on_hover_text("Draw other results on the same axes for comparison")
+                    .clicked()
+                {
+                    self.load_comparisons();
+                }
+
+                if !self.result_comparisons.is_empty() && ui.button("Clear comparisons").clicked() {
+                    self.result_comparisons.clear();
+                    self.result_plot_reset = true;
+                }
+            }
+
+            ui.add_space(10.0);
+
+            if ui
+                .button("Reset zoom")
+                .on_hover_text("Show the full time range of all plots (R)")
+                .clicked()
+                || reset_zoom_key(ui)
+            {
+                self.result_plot_reset = true;
+            }
         });
+
+        if !self.result_comparisons.is_empty() {
+            let mut remove = None;
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Compared with:");
+                for (i, comparison) in self.result_comparisons.iter_mut().enumerate() {
+                    let text = format!("{}: {}", comparison_tag(i), comparison.name);
+                    if ui.checkbox(&mut comparison.shown, text).changed() {
+                        self.result_plot_reset = true;
+                    }
+                    if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                    ui.add_space(5.0);
+                }
+            });
+            if let Some(i) = remove {
+                self.result_comparisons.remove(i);
+                self.result_plot_reset = true;
+            }
+        }
+
         ui.separator();
 
         self.raw_result_saved
@@ -1082,17 +1956,94 @@ impl Tester {
 
         let result = self.result.as_ref().unwrap();
 
+        if !result.result.raw_result.server.is_empty() {
+            ui.label(format!("Server: {}", result.result.raw_result.server));
+            ui.separator();
+        }
+
+        if let Some(label) = result.result.raw_result.label.as_ref() {
+            ui.label(format!("Label: {label}"));
+            ui.separator();
+        }
+
+        if !result.grades.is_empty() {
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Bufferbloat grade:")
+                    .on_hover_text("Based on the latency increase over idle latency under load");
+                for &(kind, grade) in &result.grades {
+                    ui.add_space(5.0);
+                    ui.label(kind.name());
+                    ui.label(
+                        RichText::new(format!(" {} ", grade.name()))
+                            .strong()
+                            .color(Color32::WHITE)
+                            .background_color(grade_color(grade)),
+                    );
+                }
+            });
+            ui.separator();
+        }
+
         if result.result.raw_result.server_overload {
             ui.label("Warning: Server overload detected during test. Result should be discarded.");
             ui.separator();
         }
 
+        if result.result.raw_result.partial {
+            ui.label("Warning: The connection to the server was lost before all measurements were received. The result is partial.");
+            ui.separator();
+        }
+
+        if let Some(diagnostic) = result.result.raw_result.server_diagnostic.as_ref() {
+            ui.label(format!("Note: {diagnostic}"));
+            ui.separator();
+        }
+
         if result.result.raw_result.load_termination_timeout {
             ui.label("Warning: Load termination timed out. There may be residual untracked traffic in the background.");
             ui.separator();
         }
 
+        if result.result.raw_result.clock_suspect {
+            ui.label("Warning: Clock drift detected during test. Upload and download latency may be inaccurate.");
+            ui.separator();
+        }
+
+        if result.result.raw_result.duplicate_pings > 0 {
+            ui.label(format!(
+                "Warning: {} duplicated ping replies received. The network may be duplicating packets.",
+                result.result.raw_result.duplicate_pings
+            ));
+            ui.separator();
+        }
+
+        if let Some(counters) = result
+            .result
+            .raw_result
+            .interface_counters
+            .as_ref()
+            .filter(|counters| !counters.is_zero())
+        {
+            ui.label(format!(
+                "Warning: {}. Some loss may be at the local interface rather than along the path.",
+                counters.describe()
+            ));
+            ui.separator();
+        }
+
+        // The pointer position is only known after the plots are drawn, so use the last frame's
+        let cursor = mem::take(&mut self.result_cursor);
+        cursor_readout(
+            ui,
+            self.result.as_ref().unwrap(),
+            cursor,
+            self.settings.plot_theme.colors(),
+            self.settings.throughput_unit,
+        );
+        ui.separator();
+
         let packet_loss_size = 75.0;
+        let fairness_size = 150.0;
 
         let result = self.result.as_ref().unwrap();
 
@@ -1104,7 +2055,13 @@ impl Tester {
             strip = strip.size(Size::remainder());
         }
 
-        for _ in 0..(1 + result.peer_latency.is_some() as u8) {
+        let fairness_plot = self.settings.fairness_plot && result.fairness_over_time.is_some();
+
+        if fairness_plot {
+            strip = strip.size(Size::exact(fairness_size));
+        }
+
+        for _ in 0..(1 + result.peer_latencies.len()) {
             strip = strip
                 .size(Size::remainder())
                 .size(Size::exact(packet_loss_size));
@@ -1117,7 +2074,28 @@ impl Tester {
 
             let y_axis_size = 30.0;
 
-            let duration = result.result.duration.as_secs_f64() * 1.1;
+            let comparisons = self.shown_comparisons();
+
+            let unit = self.settings.throughput_unit;
+            let scale = unit.scale();
+            let individual_streams = self.settings.individual_streams;
+
+            let duration = (comparisons
+                .iter()
+                .map(|(_, comparison)| comparison.result.duration.as_secs_f64())
+                .fold(result.result.duration.as_secs_f64(), f64::max)
+                * 1.1)
+                .max(MIN_PLOT_DURATION);
+
+            let name = |name: &str| {
+                if !comparisons.is_empty() {
+                    format!("{name} (A)")
+                } else {
+                    name.to_owned()
+                }
+            };
+
+            let mut hovered = None;
 
             if result.result.raw_result.streams() > 0 {
                 strip.cell(|ui| {
@@ -1137,10 +2115,9 @@ impl Tester {
                                     ui.add_space(5.0);
                                     ui.horizontal(|ui| {
                                         ui.label(
-                                            RichText::new("Download: ")
-                                                .color(Color32::from_rgb(95, 145, 62)),
+                                            RichText::new("Download: ").color(colors.download),
                                         );
-                                        ui.label(format!("{:.02} Mbps", throughput));
+                                        ui.label(unit.format(*throughput));
                                     });
                                 });
                             }
@@ -1153,11 +2130,8 @@ impl Tester {
                                 ui.vertical(|ui| {
                                     ui.add_space(5.0);
                                     ui.horizontal(|ui| {
-                                        ui.label(
-                                            RichText::new("Upload: ")
-                                                .color(Color32::from_rgb(37, 83, 169)),
-                                        );
-                                        ui.label(format!("{:.02} Mbps", throughput));
+                                        ui.label(RichText::new("Upload: ").color(colors.upload));
+                                        ui.label(unit.format(*throughput));
                                     });
                                 });
                             }
@@ -1172,9 +2146,9 @@ impl Tester {
                                     ui.horizontal(|ui| {
                                         ui.label(
                                             RichText::new("Bidirectional: ")
-                                                .color(Color32::from_rgb(149, 96, 153)),
+                                                .color(colors.bidirectional),
                                         );
-                                        ui.label(format!("{:.02} Mbps ", throughput));
+                                        ui.label(format!("{} ", unit.format(*throughput)));
                                     });
                                     if let Some(down) = result
                                         .result
@@ -1187,21 +2161,24 @@ impl Tester {
                                             .get(&(TestKind::Bidirectional, TestKind::Upload))
                                         {
                                             ui.horizontal(|ui| {
-                                                ui.label(format!("\t\t{:.02} Mbps ", down));
-                                                ui.label(
-                                                    RichText::new("down")
-                                                        .color(Color32::from_rgb(95, 145, 62)),
-                                                );
+                                                ui.label(format!("\t\t{} ", unit.format(*down)));
+                                                ui.label(RichText::new("down").color(colors.down));
                                             });
                                             ui.horizontal(|ui| {
-                                                ui.label(format!("\t\t{:.02} Mbps ", up));
-                                                ui.label(
-                                                    RichText::new("up")
-                                                        .color(Color32::from_rgb(37, 83, 169)),
-                                                );
+                                                ui.label(format!("\t\t{} ", unit.format(*up)));
+                                                ui.label(RichText::new("up").color(colors.up));
                                             });
                                         }
                                     }
+                                    if let Some(fairness) = result.fairness {
+                                        ui.horizontal(|ui| {
+                                            ui.label(format!(
+                                                "\t\tFairness: {:.0}% ",
+                                                fairness * 100.0
+                                            ));
+                                            ui.label(RichText::new("down").color(colors.down));
+                                        });
+                                    }
                                 });
                             }
 
@@ -1216,6 +2193,41 @@ impl Tester {
                                 });
                             });
 
+                            for (label, download) in [("Downloaded: ", true), ("Uploaded: ", false)]
+                            {
+                                let bytes = result.result.raw_result.total_bytes(download);
+                                if bytes > 0 {
+                                    ui.vertical(|ui| {
+                                        ui.add_space(5.0);
+                                        ui.horizontal(|ui| {
+                                            ui.label(
+                                                RichText::new(label)
+                                                    .color(Color32::from_rgb(128, 128, 128)),
+                                            );
+                                            ui.label(plot::format_bytes(bytes));
+                                        });
+                                    });
+                                }
+                            }
+
+                            for group in &result.result.stream_groups {
+                                if let Some(cv) = group.throughput_cv {
+                                    ui.vertical(|ui| {
+                                        ui.add_space(5.0);
+                                        ui.horizontal(|ui| {
+                                            ui.label(
+                                                RichText::new(format!(
+                                                    "{} stream variation: ",
+                                                    group.name()
+                                                ))
+                                                .color(Color32::from_rgb(128, 128, 128)),
+                                            );
+                                            ui.label(format!("{:.01}%", cv * 100.0));
+                                        });
+                                    });
+                                }
+                            }
+
                             ui.vertical(|ui| {
                                 ui.add_space(5.0);
                                 ui.horizontal(|ui| {
@@ -1265,60 +2277,90 @@ impl Tester {
                         .include_x(0.0)
                         .include_x(duration)
                         .include_y(0.0)
-                        .include_y(result.throughput_max * 1.1)
+                        .include_y(result.throughput_max * scale * 1.1)
+                        .include_y(
+                            comparisons
+                                .iter()
+                                .map(|(_, comparison)| comparison.throughput_max)
+                                .fold(0.0, f64::max)
+                                * scale
+                                * 1.1,
+                        )
                         .height(ui.available_height())
-                        .label_formatter(|_, value| {
-                            format!("Throughput = {:.2} Mbps\nTime = {:.2} s", value.y, value.x)
+                        .label_formatter(move |_, value| {
+                            format!(
+                                "Throughput = {:.2} {}\nTime = {:.2} s",
+                                value.y,
+                                unit.name(),
+                                value.x
+                            )
                         });
 
                     if reset {
                         plot = plot.reset();
                     }
 
-                    plot.show(ui, |plot_ui| {
-                        let width = 1.0;
+                    let response = plot.show(ui, |plot_ui| {
+                        if individual_streams {
+                            for (download, data) in &result.streams {
+                                let color = if *download {
+                                    colors.download
+                                } else {
+                                    colors.upload
+                                };
+                                let stream = data.iter().map(|v| [v.0, v.1 * scale]);
+                                let stream = Line::new(PlotPoints::from_iter(stream))
+                                    .color(color.gamma_multiply(0.3))
+                                    .allow_hover(false)
+                                    .width(line_scale);
+
+                                plot_ui.line(stream);
+                            }
+                        }
+
+                        let width = line_scale;
                         if let Some(data) = result.download.as_ref() {
-                            let download = data.iter().map(|v| [v.0, v.1]);
+                            let download = data.iter().map(|v| [v.0, v.1 * scale]);
                             let download = Line::new(PlotPoints::from_iter(download))
-                                .color(Color32::from_rgb(95, 145, 62))
+                                .color(colors.download)
                                 .width(width)
-                                .name("Download");
+                                .name(name("Download"));
 
                             plot_ui.line(download);
                         }
                         if let Some(data) = result.upload.as_ref() {
-                            let upload = data.iter().map(|v| [v.0, v.1]);
+                            let upload = data.iter().map(|v| [v.0, v.1 * scale]);
                             let upload = Line::new(PlotPoints::from_iter(upload))
-                                .color(Color32::from_rgb(37, 83, 169))
+                                .color(colors.upload)
                                 .width(width)
-                                .name("Upload");
+                                .name(name("Upload"));
 
                             plot_ui.line(upload);
                         }
                         if let Some(data) = result.both_download.as_ref() {
-                            let download = data.iter().map(|v| [v.0, v.1]);
+                            let download = data.iter().map(|v| [v.0, v.1 * scale]);
                             let download = Line::new(PlotPoints::from_iter(download))
-                                .color(Color32::from_rgb(95, 145, 62))
+                                .color(colors.download)
                                 .width(width)
-                                .name("Download");
+                                .name(name("Download"));
 
                             plot_ui.line(download);
                         }
                         if let Some(data) = result.both_upload.as_ref() {
-                            let upload = data.iter().map(|v| [v.0, v.1]);
+                            let upload = data.iter().map(|v| [v.0, v.1 * scale]);
                             let upload = Line::new(PlotPoints::from_iter(upload))
-                                .color(Color32::from_rgb(37, 83, 169))
+                                .color(colors.upload)
                                 .width(width)
-                                .name("Upload");
+                                .name(name("Upload"));
 
                             plot_ui.line(upload);
                         }
                         if let Some(data) = result.both.as_ref() {
-                            let both = data.iter().map(|v| [v.0, v.1]);
+                            let both = data.iter().map(|v| [v.0, v.1 * scale]);
                             let both = Line::new(PlotPoints::from_iter(both))
-                                .color(Color32::from_rgb(149, 96, 153))
+                                .color(colors.bidirectional)
                                 .width(width)
-                                .name("Aggregate");
+                                .name(name("Aggregate"));
 
                             plot_ui.line(both);
                         }
@@ -1328,85 +2370,206 @@ impl Tester {
                         let alpha = 0.35;
 
                         if let Some(data) = result.download_avg.as_ref() {
-                            let download = data.iter().map(|v| [v.0, v.1]);
+                            let download = data.iter().map(|v| [v.0, v.1 * scale]);
                             let download = Line::new(PlotPoints::from_iter(download))
                                 .color(
-                                    Color32::from_rgb(95, 145, 62)
+                                    colors
+                                        .download
                                         .lerp_to_gamma(Color32::BLACK, darken)
                                         .gamma_multiply(alpha),
                                 )
                                 .allow_hover(false)
-                                .width(3.5)
-                                .name("Download");
+                                .width(3.5 * line_scale)
+                                .name(name("Download"));
 
                             plot_ui.line(download);
                         }
                         if let Some(data) = result.upload_avg.as_ref() {
-                            let upload = data.iter().map(|v| [v.0, v.1]);
+                            let upload = data.iter().map(|v| [v.0, v.1 * scale]);
                             let upload = Line::new(PlotPoints::from_iter(upload))
                                 .color(
-                                    Color32::from_rgb(37, 83, 169)
+                                    colors
+                                        .upload
                                         .lerp_to_gamma(Color32::BLACK, darken)
                                         .gamma_multiply(alpha),
                                 )
                                 .allow_hover(false)
-                                .width(3.5)
-                                .name("Upload");
+                                .width(3.5 * line_scale)
+                                .name(name("Upload"));
 
                             plot_ui.line(upload);
                         }
                         if let Some(data) = result.both_download_avg.as_ref() {
-                            let download = data.iter().map(|v| [v.0, v.1]);
+                            let download = data.iter().map(|v| [v.0, v.1 * scale]);
                             let download = Line::new(PlotPoints::from_iter(download))
                                 .color(
-                                    Color32::from_rgb(95, 145, 62)
+                                    colors
+                                        .download
                                         .lerp_to_gamma(Color32::BLACK, darken)
                                         .gamma_multiply(alpha),
                                 )
                                 .allow_hover(false)
-                                .width(3.5)
-                                .name("Download");
+                                .width(3.5 * line_scale)
+                                .name(name("Download"));
 
                             plot_ui.line(download);
                         }
                         if let Some(data) = result.both_upload_avg.as_ref() {
-                            let upload = data.iter().map(|v| [v.0, v.1]);
+                            let upload = data.iter().map(|v| [v.0, v.1 * scale]);
                             let upload = Line::new(PlotPoints::from_iter(upload))
                                 .color(
-                                    Color32::from_rgb(37, 83, 169)
+                                    colors
+                                        .upload
                                         .lerp_to_gamma(Color32::BLACK, darken)
                                         .gamma_multiply(alpha),
                                 )
                                 .allow_hover(false)
-                                .width(3.5)
-                                .name("Upload");
+                                .width(3.5 * line_scale)
+                                .name(name("Upload"));
 
                             plot_ui.line(upload);
                         }
                         if let Some(data) = result.both_avg.as_ref() {
-                            let both = data.iter().map(|v| [v.0, v.1]);
+                            let both = data.iter().map(|v| [v.0, v.1 * scale]);
                             let both = Line::new(PlotPoints::from_iter(both))
                                 .color(
-                                    Color32::from_rgb(149, 96, 153)
+                                    colors
+                                        .bidirectional
                                         .lerp_to_gamma(Color32::BLACK, darken)
                                         .gamma_multiply(alpha),
                                 )
                                 .allow_hover(false)
-                                .width(3.5)
-                                .name("Aggregate");
+                                .width(3.5 * line_scale)
+                                .name(name("Aggregate"));
 
                             plot_ui.line(both);
                         }
+
+                        // Mark an even split of the aggregate throughput during the bidirectional test
+                        let both = result
+                            .result
+                            .throughputs
+                            .get(&(TestKind::Bidirectional, TestKind::Bidirectional));
+                        let both_test = result
+                            .result
+                            .raw_result
+                            .test_data
+                            .iter()
+                            .find(|test| test.kind == TestKind::Bidirectional);
+                        if let (Some(both), Some(test), Some(_)) =
+                            (both, both_test, result.fairness)
+                        {
+                            let start = result.result.start.as_secs_f64();
+                            let x0 = test.start.as_secs_f64() - start;
+                            let x1 = test.end.as_secs_f64() - start;
+                            let even = Line::new(PlotPoints::from_iter([
+                                [x0, both * scale / 2.0],
+                                [x1, both * scale / 2.0],
+                            ]))
+                            .color(colors.bidirectional.gamma_multiply(0.6))
+                            .style(LineStyle::dashed_dense())
+                            .allow_hover(false)
+                            .width(LINE_WIDTH * line_scale)
+                            .name("Even split");
+
+                            plot_ui.line(even);
+                        }
+
+                        // Draw the averages of the comparisons dashed and faded
+                        for &(tag, overlay) in &comparisons {
+                            let lines = [
+                                (&overlay.download_avg, colors.download, "Download"),
+                                (&overlay.upload_avg, colors.upload, "Upload"),
+                                (&overlay.both_download_avg, colors.download, "Download"),
+                                (&overlay.both_upload_avg, colors.upload, "Upload"),
+                                (&overlay.both_avg, colors.bidirectional, "Aggregate"),
+                            ];
+                            for (data, color, name) in lines {
+                                if let Some(data) = data.as_ref() {
+                                    let line = data.iter().map(|v| [v.0, v.1 * scale]);
+                                    let line = Line::new(PlotPoints::from_iter(line))
+                                        .color(color.gamma_multiply(0.6))
+                                        .style(LineStyle::dashed_loose())
+                                        .width(2.0 * line_scale)
+                                        .name(format!("{name} ({tag})"));
+
+                                    plot_ui.line(line);
+                                }
+                            }
+                        }
                     });
+
+                    hovered = hovered_time(&response);
                 })
             }
 
-            self.latency_and_loss(&mut strip, link, reset, false, y_axis_size);
+            if let Some(fairness) = result.fairness_over_time.as_ref().filter(|_| fairness_plot) {
+                strip.cell(|ui| {
+                    ui.label("Fairness");
 
-            let result = self.result.as_ref().unwrap();
+                    let mut plot = Plot::new("fairness")
+                        .legend(Legend::default().insertion_order(true))
+                        .y_axis_min_width(y_axis_size)
+                        .link_axis(link, true, false)
+                        .link_cursor(link, true, false)
+                        .include_x(0.0)
+                        .include_x(duration)
+                        .include_y(0.0)
+                        .include_y(100.0)
+                        .height(ui.available_height())
+                        .label_formatter(|_, value| {
+                            format!("Download share = {:.1}%\nTime = {:.2} s", value.y, value.x)
+                        });
+
+                    if reset {
+                        plot = plot.reset();
+                    }
+
+                    let response = plot.show(ui, |plot_ui| {
+                        plot_ui.hline(
+                            HLine::new(50.0)
+                                .color(Color32::from_rgb(128, 128, 128))
+                                .style(LineStyle::dashed_dense())
+                                .width(LINE_WIDTH * line_scale)
+                                .name("Even split"),
+                        );
+
+                        let share = fairness.iter().map(|v| [v.0, v.1]);
+                        let share = Line::new(PlotPoints::from_iter(share))
+                            .color(colors.download)
+                            .width(LINE_WIDTH * line_scale)
+                            .name(name("Download share"));
 
-            if result.peer_latency.is_some() {
-                self.latency_and_loss(&mut strip, link, reset, true, y_axis_size);
+                        plot_ui.line(share);
+
+                        for &(tag, overlay) in &comparisons {
+                            if let Some(data) = overlay.fairness_over_time.as_ref() {
+                                let share = data.iter().map(|v| [v.0, v.1]);
+                                let share = Line::new(PlotPoints::from_iter(share))
+                                    .color(colors.download.gamma_multiply(0.6))
+                                    .style(LineStyle::dashed_loose())
+                                    .width(LINE_WIDTH * line_scale)
+                                    .name(format!("Download share ({tag})"));
+
+                                plot_ui.line(share);
+                            }
+                        }
+                    });
+
+                    hovered = hovered.or(hovered_time(&response));
+                });
+            }
+
+            if hovered.is_some() {
+                self.result_cursor = hovered;
+            }
+
+            self.latency_and_loss(&mut strip, link, reset, None, y_axis_size);
+
+            let peers = self.result.as_ref().unwrap().peer_latencies.len();
+
+            for peer in 0..peers {
+                self.latency_and_loss(&mut strip, link, reset, Some(peer), y_axis_size);
             }
         });
     }
@@ -1415,7 +2578,16 @@ impl Tester {
         match self.server_state {
             ServerState::Stopped(ref error) => {
                 let (server_button, peer_button) = ui
-                    .horizontal_wrapped(|ui| (ui.button("Start server"), ui.button("Start peer")))
+                    .horizontal_wrapped(|ui| {
+                        let buttons = (ui.button("Start server"), ui.button("Start peer"));
+                        ui.add_space(10.0);
+                        ui.label("Log lines kept:");
+                        ui.add(
+                            egui::DragValue::new(&mut self.settings.log_lines)
+                                .range(100..=1_000_000),
+                        );
+                        buttons
+                    })
                     .inner;
 
                 if let Some(error) = error {
@@ -1433,7 +2605,9 @@ impl Tester {
 
                     let stop = serve::serve_until(
                         protocol::PORT,
+                        None,
                         peer_button.clicked(),
+                        serve::ServerLimits::default(),
                         Box::new(move |msg| {
                             tx.send(with_time(msg)).ok();
                             ctx.request_repaint();
@@ -1455,7 +2629,7 @@ impl Tester {
                             stop: Some(stop),
                             started,
                             rx,
-                            msgs: Vec::new(),
+                            msgs: VecDeque::new(),
                         });
                         self.server_state = ServerState::Starting;
                     }
@@ -1477,7 +2651,7 @@ impl Tester {
                     match server.rx.try_recv() {
                         Ok(msg) => {
                             println!("[Server] {msg}");
-                            server.msgs.push(msg);
+                            server.push(msg, self.settings.log_lines);
                         }
                         Err(TryRecvError::Disconnected) => panic!(),
                         Err(TryRecvError::Empty) => break,
@@ -1545,7 +2719,35 @@ impl Tester {
             ServerState::Stopped(ref error) => {
                 let button = ui
                     .vertical(|ui| {
-                        let button = ui.button("Start server");
+                        let button = ui
+                            .horizontal(|ui| {
+                                let button = ui.button("Start server");
+                                ui.add_space(10.0);
+                                ui.label("Port:");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.settings.remote_port)
+                                        .range(1..=u16::MAX),
+                                );
+                                ui.add_space(10.0);
+                                ui.label("Token:");
+                                ui.add(
+                                    TextEdit::singleline(&mut self.settings.remote_token)
+                                        .password(true)
+                                        .hint_text("None"),
+                                )
+                                .on_hover_text(
+                                    "Required to run tests if set. Web clients pass it by \
+                                    opening the page with ?token=<token>",
+                                );
+                                ui.add_space(10.0);
+                                ui.label("Log lines kept:");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.settings.log_lines)
+                                        .range(100..=1_000_000),
+                                );
+                                button
+                            })
+                            .inner;
                         if let Some(error) = error {
                             ui.separator();
                             ui.label(format!("Unable to start server: {}", error));
@@ -1555,6 +2757,7 @@ impl Tester {
                     .inner;
 
                 if button.clicked() {
+                    self.save_settings();
                     let ctx = ctx.clone();
                     let ctx_ = ctx.clone();
                     let ctx__ = ctx.clone();
@@ -1562,8 +2765,10 @@ impl Tester {
                     let (signal_started, started) = oneshot::channel();
                     let (signal_done, done) = oneshot::channel();
 
+                    let token = self.settings.remote_token.trim();
                     let stop = remote::serve_until(
-                        protocol::PORT + 1,
+                        self.settings.remote_port,
+                        (!token.is_empty()).then(|| token.to_owned()),
                         Box::new(move |msg| {
                             tx.send(with_time(msg)).ok();
                             ctx.request_repaint();
@@ -1585,7 +2790,7 @@ impl Tester {
                             stop: Some(stop),
                             started,
                             rx,
-                            msgs: Vec::new(),
+                            msgs: VecDeque::new(),
                         });
                         self.remote_state = ServerState::Starting;
                     }
@@ -1594,7 +2799,7 @@ impl Tester {
                 ui.label(format!(
                     "A remote server runs a web server on TCP port {}. It allows web clients to remotely start \
                     tests against other servers.",
-                    protocol::PORT + 1
+                    self.settings.remote_port
                 ));
             }
             ServerState::Running => {
@@ -1607,7 +2812,7 @@ impl Tester {
                     match remote_server.rx.try_recv() {
                         Ok(msg) => {
                             println!("[Remote] {msg}");
-                            remote_server.msgs.push(msg);
+                            remote_server.push(msg, self.settings.log_lines);
                         }
                         Err(TryRecvError::Disconnected) => panic!(),
                         Err(TryRecvError::Empty) => break,
@@ -1695,6 +2900,9 @@ impl Tester {
                 ping_interval: Duration::from_millis(
                     self.settings.latency_monitor.latency_sample_interval,
                 ),
+                reconnect: self.settings.latency_monitor.reconnect,
+                log: (!self.settings.latency_monitor.log.trim().is_empty())
+                    .then(|| PathBuf::from(self.settings.latency_monitor.log.trim())),
             },
             (!self.settings.latency_monitor.server.trim().is_empty())
                 .then_some(&self.settings.latency_monitor.server),
@@ -1727,9 +2935,22 @@ impl Tester {
                 );
                 let enter = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
 
+                if let Some(address) = self.server_finder(ctx, ui) {
+                    self.settings.latency_monitor.server = address;
+                }
+
                 if ui.button("Start test").clicked() || enter {
                     self.start_monitor(ctx)
                 }
+
+                if ui
+                    .button("Reset zoom")
+                    .on_hover_text("Show the full visible window (R)")
+                    .clicked()
+                    || reset_zoom_key(ui)
+                {
+                    self.latency_plot_reset = true;
+                }
             });
         }
 
@@ -1753,10 +2974,22 @@ impl Tester {
                     ClientState::Stopped => {}
                 }
 
+                if ui
+                    .button("Reset zoom")
+                    .on_hover_text("Show the full visible window (R)")
+                    .clicked()
+                    || reset_zoom_key(ui)
+                {
+                    self.latency_plot_reset = true;
+                }
+
                 let state = match *self.latency_data.state.lock() {
                     latency::State::Connecting => "Connecting..".to_owned(),
                     latency::State::Monitoring { ref at } => format!("Connected to {at}"),
                     latency::State::Syncing => "Synchronizing clocks..".to_owned(),
+                    latency::State::Reconnecting { ref error, delay } => {
+                        format!("Reconnecting in {} s ({error})..", delay.as_secs())
+                    }
                 };
                 ui.add(Label::new(state).wrap_mode(TextWrapMode::Truncate));
 
@@ -1796,7 +3029,67 @@ impl Tester {
                     .speed(0.05),
                 );
                 ui.label("milliseconds");
+                ui.end_row();
             });
+            ui.checkbox(
+                &mut self.settings.latency_monitor.reconnect,
+                "Reconnect automatically",
+            )
+            .on_hover_text(
+                "Keep monitoring after the connection is lost, recording the outage as packet loss",
+            );
+            ui.horizontal(|ui| {
+                ui.label("CSV log file:");
+                ui.add(
+                    TextEdit::singleline(&mut self.settings.latency_monitor.log)
+                        .hint_text("(None)"),
+                )
+                .on_hover_text("Append each latency sample to this file");
+            });
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Visible window:");
+            if ui
+                .add(
+                    egui::DragValue::new(&mut self.settings.latency_monitor.visible_window)
+                        .range(1..=1000)
+                        .speed(0.05),
+                )
+                .on_hover_text("How much of the history is shown in the plots")
+                .changed()
+            {
+                self.latency_plot_reset = true;
+            }
+            ui.label("seconds");
+        });
+
+        ui.horizontal_wrapped(|ui| {
+            let settings = &mut self.settings.latency_monitor;
+            ui.checkbox(&mut settings.alert, "Alert when latency exceeds")
+                .on_hover_text("Flash the latency plot when the link degrades");
+            ui.add(
+                egui::DragValue::new(&mut settings.alert_latency)
+                    .range(1..=10000)
+                    .speed(0.5),
+            );
+            ui.label("ms or packet loss exceeds");
+            ui.add(
+                egui::DragValue::new(&mut settings.alert_loss)
+                    .range(0.0..=100.0)
+                    .speed(0.1),
+            )
+            .on_hover_text(format!(
+                "Packet loss over the last {ALERT_LOSS_WINDOW} seconds"
+            ));
+            ui.label("%");
+            ui.add_enabled(
+                settings.alert,
+                egui::Checkbox::new(&mut settings.alert_notify, "Notify"),
+            )
+            .on_hover_text(
+                "Draw attention to the window when the alert starts, such as by flashing its taskbar entry",
+            );
         });
 
         ui.separator();
@@ -1810,11 +3103,19 @@ impl Tester {
     }
 
     fn latency_data(&mut self, ctx: &egui::Context, ui: &mut Ui) {
+        let colors = self.settings.plot_theme.colors();
+        let line_scale = self.settings.line_scale;
+
         ui.vertical(|ui| {
             let packet_loss_size = 80.0;
+            let summary_size = 34.0;
             let height = ui.available_height();
 
-            let duration = self.settings.latency_monitor.history;
+            let duration = self
+                .settings
+                .latency_monitor
+                .visible_window
+                .min(self.settings.latency_monitor.history);
 
             let points = self.latency_data.points.blocking_lock().clone();
 
@@ -1832,6 +3133,76 @@ impl Tester {
 
             let y_axis_size = 30.0;
 
+            // Summary of the round-trip latency in the visible window
+            let visible: Vec<[f64; 2]> = points
+                .iter()
+                .filter(|point| point.sent.as_secs_f64() >= now - duration)
+                .filter_map(|point| {
+                    point
+                        .total
+                        .map(|total| [point.sent.as_secs_f64() - now, 1000.0 * total.as_secs_f64()])
+                })
+                .collect();
+
+            ui.horizontal(|ui| {
+                Plot::new("latency-sparkline")
+                    .show_axes(false)
+                    .show_grid(false)
+                    .show_x(false)
+                    .show_y(false)
+                    .allow_drag(false)
+                    .allow_zoom(false)
+                    .allow_scroll(false)
+                    .allow_boxed_zoom(false)
+                    .include_x(-duration)
+                    .include_x(0.0)
+                    .include_y(0.0)
+                    .width(160.0)
+                    .height(24.0)
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(
+                            Line::new(PlotPoints::from_iter(visible.iter().copied()))
+                                .color(colors.roundtrip)
+                                .width(LINE_WIDTH * line_scale),
+                        );
+                    });
+
+                if let Some(&[_, current]) = visible.last() {
+                    let latencies = visible.iter().map(|point| point[1]);
+                    let min = latencies.clone().fold(f64::INFINITY, f64::min);
+                    let max = latencies.clone().fold(0.0, f64::max);
+                    let avg = latencies.sum::<f64>() / visible.len() as f64;
+                    ui.label(format!(
+                        "Current: {current:.1} ms   Min: {min:.1} ms   \
+                         Max: {max:.1} ms   Avg: {avg:.1} ms"
+                    ));
+                } else {
+                    ui.label("No latency samples");
+                }
+            });
+
+            let settings = &self.settings.latency_monitor;
+            let alert = settings.alert && self.latency_state == ClientState::Running && {
+                let latency_exceeded = visible
+                    .last()
+                    .is_some_and(|&[_, latency]| latency > settings.alert_latency as f64);
+
+                let recent = points.iter().filter(|point| {
+                    !point.pending && point.sent.as_secs_f64() >= now - ALERT_LOSS_WINDOW
+                });
+                let (count, lost) = recent.fold((0, 0), |(count, lost), point| {
+                    (count + 1, lost + point.total.is_none() as u64)
+                });
+                let loss_exceeded =
+                    count > 0 && lost as f64 * 100.0 / count as f64 > settings.alert_loss;
+
+                latency_exceeded || loss_exceeded
+            };
+            if alert && !self.latency_alert && settings.alert_notify {
+                request_attention(ctx);
+            }
+            self.latency_alert = alert;
+
             // Latency
             let mut plot = Plot::new("latency-ping")
                 .legend(Legend::default().insertion_order(true))
@@ -1842,7 +3213,7 @@ impl Tester {
                 .include_x(duration * 0.20)
                 .include_y(0.0)
                 .include_y(10.0)
-                .height(height - packet_loss_size)
+                .height(height - packet_loss_size - summary_size)
                 .y_axis_min_width(y_axis_size)
                 .auto_bounds(Vec2b::new(false, true))
                 .label_formatter(|_, value| {
@@ -1854,7 +3225,7 @@ impl Tester {
             }
 
             ui.label("Latency");
-            plot.show(ui, |plot_ui| {
+            let response = plot.show(ui, |plot_ui| {
                 let latency = points.iter().filter_map(|point| {
                     point.up.map(|up| {
                         let up = if let Some(total) = point.total {
@@ -1866,7 +3237,8 @@ impl Tester {
                     })
                 });
                 let latency = Line::new(PlotPoints::from_iter(latency))
-                    .color(Color32::from_rgb(37, 83, 169))
+                    .color(colors.up)
+                    .width(LINE_WIDTH * line_scale)
                     .name("Up");
 
                 plot_ui.line(latency);
@@ -1878,7 +3250,8 @@ impl Tester {
                         .map(|down| [point.sent.as_secs_f64() - now, 1000.0 * down.as_secs_f64()])
                 });
                 let latency = Line::new(PlotPoints::from_iter(latency))
-                    .color(Color32::from_rgb(95, 145, 62))
+                    .color(colors.down)
+                    .width(LINE_WIDTH * line_scale)
                     .name("Down");
 
                 plot_ui.line(latency);
@@ -1889,15 +3262,25 @@ impl Tester {
                         .map(|total| [point.sent.as_secs_f64() - now, 1000.0 * total.as_secs_f64()])
                 });
                 let latency = Line::new(PlotPoints::from_iter(latency))
-                    .color(Color32::from_rgb(50, 50, 50))
+                    .color(colors.roundtrip)
+                    .width(LINE_WIDTH * line_scale)
                     .name("Round-trip");
 
                 plot_ui.line(latency);
             });
 
+            // Flash the plot twice a second while alerting
+            if alert && (now * 2.0) as u64 % 2 == 0 {
+                ui.painter().rect_filled(
+                    response.response.rect,
+                    0.0,
+                    Color32::from_rgba_unmultiplied(255, 0, 0, 40),
+                );
+            }
+
             // Packet loss
             let mut plot = Plot::new("latency-loss")
-                .legend(Legend::default())
+                .legend(Legend::default().text_style(TextStyle::Small))
                 .show_axes([false, true])
                 .show_grid(Vec2b::new(true, false))
                 .y_axis_min_width(y_axis_size)
@@ -1928,24 +3311,26 @@ impl Tester {
                 for point in loss {
                     let loss = point.sent.as_secs_f64() - now;
 
-                    let (color, s, e) = if point.up.is_some() {
-                        (Color32::from_rgb(95, 145, 62), 1.0, 0.0)
+                    let (color, s, e, name) = if point.up.is_some() {
+                        (colors.down, 1.0, 0.0, "Download loss")
                     } else {
-                        (Color32::from_rgb(37, 83, 169), -1.0, 0.0)
+                        (colors.up, -1.0, 0.0, "Upload loss")
                     };
 
                     plot_ui.line(
                         Line::new(PlotPoints::from_iter(
                             [[loss, s], [loss, e]].iter().copied(),
                         ))
-                        .color(color),
+                        .width(LINE_WIDTH * line_scale)
+                        .color(color)
+                        .name(name),
                     );
 
                     plot_ui.line(
                         Line::new(PlotPoints::from_iter(
                             [[loss, s], [loss, s - s / 5.0]].iter().copied(),
                         ))
-                        .width(3.0)
+                        .width(3.0 * line_scale)
                         .color(color),
                     );
                 }
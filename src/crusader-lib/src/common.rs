@@ -1,29 +1,35 @@
+#[cfg(feature = "client")]
+use crate::{file_format::Coordination, iface::Interface, proxy::Proxy};
 use crate::{
-    protocol::{receive, send, ClientMessage, Hello, Ping, ServerMessage},
+    icmp::Echo,
+    protocol::{
+        codec, receive, send, ClientMessage, Hello, Ping, ServerMessage, LEGACY_VERSION,
+        MAX_PING_SIZE,
+    },
     serve::OnDrop,
+    transport::{self, PingSocket, ReadHalf, WriteHalf},
 };
-use anyhow::{anyhow, bail, Context};
+use anyhow::{bail, Context};
 use bytes::{Bytes, BytesMut};
 use futures::{pin_mut, select, FutureExt, Sink, Stream};
+use parking_lot::Mutex;
 use rand::Rng;
 use rand::{rngs::StdRng, SeedableRng};
 use std::{
+    collections::VecDeque,
     error::Error,
+    fmt,
+    future::Future,
     io::Cursor,
     net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering},
-        Arc,
+        Arc, OnceLock,
     },
     time::Duration,
 };
 use tokio::{
-    join,
-    net::{
-        self,
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpStream, ToSocketAddrs, UdpSocket,
-    },
+    net::{self, TcpStream, ToSocketAddrs},
     sync::{
         oneshot,
         watch::{self, error::RecvError},
@@ -36,6 +42,47 @@ use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 #[cfg(feature = "client")]
 pub(crate) type Msg = Arc<dyn Fn(&str) + Send + Sync>;
 
+// How long to wait for a TCP connection to be established by default
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(8);
+
+// How long to wait for latency replies from the server by default
+pub const LATENCY_TIMEOUT: Duration = Duration::from_secs(1);
+
+// The samples used to measure the idle latency by default
+pub const IDLE_LATENCY_SAMPLES: u32 = 100;
+pub const IDLE_LATENCY_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Errors from measuring the idle latency to a server.
+#[derive(Debug)]
+pub enum LatencyError {
+    /// The server accepted the TCP connection, but no UDP replies were received.
+    UdpBlocked { server: SocketAddr },
+    /// Too few UDP replies were received to estimate the latency.
+    InsufficientSamples { received: usize, required: usize },
+    /// The server didn't complete the measurement in time.
+    Timeout,
+}
+
+impl fmt::Display for LatencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LatencyError::UdpBlocked { server } => write!(
+                f,
+                "No UDP replies from server {server}. \
+                 Check that UDP port {} is reachable and not blocked by a firewall",
+                server.port()
+            ),
+            LatencyError::InsufficientSamples { received, required } => write!(
+                f,
+                "Unable to get enough latency samples from server ({received} of {required})"
+            ),
+            LatencyError::Timeout => write!(f, "Timed out waiting for latency measurements"),
+        }
+    }
+}
+
+impl Error for LatencyError {}
+
 #[allow(unused)]
 #[derive(PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord)]
 pub(crate) enum TestState {
@@ -60,19 +107,281 @@ pub struct Config {
     pub port: u16,
     pub load_duration: Duration,
     pub grace_duration: Duration,
+    /// The grace before the first test. Uses `grace_duration` if `None`.
+    pub initial_grace: Option<Duration>,
+    /// The grace after the last test. Uses `grace_duration` if `None`.
+    pub final_grace: Option<Duration>,
     pub streams: u64,
     pub stream_stagger: Duration,
     pub ping_interval: Duration,
+    pub ping_payload_size: usize,
+    /// The maximum random delay added to each latency sample, so pings are not perfectly periodic.
+    pub ping_jitter: Duration,
     pub throughput_interval: Duration,
+    pub force_ipv4: bool,
+    pub force_ipv6: bool,
+    pub setup_timeout: Duration,
+    /// How long to wait for each attempt to connect to the server.
+    pub connect_timeout: Duration,
+    /// How many times to retry connecting to the server, with an increasing delay between attempts.
+    pub connect_retries: u32,
+    /// The TCP send and receive buffer size of the throughput connections. Uses the OS default if `None`.
+    pub socket_buffer_size: Option<u32>,
+    /// The size of reads and writes on the throughput connections.
+    /// Defaults to `DEFAULT_READ_SIZE` and `DEFAULT_WRITE_SIZE` if `None`.
+    pub chunk_size: Option<usize>,
+    /// Generate fresh random data for uploads on each run instead of using a fixed seed.
+    pub incompressible: bool,
+    /// Reduces the stored throughput points of each stream to this count, preserving peaks.
+    pub max_throughput_points: Option<usize>,
+    /// Tunnels the TCP connections to the server through a proxy. Latency isn't measured then.
+    pub proxy: Option<Proxy>,
+    /// Waits for the other clients of the group before starting the test.
+    pub coordination: Option<Coordination>,
+    /// The number of samples and their interval used to measure the idle latency before and after the test.
+    pub idle_latency_samples: u32,
+    pub idle_latency_interval: Duration,
+    /// The start of each load excluded from the average throughput, as TCP is still ramping up.
+    /// Uses 20% of the load duration, up to 2 seconds, if `None`.
+    pub warmup: Option<Duration>,
+    /// Paces each upload stream to this rate in Mbps instead of sending as fast as possible.
+    pub upload_rate: Option<f64>,
+    /// Measures latency with ICMP echo requests instead of UDP pings, for when UDP is blocked.
+    /// Only the round-trip latency is available then.
+    pub icmp: bool,
+    /// Ends each load once this many bytes are transferred in its direction instead of after
+    /// the load duration, which then only limits how long it can take.
+    pub load_bytes: Option<u64>,
+    /// Records the error and drop counters of this local interface during the test. Linux only.
+    pub interface: Option<Interface>,
+    /// Caps the total throughput of the streams of each load to this rate in Mbps.
+    /// Both directions of the bidirectional test share the cap.
+    pub total_rate: Option<f64>,
+    /// Marks the load and ping traffic sent by the client with this DSCP value.
+    pub dscp: Option<u8>,
+}
+
+#[cfg(feature = "client")]
+pub(crate) const DEFAULT_READ_SIZE: usize = 512 * 1024;
+pub(crate) const DEFAULT_WRITE_SIZE: usize = 128 * 1024;
+
+#[cfg(feature = "client")]
+impl Config {
+    /// A quick download only test which finishes in about 10 seconds.
+    /// It uses a single stream loading for 5 seconds with 0.25 seconds of grace
+    /// before and after, sampling latency every 5 ms and throughput every 60 ms.
+    pub fn fast() -> Self {
+        Config {
+            download: true,
+            upload: false,
+            bidirectional: false,
+            port: crate::protocol::PORT,
+            load_duration: Duration::from_secs(5),
+            grace_duration: Duration::from_millis(250),
+            initial_grace: None,
+            final_grace: None,
+            streams: 1,
+            stream_stagger: Duration::ZERO,
+            ping_interval: Duration::from_millis(5),
+            ping_payload_size: crate::protocol::PING_SIZE,
+            ping_jitter: Duration::ZERO,
+            throughput_interval: Duration::from_millis(60),
+            force_ipv4: false,
+            force_ipv6: false,
+            setup_timeout: LATENCY_TIMEOUT,
+            connect_timeout: CONNECT_TIMEOUT,
+            connect_retries: 0,
+            socket_buffer_size: None,
+            chunk_size: None,
+            incompressible: false,
+            max_throughput_points: None,
+            proxy: None,
+            coordination: None,
+            idle_latency_samples: IDLE_LATENCY_SAMPLES,
+            idle_latency_interval: IDLE_LATENCY_INTERVAL,
+            warmup: None,
+            upload_rate: None,
+            icmp: false,
+            load_bytes: None,
+            interface: None,
+            total_rate: None,
+            dscp: None,
+        }
+    }
+
+    /// A builder starting from the default configuration.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+}
+
+/// The default test of 8 streams loading for 10 seconds in each direction,
+/// with 2 seconds of grace between them.
+#[cfg(feature = "client")]
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            upload: true,
+            bidirectional: true,
+            load_duration: Duration::from_secs(10),
+            grace_duration: Duration::from_secs(2),
+            streams: 8,
+            ..Config::fast()
+        }
+    }
+}
+
+/// Builds a `Config`, so new options don't break callers which only set some of them.
+#[cfg(feature = "client")]
+#[derive(Copy, Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+#[cfg(feature = "client")]
+impl From<Config> for ConfigBuilder {
+    fn from(config: Config) -> Self {
+        ConfigBuilder { config }
+    }
+}
+
+#[cfg(feature = "client")]
+macro_rules! setters {
+    ($($field:ident: $type:ty),* $(,)?) => {
+        $(
+            pub fn $field(mut self, $field: $type) -> Self {
+                self.config.$field = $field;
+                self
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "client")]
+impl ConfigBuilder {
+    setters! {
+        download: bool,
+        upload: bool,
+        bidirectional: bool,
+        port: u16,
+        load_duration: Duration,
+        grace_duration: Duration,
+        initial_grace: Option<Duration>,
+        final_grace: Option<Duration>,
+        streams: u64,
+        stream_stagger: Duration,
+        ping_interval: Duration,
+        ping_payload_size: usize,
+        ping_jitter: Duration,
+        throughput_interval: Duration,
+        force_ipv4: bool,
+        force_ipv6: bool,
+        setup_timeout: Duration,
+        connect_timeout: Duration,
+        connect_retries: u32,
+        socket_buffer_size: Option<u32>,
+        chunk_size: Option<usize>,
+        incompressible: bool,
+        max_throughput_points: Option<usize>,
+        proxy: Option<Proxy>,
+        coordination: Option<Coordination>,
+        idle_latency_samples: u32,
+        idle_latency_interval: Duration,
+        warmup: Option<Duration>,
+        upload_rate: Option<f64>,
+        icmp: bool,
+        load_bytes: Option<u64>,
+        interface: Option<Interface>,
+        total_rate: Option<f64>,
+        dscp: Option<u8>,
+    }
+
+    pub fn build(self) -> Config {
+        self.config
+    }
 }
 
 pub async fn connect<A: ToSocketAddrs>(addr: A, name: &str) -> Result<TcpStream, anyhow::Error> {
-    match timeout(Duration::from_secs(8), net::TcpStream::connect(addr)).await {
+    connect_with_timeout(addr, name, CONNECT_TIMEOUT).await
+}
+
+pub async fn connect_with_timeout<A: ToSocketAddrs>(
+    addr: A,
+    name: &str,
+    duration: Duration,
+) -> Result<TcpStream, anyhow::Error> {
+    match timeout(duration, net::TcpStream::connect(addr)).await {
         Ok(v) => v.with_context(|| format!("Failed to connect to {name}")),
         Err(_) => bail!("Timed out trying to connect to {name}. Is the {name} running?"),
     }
 }
 
+/// Marks the traffic sent on a socket with a DSCP value, in the IPv4 ToS or IPv6 traffic class field.
+#[cfg(feature = "client")]
+pub(crate) fn set_dscp(
+    socket: socket2::SockRef<'_>,
+    ipv6: bool,
+    dscp: u8,
+) -> Result<(), anyhow::Error> {
+    // The DSCP is the upper 6 bits, the rest is left for ECN
+    let tos = u32::from(dscp) << 2;
+    if ipv6 {
+        set_traffic_class(&socket, tos)
+    } else {
+        socket.set_tos(tos)
+    }
+    .context("Failed to set the DSCP marking")
+}
+
+#[cfg(all(
+    feature = "client",
+    any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd"
+    )
+))]
+fn set_traffic_class(socket: &socket2::Socket, class: u32) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let class = class as libc::c_int;
+    // SAFETY: The option value points to `class`, which outlives the call and is the `c_int` of
+    // the length given, and the file descriptor stays open as `socket` is borrowed.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TCLASS,
+            &class as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(all(
+    feature = "client",
+    not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd"
+    ))
+))]
+fn set_traffic_class(_socket: &socket2::Socket, _class: u32) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "IPv6 traffic class marking is unsupported on this platform",
+    ))
+}
+
 pub fn interface_ips() -> Vec<(String, IpAddr)> {
     let mut _result = Vec::new();
 
@@ -154,24 +463,31 @@ pub fn inherit_local(socket: SocketAddr, ip: IpAddr, port: u16) -> SocketAddr {
     SocketAddr::new(ip, port)
 }
 
-pub(crate) fn data() -> Vec<u8> {
-    let mut vec = Vec::with_capacity(128 * 1024);
-    let mut rng = StdRng::from_seed([
-        18, 141, 186, 158, 195, 76, 244, 56, 219, 131, 65, 128, 250, 63, 228, 44, 233, 34, 9, 51,
-        13, 72, 230, 131, 223, 240, 124, 77, 103, 238, 103, 186,
-    ]);
-    for _ in 0..vec.capacity() {
+pub(crate) fn data(size: usize, incompressible: bool) -> Vec<u8> {
+    let mut vec = Vec::with_capacity(size);
+    let mut rng = if incompressible {
+        StdRng::from_entropy()
+    } else {
+        StdRng::from_seed([
+            18, 141, 186, 158, 195, 76, 244, 56, 219, 131, 65, 128, 250, 63, 228, 44, 233, 34, 9,
+            51, 13, 72, 230, 131, 223, 240, 124, 77, 103, 238, 103, 186,
+        ])
+    };
+    for _ in 0..size {
         vec.push(rng.gen())
     }
     vec
 }
 
 pub(crate) async fn read_data(
-    stream: TcpStream,
+    stream: transport::Stream,
     buffer: &mut [u8],
     bytes: Arc<AtomicU64>,
     until: Instant,
     writer_done: oneshot::Receiver<()>,
+    total: Option<&AtomicU64>,
+    limit: Option<(&AtomicU64, u64)>,
+    pacer: Option<&Pacer>,
 ) -> Result<bool, anyhow::Error> {
     stream.set_linger(Some(Duration::from_secs(0))).ok();
 
@@ -222,6 +538,14 @@ pub(crate) async fn read_data(
     });
 
     loop {
+        if let Some(pacer) = pacer {
+            // Reading slower makes TCP flow control slow down the sender
+            time::sleep_until(pacer.due().min(until)).await;
+        }
+
+        // Keep reads small enough that the pacer isn't pushed far into the future
+        let len = pacer.map_or(buffer.len(), |pacer| pacer.burst().min(buffer.len()));
+
         if let Ok(Err(err)) = time::timeout(Duration::from_millis(50), stream.readable()).await {
             if err.kind() == std::io::ErrorKind::ConnectionReset
                 || err.kind() == std::io::ErrorKind::ConnectionAborted
@@ -237,10 +561,22 @@ pub(crate) async fn read_data(
                 return Ok(true);
             }
 
-            match stream.try_read(buffer) {
+            match stream.try_read(&mut buffer[..len]) {
                 Ok(0) => return Ok(false),
                 Ok(n) => {
                     bytes.fetch_add(n as u64, Ordering::Release);
+                    if let Some(total) = total {
+                        total.fetch_add(n as u64, Ordering::Relaxed);
+                    }
+                    if limit_reached(limit, n) {
+                        return Ok(false);
+                    }
+                    if let Some(pacer) = pacer {
+                        pacer.sent(n);
+                        if pacer.due() > Instant::now() {
+                            break;
+                        }
+                    }
                     yield_now().await;
                 }
                 Err(err) => {
@@ -259,10 +595,58 @@ pub(crate) async fn read_data(
     }
 }
 
+/// The interval over which a pacer allows a burst of data.
+const PACING_TICK: Duration = Duration::from_millis(10);
+
+/// Paces the data of one or more streams to a rate.
+pub(crate) struct Pacer {
+    /// Bytes per second.
+    rate: f64,
+    start: OnceLock<Instant>,
+    sent: AtomicU64,
+}
+
+impl Pacer {
+    #[cfg(feature = "client")]
+    pub(crate) fn new(mbps: f64) -> Self {
+        Pacer {
+            rate: mbps * 1000.0 * 1000.0 / 8.0,
+            start: OnceLock::new(),
+            sent: AtomicU64::new(0),
+        }
+    }
+
+    /// When the bytes sent so far are due. The pacing starts with the first call.
+    fn due(&self) -> Instant {
+        let start = *self.start.get_or_init(Instant::now);
+        start + Duration::from_secs_f64(self.sent.load(Ordering::Relaxed) as f64 / self.rate)
+    }
+
+    fn sent(&self, n: usize) {
+        self.sent.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// The bytes allowed in one `PACING_TICK`.
+    fn burst(&self) -> usize {
+        ((self.rate * PACING_TICK.as_secs_f64()) as usize).max(1)
+    }
+}
+
+/// Adds `n` bytes to the count shared by the streams of a load,
+/// returning true once it reaches the byte limit.
+fn limit_reached(limit: Option<(&AtomicU64, u64)>, n: usize) -> bool {
+    limit.is_some_and(|(transferred, limit)| {
+        transferred.fetch_add(n as u64, Ordering::Relaxed) + n as u64 >= limit
+    })
+}
+
 pub(crate) async fn write_data(
-    stream: TcpStream,
+    stream: transport::Stream,
     data: &[u8],
     until: Instant,
+    total: Option<&AtomicU64>,
+    pacers: &[&Pacer],
+    limit: Option<(&AtomicU64, u64)>,
 ) -> Result<(), anyhow::Error> {
     stream.set_nodelay(false).ok();
     stream.set_linger(Some(Duration::from_secs(0))).ok();
@@ -276,6 +660,10 @@ pub(crate) async fn write_data(
     });
 
     loop {
+        if let Some(due) = pacers.iter().map(|pacer| pacer.due()).max() {
+            time::sleep_until(due.min(until)).await;
+        }
+
         if let Ok(Err(err)) = time::timeout(Duration::from_millis(50), stream.writable()).await {
             if err.kind() == std::io::ErrorKind::ConnectionReset
                 || err.kind() == std::io::ErrorKind::ConnectionAborted
@@ -289,8 +677,24 @@ pub(crate) async fn write_data(
         if done_.load(Ordering::Acquire) {
             break;
         }
-        match stream.try_write(data) {
-            Ok(_) => (),
+        let len = pacers
+            .iter()
+            .map(|pacer| pacer.burst())
+            .min()
+            .unwrap_or(data.len())
+            .min(data.len());
+        match stream.try_write(&data[..len]) {
+            Ok(n) => {
+                for pacer in pacers {
+                    pacer.sent(n);
+                }
+                if let Some(total) = total {
+                    total.fetch_add(n as u64, Ordering::Relaxed);
+                }
+                if limit_reached(limit, n) {
+                    break;
+                }
+            }
             Err(err) => {
                 if err.kind() == std::io::ErrorKind::WouldBlock {
                 } else if err.kind() == std::io::ErrorKind::ConnectionReset
@@ -318,25 +722,67 @@ pub(crate) async fn hello<
 >(
     tx: &mut T,
     rx: &mut R,
-) -> Result<(), anyhow::Error>
+) -> Result<u64, anyhow::Error>
 where
     T::Error: Error + Send + Sync + 'static,
     RE: Error + Send + Sync + 'static,
 {
-    let hello = Hello::new();
+    hello_offering(tx, rx, Hello::new()).await
+}
 
+async fn hello_offering<
+    T: Sink<Bytes> + Unpin,
+    R: Stream<Item = Result<BytesMut, RE>> + Unpin,
+    RE,
+>(
+    tx: &mut T,
+    rx: &mut R,
+    hello: Hello,
+) -> Result<u64, anyhow::Error>
+where
+    T::Error: Error + Send + Sync + 'static,
+    RE: Error + Send + Sync + 'static,
+{
     send(tx, &hello).await.context("Sending hello")?;
     let server_hello: Hello = receive(rx).await.context("Receiving hello")?;
 
-    if hello != server_hello {
-        bail!(
-            "Mismatched server hello, got {:?}, expected {:?}",
-            server_hello,
-            hello
-        );
+    server_hello.negotiate()
+}
+
+/// Exchanges hellos on a new control connection, returning it framed along with the protocol
+/// version. A server released before the version negotiation closes the connection after replying
+/// with its own version, so `reconnect` is used to connect again offering exactly that version.
+pub(crate) async fn control_hello<F: Future<Output = Result<transport::Stream, anyhow::Error>>>(
+    control: transport::Stream,
+    reconnect: impl FnOnce() -> F,
+) -> Result<
+    (
+        FramedRead<ReadHalf, LengthDelimitedCodec>,
+        FramedWrite<WriteHalf, LengthDelimitedCodec>,
+        u64,
+    ),
+    anyhow::Error,
+> {
+    let (rx, tx) = control.into_split();
+    let mut control_rx = FramedRead::new(rx, codec());
+    let mut control_tx = FramedWrite::new(tx, codec());
+
+    let version = hello(&mut control_tx, &mut control_rx).await?;
+    if version != LEGACY_VERSION {
+        return Ok((control_rx, control_tx, version));
     }
 
-    Ok(())
+    let (rx, tx) = reconnect().await?.into_split();
+    let mut control_rx = FramedRead::new(rx, codec());
+    let mut control_tx = FramedWrite::new(tx, codec());
+
+    let version = hello_offering(
+        &mut control_tx,
+        &mut control_rx,
+        Hello::offering(LEGACY_VERSION),
+    )
+    .await?;
+    Ok((control_rx, control_tx, version))
 }
 
 pub(crate) fn udp_handle(result: std::io::Result<()>) -> std::io::Result<()> {
@@ -352,17 +798,32 @@ pub(crate) fn udp_handle(result: std::io::Result<()>) -> std::io::Result<()> {
     }
 }
 
+/// Serializes `ping` into `buf`, padding it with zeroes up to `ping_size` bytes.
+fn ping_packet<'a>(
+    buf: &'a mut [u8; MAX_PING_SIZE],
+    ping: &Ping,
+    ping_size: usize,
+) -> Result<&'a [u8], anyhow::Error> {
+    buf.fill(0);
+    let mut cursor = Cursor::new(&mut buf[..]);
+    bincode::serialize_into(&mut cursor, ping)?;
+    let len = (cursor.position() as usize).max(ping_size.min(MAX_PING_SIZE));
+    Ok(&buf[0..len])
+}
+
 async fn ping_measure_send(
     mut index: u64,
     id: u64,
     setup_start: Instant,
-    socket: Arc<UdpSocket>,
+    socket: Arc<PingSocket>,
     samples: u32,
+    interval: Duration,
+    ping_size: usize,
 ) -> Result<(Vec<Duration>, u64), anyhow::Error> {
     let mut storage = Vec::with_capacity(samples as usize);
-    let mut buf = [0; 64];
+    let mut buf = [0; MAX_PING_SIZE];
 
-    let mut interval = time::interval(Duration::from_millis(5));
+    let mut interval = time::interval(interval);
 
     for _ in 0..samples {
         interval.tick().await;
@@ -373,9 +834,7 @@ async fn ping_measure_send(
 
         index += 1;
 
-        let mut cursor = Cursor::new(&mut buf[..]);
-        bincode::serialize_into(&mut cursor, &ping)?;
-        let buf = &cursor.get_ref()[0..(cursor.position() as usize)];
+        let buf = ping_packet(&mut buf, &ping, ping_size)?;
 
         socket.send(buf).await?;
 
@@ -387,15 +846,21 @@ async fn ping_measure_send(
 
 async fn ping_measure_recv(
     setup_start: Instant,
-    socket: Arc<UdpSocket>,
+    socket: Arc<PingSocket>,
     samples: u32,
+    interval: Duration,
+    timeout: Duration,
 ) -> Result<Vec<(Ping, Duration)>, anyhow::Error> {
     let mut storage = Vec::with_capacity(samples as usize);
-    let mut buf = [0; 64];
+    let mut buf = [0; MAX_PING_SIZE];
 
-    let end = time::sleep(Duration::from_millis(5) * samples + Duration::from_millis(1000)).fuse();
+    let end = time::sleep(interval * samples + timeout).fuse();
     pin_mut!(end);
 
+    // Give up early if nothing at all arrives
+    let no_reply = time::sleep(timeout).fuse();
+    pin_mut!(no_reply);
+
     loop {
         let result = {
             let packet = socket.recv(&mut buf).fuse();
@@ -404,15 +869,20 @@ async fn ping_measure_recv(
             select! {
                 result = packet => result,
                 _ = end => break,
+                _ = no_reply => {
+                    if storage.is_empty() {
+                        break;
+                    }
+                    continue;
+                },
             }
         };
 
         let current = setup_start.elapsed();
         let len = result?;
-        let buf = buf
-            .get_mut(..len)
-            .ok_or_else(|| anyhow!("Pong too large"))?;
-        let ping: Ping = bincode::deserialize(buf)?;
+        let Some(ping) = parse_pong(&buf[..len]) else {
+            continue;
+        };
 
         storage.push((ping, current));
     }
@@ -426,17 +896,21 @@ pub struct LatencyResult {
     pub server_pong: Duration,
     pub server_offset: u64,
     pub server_time: u64,
-    pub control_rx: FramedRead<OwnedReadHalf, LengthDelimitedCodec>,
+    pub control_rx: FramedRead<ReadHalf, LengthDelimitedCodec>,
 }
 
 pub(crate) async fn measure_latency(
     id: u64,
     ping_index: &mut u64,
-    mut control_tx: &mut FramedWrite<OwnedWriteHalf, LengthDelimitedCodec>,
-    mut control_rx: FramedRead<OwnedReadHalf, LengthDelimitedCodec>,
+    mut control_tx: &mut FramedWrite<WriteHalf, LengthDelimitedCodec>,
+    mut control_rx: FramedRead<ReadHalf, LengthDelimitedCodec>,
     server: SocketAddr,
-    local_udp: SocketAddr,
+    socket: PingSocket,
     setup_start: Instant,
+    ping_size: usize,
+    samples: u32,
+    interval: Duration,
+    timeout: Duration,
 ) -> Result<LatencyResult, anyhow::Error> {
     send(&mut control_tx, &ClientMessage::GetMeasurements).await?;
 
@@ -449,6 +923,8 @@ pub(crate) async fn measure_latency(
                 ServerMessage::LatencyMeasures(measures) => {
                     latencies.extend(measures.into_iter());
                 }
+                // Overload during the idle latency measurement isn't reported
+                ServerMessage::OverloadDiagnostic(_) => (),
                 ServerMessage::MeasurementsDone { .. } => break,
                 _ => bail!("Unexpected message {:?}", reply),
             };
@@ -457,12 +933,9 @@ pub(crate) async fn measure_latency(
         Ok((latencies, control_rx))
     });
 
-    let udp_socket = Arc::new(net::UdpSocket::bind(local_udp).await?);
-    udp_socket.connect(server).await?;
+    let udp_socket = Arc::new(socket);
     let udp_socket2 = udp_socket.clone();
 
-    let samples = 100;
-
     let ping_start_index = *ping_index;
     let ping_send = tokio::spawn(ping_measure_send(
         ping_start_index,
@@ -470,19 +943,25 @@ pub(crate) async fn measure_latency(
         setup_start,
         udp_socket,
         samples,
+        interval,
+        ping_size,
     ));
 
-    let ping_recv = tokio::spawn(ping_measure_recv(setup_start, udp_socket2, samples));
+    let mut recv = ping_measure_recv(setup_start, udp_socket2, samples, interval, timeout).await?;
 
-    let (sent, recv) = join!(ping_send, ping_recv);
+    if recv.is_empty() {
+        ping_send.abort();
+        return Err(LatencyError::UdpBlocked { server }.into());
+    }
 
-    send(&mut control_tx, &ClientMessage::StopMeasurements).await?;
+    let (sent, new_ping_index) = ping_send.await??;
+    *ping_index = new_ping_index;
 
-    let (mut latencies, control_rx) = latencies.await??;
+    send(&mut control_tx, &ClientMessage::StopMeasurements).await?;
 
-    let (sent, new_ping_index) = sent??;
-    *ping_index = new_ping_index;
-    let mut recv = recv??;
+    let (mut latencies, control_rx) = time::timeout(timeout, latencies)
+        .await
+        .map_err(|_| LatencyError::Timeout)???;
 
     latencies.sort_by_key(|d| d.index);
     recv.sort_by_key(|d| d.0.index);
@@ -507,7 +986,11 @@ pub(crate) async fn measure_latency(
         bail!("Unable to measure latency to server");
     }
     if pings.len() < (samples / 2) as usize {
-        bail!("Unable get enough latency samples from server");
+        return Err(LatencyError::InsufficientSamples {
+            received: pings.len(),
+            required: (samples / 2) as usize,
+        }
+        .into());
     }
 
     pings.sort_by_key(|d| d.1);
@@ -562,21 +1045,32 @@ pub(crate) async fn ping_send(
     id: u64,
     state_rx: watch::Receiver<(TestState, Instant)>,
     setup_start: Instant,
-    socket: Arc<UdpSocket>,
+    socket: Arc<PingSocket>,
     interval: Duration,
     estimated_duration: Duration,
+    ping_size: usize,
+    jitter: Duration,
+    icmp: Option<Echo>,
+    live: Option<Arc<LiveLatency>>,
 ) -> Result<(Vec<Duration>, u64), anyhow::Error> {
     let mut storage = Vec::with_capacity(
         ((estimated_duration.as_secs_f64() + 2.0) * (1000.0 / interval.as_millis() as f64) * 1.5)
             as usize,
     );
-    let mut buf = [0; 64];
+    let mut buf = [0; MAX_PING_SIZE];
+
+    // Keep the jitter well below the interval so pings stay in order.
+    let jitter = jitter.min(interval / 2);
 
     let mut interval = time::interval(interval);
 
     loop {
         interval.tick().await;
 
+        if !jitter.is_zero() {
+            time::sleep(jitter.mul_f64(rand::random::<f64>())).await;
+        }
+
         if state_rx.borrow().0 >= TestState::End {
             break;
         }
@@ -590,12 +1084,21 @@ pub(crate) async fn ping_send(
 
         ping_index += 1;
 
-        let mut cursor = Cursor::new(&mut buf[..]);
-        bincode::serialize_into(&mut cursor, &ping).unwrap();
-        let buf = &cursor.get_ref()[0..(cursor.position() as usize)];
+        let request;
+        let buf = match icmp {
+            Some(echo) => {
+                request = echo.request(ping.index);
+                &request[..]
+            }
+            None => ping_packet(&mut buf, &ping, ping_size).unwrap(),
+        };
 
         udp_handle(socket.send(buf).await.map(|_| ())).context("Unable to send UDP ping packet")?;
 
+        if let Some(live) = &live {
+            live.sent(ping.index, current);
+        }
+
         storage.push(current);
     }
 
@@ -605,15 +1108,17 @@ pub(crate) async fn ping_send(
 pub(crate) async fn ping_recv(
     mut state_rx: watch::Receiver<(TestState, Instant)>,
     setup_start: Instant,
-    socket: Arc<UdpSocket>,
+    socket: Arc<PingSocket>,
     interval: Duration,
     estimated_duration: Duration,
+    icmp: Option<Echo>,
+    live: Option<Arc<LiveLatency>>,
 ) -> Result<Vec<(Ping, Duration)>, anyhow::Error> {
     let mut storage = Vec::with_capacity(
         ((estimated_duration.as_secs_f64() + 2.0) * (1000.0 / interval.as_millis() as f64) * 1.5)
             as usize,
     );
-    let mut buf = [0; 64];
+    let mut buf = [0; MAX_PING_SIZE];
 
     let end = wait_for_state(&mut state_rx, TestState::EndPingRecv).fuse();
     pin_mut!(end);
@@ -631,10 +1136,17 @@ pub(crate) async fn ping_recv(
 
         let current = setup_start.elapsed();
         let len = result?;
-        let buf = buf
-            .get_mut(..len)
-            .ok_or_else(|| anyhow!("Pong too large"))?;
-        let ping: Ping = bincode::deserialize(buf)?;
+        let ping = match icmp {
+            Some(echo) => echo.parse_reply(&buf[..len]),
+            None => parse_pong(&buf[..len]),
+        };
+        let Some(ping) = ping else {
+            continue;
+        };
+
+        if let Some(live) = &live {
+            live.received(ping.index, current);
+        }
 
         storage.push((ping, current));
     }
@@ -642,6 +1154,58 @@ pub(crate) async fn ping_recv(
     Ok(storage)
 }
 
+/// The number of recent pings `LiveLatency` keeps the send time of.
+const LIVE_PINGS: usize = 1000;
+
+/// Tracks the round-trip latency of the latest ping reply while a test runs.
+#[derive(Default)]
+pub(crate) struct LiveLatency {
+    sent: Mutex<VecDeque<(u64, Duration)>>,
+    latest: Mutex<Option<Duration>>,
+}
+
+impl LiveLatency {
+    fn sent(&self, index: u64, time: Duration) {
+        let mut sent = self.sent.lock();
+        if sent.len() >= LIVE_PINGS {
+            sent.pop_front();
+        }
+        sent.push_back((index, time));
+    }
+
+    fn received(&self, index: u64, time: Duration) {
+        let sent = self.sent.lock();
+        let ping = sent
+            .front()
+            .and_then(|&(first, _)| index.checked_sub(first))
+            .and_then(|offset| sent.get(offset as usize));
+        if let Some(&(sent_index, sent_time)) = ping {
+            if sent_index == index {
+                *self.latest.lock() = Some(time.saturating_sub(sent_time));
+            }
+        }
+    }
+
+    #[cfg(feature = "client")]
+    pub(crate) fn latest(&self) -> Option<Duration> {
+        *self.latest.lock()
+    }
+}
+
+/// Parses a pong, returning `None` for malformed packets so they can be skipped.
+pub(crate) fn parse_pong(packet: &[u8]) -> Option<Ping> {
+    match bincode::deserialize(packet) {
+        Ok(ping) => Some(ping),
+        Err(error) => {
+            log::debug!(
+                "Ignoring malformed UDP packet of {} bytes: {error}",
+                packet.len()
+            );
+            None
+        }
+    }
+}
+
 pub(crate) async fn wait_for_state(
     state_rx: &mut watch::Receiver<(TestState, Instant)>,
     state: TestState,
@@ -54,6 +54,7 @@ enum Message {
 }
 
 #[cfg(feature = "client")]
+#[derive(Clone, Debug)]
 pub struct Server {
     pub at: String,
     pub socket: SocketAddr,
@@ -88,6 +89,68 @@ fn interfaces() -> Vec<u32> {
 
 #[cfg(feature = "client")]
 pub async fn locate(peer_server: bool) -> Result<Server, anyhow::Error> {
+    use std::time::Duration;
+
+    let mut server = None;
+    discover(peer_server, Duration::from_secs(1), |found| {
+        server = Some(found);
+        true
+    })
+    .await?;
+
+    server.ok_or_else(|| {
+        if peer_server {
+            anyhow!("Failed to locate local latency peer")
+        } else {
+            anyhow!("Failed to locate local server")
+        }
+    })
+}
+
+/// Lists the servers which reply to discovery within `wait`.
+#[cfg(feature = "client")]
+pub async fn locate_all(
+    peer_server: bool,
+    wait: std::time::Duration,
+) -> Result<Vec<Server>, anyhow::Error> {
+    let mut servers: Vec<Server> = Vec::new();
+    discover(peer_server, wait, |found| {
+        if servers.iter().all(|server| server.socket != found.socket) {
+            servers.push(found);
+        }
+        false
+    })
+    .await?;
+    Ok(servers)
+}
+
+/// Blocking version of `locate_all` for local servers.
+#[cfg(feature = "client")]
+pub fn find_servers(wait: std::time::Duration) -> Result<Vec<Server>, anyhow::Error> {
+    tokio::runtime::Runtime::new()?.block_on(locate_all(false, wait))
+}
+
+#[cfg(feature = "client")]
+impl Server {
+    /// The address to connect to the server with, without the port.
+    pub fn address(&self) -> String {
+        match self.socket {
+            SocketAddr::V6(socket) if socket.scope_id() != 0 => {
+                format!("{}%{}", socket.ip(), socket.scope_id())
+            }
+            socket => socket.ip().to_string(),
+        }
+    }
+}
+
+/// Sends discovery packets and passes replying servers to `found` until it returns `true`
+/// or `wait` elapses.
+#[cfg(feature = "client")]
+async fn discover(
+    peer_server: bool,
+    wait: std::time::Duration,
+    mut found: impl FnMut(Server) -> bool,
+) -> Result<(), anyhow::Error> {
     use crate::{common::fresh_socket_addr, serve::OnDrop};
     use std::{
         net::SocketAddrV6,
@@ -118,7 +181,7 @@ pub async fn locate(peer_server: bool) -> Result<Server, anyhow::Error> {
             if peer != peer_server {
                 bail!("Wrong server kind");
             }
-            if protocol_version != protocol::VERSION {
+            if !(protocol::MIN_VERSION..=protocol::VERSION).contains(&protocol_version) {
                 bail!("Wrong protocol");
             }
             let socket = fresh_socket_addr(src, port);
@@ -203,22 +266,20 @@ pub async fn locate(peer_server: bool) -> Result<Server, anyhow::Error> {
         loop {
             if let Ok((len, src)) = socket.recv_from(&mut buf).await {
                 if let Ok(server) = handle_packet(peer_server, &buf[..len], src) {
-                    return server;
+                    if found(server) {
+                        return;
+                    }
                 }
             }
         }
     };
 
-    timeout(Duration::from_secs(1), find).await.map_err(|_| {
-        if peer_server {
-            anyhow!("Failed to locate local latency peer")
-        } else {
-            anyhow!("Failed to locate local server")
-        }
-    })
+    timeout(wait, find).await.ok();
+
+    Ok(())
 }
 
-pub fn serve(state: Arc<State>, port: u16) -> Result<(), anyhow::Error> {
+pub(crate) fn serve(state: Arc<State>, port: u16) -> Result<(), anyhow::Error> {
     async fn handle_packet(
         port: u16,
         peer_server: bool,
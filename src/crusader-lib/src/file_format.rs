@@ -1,5 +1,6 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::fs::File;
+use std::io::BufRead;
 use std::io::BufReader;
 use std::io::BufWriter;
 use std::io::Read;
@@ -7,6 +8,8 @@ use std::io::Write;
 use std::path::Path;
 use std::time::Duration;
 
+use crate::common::{IDLE_LATENCY_INTERVAL, IDLE_LATENCY_SAMPLES};
+use crate::plot::{group_bytes, to_rates};
 use crate::protocol;
 use crate::protocol::RawLatency;
 
@@ -60,6 +63,19 @@ impl RawConfigV0 {
             grace_duration: Duration::from_secs(self.grace_duration),
             ping_interval: Duration::from_millis(self.ping_interval),
             bandwidth_interval: Duration::from_millis(self.bandwidth_interval),
+            ping_payload_size: 0,
+            incompressible: false,
+            ping_jitter: Duration::ZERO,
+            initial_grace: None,
+            final_grace: None,
+            idle_latency_samples: 0,
+            idle_latency_interval: Duration::ZERO,
+            warmup: None,
+            upload_rate: None,
+            icmp: false,
+            load_bytes: None,
+            total_rate: None,
+            dscp: None,
         }
     }
 }
@@ -78,6 +94,7 @@ impl RawResultV0 {
         RawResult {
             version: 0,
             generated_by: String::new(),
+            server_version: None,
             config: self.config.to_v1(),
             start: self.start,
             server_latency: Duration::from_secs(0),
@@ -86,13 +103,54 @@ impl RawResultV0 {
             stream_groups: self.stream_groups.clone(),
             pings: self.pings.iter().map(|ping| ping.to_v1()).collect(),
             server_overload: false,
+            server_diagnostic: None,
             load_termination_timeout: false,
-            peer_pings: None,
+            peer_pings: Vec::new(),
             test_data: Vec::new(),
+            clock_suspect: false,
+            duplicate_pings: 0,
+            server: String::new(),
+            label: None,
+            coordination: None,
+            interface_counters: None,
+            partial: false,
+            missing_test_details: false,
+            missing_one_way_latency: false,
+            missing_loss_direction: false,
         }
     }
 }
 
+/// The change in the error and drop counters of a local network interface during a test.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InterfaceCounters {
+    pub interface: String,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+}
+
+impl InterfaceCounters {
+    pub fn is_zero(&self) -> bool {
+        self.rx_errors == 0 && self.tx_errors == 0 && self.rx_dropped == 0 && self.tx_dropped == 0
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{} RX errors, {} TX errors, {} RX drops and {} TX drops on interface {}",
+            self.rx_errors, self.tx_errors, self.rx_dropped, self.tx_dropped, self.interface
+        )
+    }
+}
+
+/// A group of clients on the same server which start their load phases together.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Coordination {
+    pub id: u64,
+    pub clients: u32,
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum TestKind {
     Download,
@@ -129,6 +187,40 @@ pub struct RawStream {
 }
 
 impl RawStream {
+    /// Reduces the points to at most `max_points` by splitting them into buckets and keeping
+    /// the last point of each bucket along with the interval with the highest throughput.
+    pub fn downsample(&mut self, max_points: usize) {
+        if self.data.len() <= max_points || max_points < 4 {
+            return;
+        }
+
+        let rate = |i: usize| {
+            let bytes = self.data[i].bytes.saturating_sub(self.data[i - 1].bytes);
+            let time = self.data[i].time.saturating_sub(self.data[i - 1].time);
+            bytes as f64 / time.as_secs_f64().max(1e-6)
+        };
+
+        let intervals = self.data.len() - 1;
+        let buckets = (max_points - 1) / 3;
+        let mut keep = vec![0];
+
+        for bucket in 0..buckets {
+            let start = 1 + bucket * intervals / buckets;
+            let end = 1 + (bucket + 1) * intervals / buckets;
+            let peak = (start..end)
+                .max_by(|&a, &b| rate(a).total_cmp(&rate(b)))
+                .unwrap_or(start);
+
+            for i in [peak - 1, peak, end - 1] {
+                if keep.last() != Some(&i) {
+                    keep.push(i);
+                }
+            }
+        }
+
+        self.data = keep.into_iter().map(|i| self.data[i].clone()).collect();
+    }
+
     pub(crate) fn to_vec(&self) -> Vec<(u64, u64)> {
         self.data
             .iter()
@@ -159,6 +251,33 @@ pub struct RawConfig {
     pub grace_duration: Duration,
     pub ping_interval: Duration,
     pub bandwidth_interval: Duration,
+    #[serde(default)]
+    pub ping_payload_size: u64, // Added in V3
+    #[serde(default)]
+    pub incompressible: bool, // Added in V3
+    #[serde(default)]
+    pub ping_jitter: Duration, // Added in V3
+    #[serde(default)]
+    pub initial_grace: Option<Duration>, // Added in V3
+    #[serde(default)]
+    pub final_grace: Option<Duration>, // Added in V3
+    #[serde(default)]
+    pub idle_latency_samples: u32, // Added in V3
+    #[serde(default)]
+    pub idle_latency_interval: Duration, // Added in V3
+    #[serde(default)]
+    pub warmup: Option<Duration>, // Added in V3
+    #[serde(default)]
+    pub upload_rate: Option<f64>, // Added in V3
+    #[serde(default)]
+    pub icmp: bool, // Added in V3
+    /// Set when each load ended after transferring this many bytes instead of after the load duration.
+    #[serde(default)]
+    pub load_bytes: Option<u64>, // Added in V3
+    #[serde(default)]
+    pub total_rate: Option<f64>, // Added in V3
+    #[serde(default)]
+    pub dscp: Option<u8>, // Added in V3
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq)]
@@ -171,30 +290,78 @@ impl Default for RawHeader {
     fn default() -> Self {
         Self {
             magic: protocol::MAGIC,
-            version: 2,
+            version: 3,
         }
     }
 }
 
+/// The compression used for the data following the header of a raw result.
+/// It's detected when loading, so files using either can be read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Faster, but produces larger files. Used by files from older versions.
+    Snappy,
+    #[default]
+    Gzip,
+    /// The fastest to save, but produces much larger files.
+    None,
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The stream identifier which starts the Snappy frame format.
+const SNAPPY_MAGIC: [u8; 10] = [0xff, 0x06, 0x00, 0x00, b's', b'N', b'a', b'P', b'p', b'Y'];
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct RawResult {
     pub version: u64,
     pub generated_by: String,
+    /// The version of Crusader the server was running.
+    #[serde(default)]
+    pub server_version: Option<String>, // Added in V3
     pub config: RawConfig,
     pub ipv6: bool,
     #[serde(default)]
     pub load_termination_timeout: bool, // Added in V2
     #[serde(default)]
     pub server_overload: bool, // Added in V2
+    #[serde(default)]
+    pub server_diagnostic: Option<String>, // Added in V3
     pub server_latency: Duration,
     pub start: Duration,
     pub duration: Duration,
     pub stream_groups: Vec<RawStreamGroup>,
     pub pings: Vec<RawPing>,
-    #[serde(default)]
-    pub peer_pings: Option<Vec<RawPing>>, // Added in V2
+    /// The pings of each latency peer along with the address of the peer.
+    #[serde(default, deserialize_with = "peer_pings")]
+    pub peer_pings: Vec<(String, Vec<RawPing>)>, // Added in V2
     #[serde(default)] // Added in V2
     pub test_data: Vec<TestData>,
+    #[serde(default)]
+    pub clock_suspect: bool, // Added in V3
+    /// The number of ping replies received more than once.
+    #[serde(default)]
+    pub duplicate_pings: u64, // Added in V3
+    #[serde(default)]
+    pub server: String, // Added in V3
+    #[serde(default)]
+    pub label: Option<String>, // Added in V3
+    #[serde(default)]
+    pub coordination: Option<Coordination>, // Added in V3
+    #[serde(default)]
+    pub interface_counters: Option<InterfaceCounters>, // Added in V3
+    /// Set when the connection to the server was lost before all measurements were received.
+    #[serde(default)]
+    pub partial: bool, // Added in V3
+    /// Set by `migrate` for V0 results, which don't include the IP version and idle latency.
+    #[serde(default)]
+    pub missing_test_details: bool, // Added in V3
+    /// Set by `migrate` for V0 results, which only have the round-trip latency of pings.
+    #[serde(default)]
+    pub missing_one_way_latency: bool, // Added in V3
+    /// Set by `migrate` for results before V2, where a lost ping has no latency at all.
+    #[serde(default)]
+    pub missing_loss_direction: bool, // Added in V3
 }
 
 impl RawResult {
@@ -219,6 +386,15 @@ impl RawResult {
             .any(|group| !group.download && !group.both)
     }
 
+    /// The label of the latency peer at `index`, numbered when there are several.
+    pub fn peer_label(&self, index: usize) -> String {
+        if self.peer_pings.len() > 1 {
+            format!("Peer {}", index + 1)
+        } else {
+            "Peer".to_owned()
+        }
+    }
+
     pub fn idle(&self) -> bool {
         self.stream_groups.is_empty()
     }
@@ -227,7 +403,79 @@ impl RawResult {
         self.stream_groups.iter().any(|group| group.both)
     }
 
+    /// The total bytes transferred by the streams in one direction.
+    pub fn total_bytes(&self, download: bool) -> u64 {
+        self.stream_groups
+            .iter()
+            .filter(|group| group.download == download)
+            .flat_map(|group| &group.streams)
+            .filter_map(|stream| stream.data.last())
+            .map(|point| point.bytes)
+            .sum()
+    }
+
+    /// Whether the test details such as the IP version and idle latency are known.
+    /// V0 results don't include them.
+    pub fn has_test_details(&self) -> bool {
+        !self.missing_test_details
+    }
+
+    /// Whether the pings have separate up and down latencies.
+    /// V0 results and results measured with ICMP only have the round-trip latency.
+    pub fn has_one_way_latency(&self) -> bool {
+        !self.missing_one_way_latency && !self.config.icmp
+    }
+
+    /// Whether lost pings record the direction they were lost in.
+    /// Before V2 and with ICMP a lost ping has no latency at all.
+    pub fn has_loss_direction(&self) -> bool {
+        !self.missing_loss_direction && !self.config.icmp
+    }
+
+    /// Normalizes a result loaded from an older file format version into the current
+    /// version, filling in what older versions didn't store and recording what they lack.
+    pub fn migrate(&mut self) {
+        let current = RawHeader::default().version;
+        if self.version >= current {
+            return;
+        }
+
+        if self.version == 0 {
+            self.missing_test_details = true;
+            self.missing_one_way_latency = true;
+        }
+        if self.version < 2 {
+            self.missing_loss_direction = true;
+        }
+
+        if self.version >= 1 {
+            // The idle latency was measured with the defaults of the time before they were stored
+            self.config.idle_latency_samples = IDLE_LATENCY_SAMPLES;
+            self.config.idle_latency_interval = IDLE_LATENCY_INTERVAL;
+        }
+        // Pings weren't padded before their size was stored
+        self.config.ping_payload_size = protocol::PING_SIZE as u64;
+
+        self.version = current;
+    }
+
+    /// Reduces the throughput points of each stream to at most `max_points`,
+    /// preserving the peak throughput. See `RawStream::downsample`.
+    pub fn downsample(&mut self, max_points: usize) {
+        for group in &mut self.stream_groups {
+            for stream in &mut group.streams {
+                stream.downsample(max_points);
+            }
+        }
+    }
+
     pub fn load_from_reader(reader: impl Read) -> Option<Self> {
+        let mut result = Self::decode(reader)?;
+        result.migrate();
+        Some(result)
+    }
+
+    fn decode(reader: impl Read) -> Option<Self> {
         let mut file = BufReader::new(reader);
         let header: RawHeader = bincode::deserialize_from(&mut file).ok()?;
         if header.magic != RawHeader::default().magic {
@@ -238,32 +486,377 @@ impl RawResult {
                 let result: RawResultV0 = bincode::deserialize_from(file).ok()?;
                 Some(result.to_v1())
             }
-            1 | 2 => {
-                let data = snap::read::FrameDecoder::new(file);
-                Some(rmp_serde::decode::from_read(data).ok()?)
+            1..=3 => {
+                let start = file.fill_buf().ok()?;
+                if start.starts_with(&GZIP_MAGIC) {
+                    let data = flate2::bufread::GzDecoder::new(file);
+                    Some(rmp_serde::decode::from_read(data).ok()?)
+                } else if start.starts_with(&SNAPPY_MAGIC) {
+                    let data = snap::read::FrameDecoder::new(file);
+                    Some(rmp_serde::decode::from_read(data).ok()?)
+                } else {
+                    Some(rmp_serde::decode::from_read(file).ok()?)
+                }
             }
             _ => None,
         }
     }
 
+    /// Loads a result, as JSON if the path has a `.json` extension.
     pub fn load(path: &Path) -> Option<Self> {
-        Self::load_from_reader(File::open(path).ok()?)
+        let file = File::open(path).ok()?;
+        if is_json(path) {
+            let mut result: Self = serde_json::from_reader(BufReader::new(file)).ok()?;
+            result.migrate();
+            Some(result)
+        } else {
+            Self::load_from_reader(file)
+        }
     }
 
     pub fn save_to_writer(&self, writer: impl Write) -> Result<(), anyhow::Error> {
+        self.save_to_writer_with(writer, Compression::default())
+    }
+
+    pub fn save_to_writer_with(
+        &self,
+        writer: impl Write,
+        compression: Compression,
+    ) -> Result<(), anyhow::Error> {
         let mut file = BufWriter::new(writer);
 
         bincode::serialize_into(&mut file, &RawHeader::default())?;
 
-        let mut compressor = snap::write::FrameEncoder::new(file);
-
-        self.serialize(&mut rmp_serde::Serializer::new(&mut compressor).with_struct_map())?;
+        match compression {
+            Compression::Snappy => {
+                let mut compressor = snap::write::FrameEncoder::new(file);
+                self.serialize(&mut rmp_serde::Serializer::new(&mut compressor).with_struct_map())?;
+                compressor.flush()?;
+            }
+            Compression::Gzip => {
+                let mut compressor =
+                    flate2::write::GzEncoder::new(file, flate2::Compression::default());
+                self.serialize(&mut rmp_serde::Serializer::new(&mut compressor).with_struct_map())?;
+                compressor.finish()?.flush()?;
+            }
+            Compression::None => {
+                self.serialize(&mut rmp_serde::Serializer::new(&mut file).with_struct_map())?;
+                file.flush()?;
+            }
+        }
 
-        compressor.flush()?;
         Ok(())
     }
 
+    /// Saves the result, as compact JSON if the path has a `.json` extension.
     pub fn save(&self, name: &Path) -> Result<(), anyhow::Error> {
-        self.save_to_writer(File::create(name)?)
+        self.save_with(name, Compression::default())
+    }
+
+    /// Like `save`, but using the given compression for non-JSON files.
+    pub fn save_with(&self, name: &Path, compression: Compression) -> Result<(), anyhow::Error> {
+        if is_json(name) {
+            let mut file = BufWriter::new(File::create(name)?);
+            serde_json::to_writer(&mut file, self)?;
+            file.flush()?;
+            Ok(())
+        } else {
+            self.save_to_writer_with(File::create(name)?, compression)
+        }
+    }
+
+    pub fn export_pings_csv(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        writeln!(file, "server,sent_ms,index,total_ms,up_ms,down_ms,lost")?;
+
+        let ms = |duration: Option<Duration>| {
+            duration
+                .map(|duration| format!("{}", duration.as_secs_f64() * 1000.0))
+                .unwrap_or_default()
+        };
+
+        for ping in &self.pings {
+            let total = ping.latency.and_then(|latency| latency.total);
+            writeln!(
+                file,
+                "{},{},{},{},{},{},{}",
+                self.server,
+                ping.sent.as_secs_f64() * 1000.0,
+                ping.index,
+                ms(total),
+                ms(ping.latency.map(|latency| latency.up)),
+                ms(ping.latency.and_then(|latency| latency.down())),
+                total.is_none(),
+            )?;
+        }
+
+        file.flush()?;
+        Ok(())
+    }
+
+    pub fn export_throughput_csv(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        writeln!(
+            file,
+            "server,group,download,both,stream_id,time_ms,bytes,Mbps"
+        )?;
+
+        for (group_index, group) in self.stream_groups.iter().enumerate() {
+            for (stream_index, stream) in group.streams.iter().enumerate() {
+                let bytes: Vec<(u64, f64)> = stream
+                    .to_vec()
+                    .into_iter()
+                    .map(|(time, bytes)| (time, bytes as f64))
+                    .collect();
+                let rates = to_rates(&bytes);
+                // Skip the zero point `to_rates` may insert before the first sample
+                let rates = &rates[rates.len().saturating_sub(bytes.len() + 1)..];
+                for (point, &(_, rate)) in stream.data.iter().zip(rates) {
+                    writeln!(
+                        file,
+                        "{},{},{},{},{},{},{},{}",
+                        self.server,
+                        group_index,
+                        group.download,
+                        group.both,
+                        stream_index,
+                        point.time.as_secs_f64() * 1000.0,
+                        point.bytes,
+                        rate,
+                    )?;
+                }
+            }
+
+            // The streams of the group together, as summed for the plots
+            let bytes = group_bytes(group, self.config.bandwidth_interval);
+            let rates = to_rates(&bytes);
+            let rates = &rates[rates.len().saturating_sub(bytes.len() + 1)..];
+            for (&(time, bytes), &(_, rate)) in bytes.iter().zip(rates) {
+                writeln!(
+                    file,
+                    "{},{},{},{},all,{},{},{}",
+                    self.server,
+                    group_index,
+                    group.download,
+                    group.both,
+                    time as f64 / 1000.0,
+                    bytes,
+                    rate,
+                )?;
+            }
+        }
+
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Results used to store the pings of a single unnamed latency peer.
+fn peer_pings<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(String, Vec<RawPing>)>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum PeerPings {
+        Named(Vec<(String, Vec<RawPing>)>),
+        Single(Option<Vec<RawPing>>),
+    }
+
+    Ok(match PeerPings::deserialize(deserializer)? {
+        PeerPings::Named(peers) => peers,
+        PeerPings::Single(pings) => pings
+            .map(|pings| vec![(String::new(), pings)])
+            .unwrap_or_default(),
+    })
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_data(name: &str) -> RawResult {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../data")
+            .join(name);
+        RawResult::load(&path).unwrap()
+    }
+
+    #[test]
+    fn migrate_v0() {
+        let result = load_data("v0.crr");
+        assert_eq!(result.version, RawHeader::default().version);
+        assert!(!result.has_test_details());
+        assert!(!result.has_one_way_latency());
+        assert!(!result.has_loss_direction());
+        assert_eq!(result.config.idle_latency_samples, 0);
+        assert_eq!(result.config.ping_payload_size, protocol::PING_SIZE as u64);
+        assert!(!result.pings.is_empty());
+
+        // What's missing is still known after saving in the current version
+        let mut data = Vec::new();
+        result.save_to_writer(&mut data).unwrap();
+        let result = RawResult::load_from_reader(&data[..]).unwrap();
+        assert!(!result.has_test_details());
+        assert!(!result.has_one_way_latency());
+        assert!(!result.has_loss_direction());
+    }
+
+    #[test]
+    fn migrate_v1() {
+        let result = load_data("v1.crr");
+        assert_eq!(result.version, RawHeader::default().version);
+        assert!(result.has_test_details());
+        assert!(result.has_one_way_latency());
+        assert!(!result.has_loss_direction());
+        assert_eq!(result.config.idle_latency_samples, IDLE_LATENCY_SAMPLES);
+        assert_eq!(result.config.idle_latency_interval, IDLE_LATENCY_INTERVAL);
+        assert_eq!(result.config.ping_payload_size, protocol::PING_SIZE as u64);
+    }
+
+    #[test]
+    fn migrate_v2() {
+        let result = load_data("v2.crr");
+        assert_eq!(result.version, RawHeader::default().version);
+        assert!(result.has_test_details());
+        assert!(result.has_one_way_latency());
+        assert!(result.has_loss_direction());
+        assert_eq!(result.config.idle_latency_samples, IDLE_LATENCY_SAMPLES);
+        assert_eq!(result.config.idle_latency_interval, IDLE_LATENCY_INTERVAL);
+        assert_eq!(result.config.ping_payload_size, protocol::PING_SIZE as u64);
+    }
+
+    #[test]
+    fn single_peer_pings() {
+        // V2 results stored the pings of at most one latency peer, and this one has none
+        let result = load_data("v2.crr");
+        assert!(result.peer_pings.is_empty());
+
+        let mut value = serde_json::to_value(&result).unwrap();
+        value["peer_pings"] = serde_json::to_value(&result.pings).unwrap();
+        let data = rmp_serde::to_vec(&value).unwrap();
+        let result: RawResult = rmp_serde::from_slice(&data).unwrap();
+        assert_eq!(result.peer_pings.len(), 1);
+        assert_eq!(result.peer_pings[0].0, "");
+        assert_eq!(result.peer_pings[0].1.len(), result.pings.len());
+    }
+
+    #[test]
+    fn multiple_peer_pings() {
+        let mut result = load_data("v2.crr");
+        result.peer_pings = vec![
+            ("192.168.1.2:35481".to_owned(), result.pings.clone()),
+            ("192.168.1.3:35481".to_owned(), result.pings[..10].to_vec()),
+        ];
+
+        let mut data = Vec::new();
+        result.save_to_writer(&mut data).unwrap();
+        let result = RawResult::load_from_reader(&data[..]).unwrap();
+        let peers: Vec<_> = result
+            .peer_pings
+            .iter()
+            .map(|(peer, pings)| (peer.as_str(), pings.len()))
+            .collect();
+        assert_eq!(
+            peers,
+            [
+                ("192.168.1.2:35481", result.pings.len()),
+                ("192.168.1.3:35481", 10)
+            ]
+        );
+    }
+
+    #[test]
+    fn throughput_csv_aggregate() {
+        let mut result = load_data("v2.crr");
+        let interval = result.config.bandwidth_interval;
+        let stream = |bytes_per_interval: u64| RawStream {
+            data: (1..=10)
+                .map(|i| RawPoint {
+                    time: interval * i,
+                    bytes: bytes_per_interval * i as u64,
+                })
+                .collect(),
+        };
+        result.stream_groups = vec![RawStreamGroup {
+            download: true,
+            both: false,
+            streams: vec![stream(1000), stream(3000)],
+        }];
+
+        let path = std::env::temp_dir().join(format!("crusader-test-{}.csv", std::process::id()));
+        result.export_throughput_csv(&path).unwrap();
+        let csv = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        // The bytes and rate of each stream by time
+        let mut streams = std::collections::HashMap::<_, Vec<(f64, f64)>>::new();
+        let mut aggregate = Vec::new();
+        for line in csv.unwrap().lines().skip(1) {
+            let fields: Vec<_> = line.split(',').collect();
+            let row = (
+                fields[5].to_owned(),
+                fields[6].parse::<f64>().unwrap(),
+                fields[7].parse::<f64>().unwrap(),
+            );
+            if fields[4] == "all" {
+                aggregate.push(row);
+            } else {
+                streams.entry(row.0).or_default().push((row.1, row.2));
+            }
+        }
+
+        let mut matched = 0;
+        for (time, bytes, rate) in aggregate {
+            if let Some(rows) = streams.get(&time).filter(|rows| rows.len() == 2) {
+                assert_eq!(bytes, rows.iter().map(|row| row.0).sum::<f64>());
+                assert!((rate - rows.iter().map(|row| row.1).sum::<f64>()).abs() < 1e-6);
+                matched += 1;
+            }
+        }
+        assert_eq!(matched, 10);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut result = load_data("v2.crr");
+        result.server_version = Some("0.3.3".to_owned());
+        result.server_diagnostic = Some("Timer lag".to_owned());
+        result.server = "example.com".to_owned();
+        result.label = Some("Wi-Fi".to_owned());
+        result.clock_suspect = true;
+        result.duplicate_pings = 2;
+        result.partial = true;
+        result.coordination = Some(Coordination { id: 7, clients: 3 });
+        result.interface_counters = Some(InterfaceCounters {
+            interface: "eth0".to_owned(),
+            rx_errors: 1,
+            tx_errors: 2,
+            rx_dropped: 3,
+            tx_dropped: 4,
+        });
+        result.config.ping_jitter = Duration::from_millis(3);
+        result.config.initial_grace = Some(Duration::from_secs(2));
+        result.config.warmup = Some(Duration::from_secs(1));
+        result.config.upload_rate = Some(10.0);
+        result.config.load_bytes = Some(1000);
+        result.config.total_rate = Some(20.0);
+        result.config.dscp = Some(46);
+
+        let path = std::env::temp_dir().join(format!("crusader-test-{}.json", std::process::id()));
+        result.save(&path).unwrap();
+        let loaded = RawResult::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(loaded.unwrap()).unwrap(),
+            serde_json::to_value(&result).unwrap()
+        );
     }
 }
@@ -0,0 +1,178 @@
+//! Latency measurement with ICMP echo requests, used when UDP to the server is blocked.
+//!
+//! ICMP only gives the round-trip latency, so it can't be split into up and down latency.
+//! Unprivileged ICMP sockets are used where the OS allows them, which on Linux requires the group
+//! of the user to be within the `net.ipv4.ping_group_range` sysctl. Otherwise a raw socket is used,
+//! which requires root or the `CAP_NET_RAW` capability. ICMP isn't supported on Windows.
+
+#[cfg(feature = "client")]
+use anyhow::bail;
+use std::net::IpAddr;
+#[cfg(feature = "client")]
+use std::time::Duration;
+#[cfg(feature = "client")]
+use tokio::{
+    net::UdpSocket,
+    time::{self, Instant},
+};
+
+use crate::protocol::Ping;
+
+const ECHO_REQUEST_V4: u8 = 8;
+const ECHO_REPLY_V4: u8 = 0;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+
+/// The ICMP header followed by the client id and the ping index.
+const PACKET_SIZE: usize = 8 + 8 + 8;
+
+/// Identifies the echo requests of a client to a server.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Echo {
+    pub server: IpAddr,
+    pub id: u64,
+}
+
+impl Echo {
+    pub(crate) fn request(&self, index: u64) -> [u8; PACKET_SIZE] {
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = if self.server.is_ipv4() {
+            ECHO_REQUEST_V4
+        } else {
+            ECHO_REQUEST_V6
+        };
+        // The kernel may replace the identifier, so the payload identifies the ping instead
+        packet[4..6].copy_from_slice(&(self.id as u16).to_be_bytes());
+        packet[6..8].copy_from_slice(&(index as u16).to_be_bytes());
+        packet[8..16].copy_from_slice(&self.id.to_be_bytes());
+        packet[16..24].copy_from_slice(&index.to_be_bytes());
+
+        // The kernel computes the checksum for ICMPv6
+        if self.server.is_ipv4() {
+            let checksum = checksum(&packet);
+            packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+        }
+
+        packet
+    }
+
+    /// Parses an echo reply, returning `None` for other ICMP packets.
+    pub(crate) fn parse_reply(&self, packet: &[u8]) -> Option<Ping> {
+        // Raw IPv4 sockets receive the IP header too
+        let packet = match packet.first() {
+            Some(&byte) if self.server.is_ipv4() && byte >> 4 == 4 => {
+                packet.get((byte & 0xf) as usize * 4..)?
+            }
+            _ => packet,
+        };
+
+        let reply = if self.server.is_ipv4() {
+            ECHO_REPLY_V4
+        } else {
+            ECHO_REPLY_V6
+        };
+        if packet.len() < PACKET_SIZE || packet[0] != reply {
+            return None;
+        }
+
+        let id = u64::from_be_bytes(packet[8..16].try_into().unwrap());
+        let index = u64::from_be_bytes(packet[16..24].try_into().unwrap());
+        (id == self.id).then_some(Ping { id, index })
+    }
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32)
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Creates an ICMP socket connected to `server`.
+#[cfg(all(feature = "client", unix))]
+pub(crate) fn socket(server: IpAddr) -> Result<UdpSocket, anyhow::Error> {
+    use anyhow::Context;
+    use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+    use std::net::SocketAddr;
+
+    let (domain, protocol) = if server.is_ipv4() {
+        (Domain::IPV4, Protocol::ICMPV4)
+    } else {
+        (Domain::IPV6, Protocol::ICMPV6)
+    };
+
+    let socket = Socket::new(domain, Type::DGRAM, Some(protocol))
+        .or_else(|_| Socket::new(domain, Type::from(libc::SOCK_RAW), Some(protocol)))
+        .context(
+            "Unable to create an ICMP socket. \
+            This requires root or the `CAP_NET_RAW` capability",
+        )?;
+    socket.set_nonblocking(true)?;
+    socket.connect(&SockAddr::from(SocketAddr::new(server, 0)))?;
+
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+#[cfg(all(feature = "client", not(unix)))]
+pub(crate) fn socket(_server: IpAddr) -> Result<UdpSocket, anyhow::Error> {
+    bail!("ICMP latency measurement is unsupported on this platform")
+}
+
+/// Measures the idle round-trip latency to the server, returning the median of the replies.
+#[cfg(feature = "client")]
+pub(crate) async fn idle_latency(
+    echo: Echo,
+    samples: u32,
+    interval: Duration,
+    timeout: Duration,
+) -> Result<Duration, anyhow::Error> {
+    let socket = socket(echo.server)?;
+    let mut buf = [0; 1024];
+    let mut sent = Vec::new();
+    let mut latencies = Vec::new();
+
+    let mut next = Instant::now();
+    let mut deadline = next;
+
+    loop {
+        if sent.len() < samples as usize && Instant::now() >= next {
+            socket.send(&echo.request(sent.len() as u64)).await?;
+            sent.push(Instant::now());
+            next += interval;
+            deadline = Instant::now() + timeout;
+        }
+
+        let wake = if sent.len() < samples as usize {
+            next
+        } else {
+            deadline
+        };
+
+        match time::timeout_at(wake, socket.recv(&mut buf)).await {
+            Ok(result) => {
+                let len = result?;
+                if let Some(ping) = echo.parse_reply(&buf[..len]) {
+                    if let Some(sent) = sent.get(ping.index as usize) {
+                        latencies.push(sent.elapsed());
+                    }
+                }
+                if latencies.len() >= samples as usize {
+                    break;
+                }
+            }
+            Err(_) if sent.len() >= samples as usize => break,
+            Err(_) => (),
+        }
+    }
+
+    if latencies.is_empty() {
+        bail!("No ICMP echo replies from server {}", echo.server);
+    }
+
+    latencies.sort();
+    Ok(latencies[latencies.len() / 2])
+}
@@ -0,0 +1,87 @@
+//! Error and drop counters of a local network interface, so the loss seen in a test can be
+//! told apart from drops at the NIC. Only Linux exposes them, in `/sys/class/net/<name>/statistics`.
+
+use anyhow::bail;
+use std::fmt;
+
+use crate::file_format::InterfaceCounters;
+
+/// The longest interface name Linux allows.
+const MAX_NAME: usize = 15;
+
+/// The name of a network interface. It's stored inline so `Config` stays `Copy`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Interface {
+    name: [u8; MAX_NAME],
+    len: u8,
+}
+
+impl Interface {
+    pub fn new(name: &str) -> Result<Self, anyhow::Error> {
+        if name.is_empty() || name.len() > MAX_NAME || name.contains('/') {
+            bail!("Invalid network interface name `{name}`");
+        }
+        let mut bytes = [0; MAX_NAME];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(Interface {
+            name: bytes,
+            len: name.len() as u8,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        std::str::from_utf8(&self.name[..self.len as usize]).unwrap()
+    }
+}
+
+impl fmt::Debug for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn read(interface: Interface) -> Result<InterfaceCounters, anyhow::Error> {
+    use anyhow::Context;
+    use std::{fs, path::Path};
+
+    let dir = Path::new("/sys/class/net")
+        .join(interface.name())
+        .join("statistics");
+    if !dir.is_dir() {
+        bail!("Network interface `{}` not found", interface.name());
+    }
+    let counter = |name: &str| -> Result<u64, anyhow::Error> {
+        let path = dir.join(name);
+        let value = fs::read_to_string(&path)
+            .with_context(|| format!("Unable to read {}", path.display()))?;
+        value
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid counter in {}", path.display()))
+    };
+
+    Ok(InterfaceCounters {
+        interface: interface.name().to_owned(),
+        rx_errors: counter("rx_errors")?,
+        tx_errors: counter("tx_errors")?,
+        rx_dropped: counter("rx_dropped")?,
+        tx_dropped: counter("tx_dropped")?,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn read(_interface: Interface) -> Result<InterfaceCounters, anyhow::Error> {
+    bail!("Interface counters are only available on Linux")
+}
+
+/// The change in the counters between two reads.
+pub(crate) fn delta(start: &InterfaceCounters, end: &InterfaceCounters) -> InterfaceCounters {
+    InterfaceCounters {
+        interface: end.interface.clone(),
+        rx_errors: end.rx_errors.saturating_sub(start.rx_errors),
+        tx_errors: end.tx_errors.saturating_sub(start.tx_errors),
+        rx_dropped: end.rx_dropped.saturating_sub(start.rx_dropped),
+        tx_dropped: end.tx_dropped.saturating_sub(start.tx_dropped),
+    }
+}
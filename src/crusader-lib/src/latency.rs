@@ -1,38 +1,58 @@
-use anyhow::{anyhow, bail, Context};
-use futures::future::FutureExt;
-use futures::select;
+use anyhow::{bail, Context};
+use futures::future::{FutureExt, Shared};
+use futures::{pin_mut, select, Future};
 use parking_lot::Mutex;
 use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
 use std::{
-    io::Cursor,
+    io::{BufWriter, Cursor, Write},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
 use std::{iter, thread};
 use tokio::net::UdpSocket;
+use tokio::signal;
 use tokio::sync::mpsc::{channel, Sender};
-use tokio::sync::oneshot;
+use tokio::sync::oneshot::{self, error::RecvError};
 use tokio::task;
 use tokio::time::Instant;
 use tokio::{
     net::{self},
     time,
 };
-use tokio_util::codec::{FramedRead, FramedWrite};
 
-use crate::common::{connect, hello, measure_latency, udp_handle, LatencyResult};
-use crate::discovery;
-use crate::protocol::{codec, receive, send, ClientMessage, Ping, ServerMessage};
+use crate::common::{
+    connect, control_hello, measure_latency, parse_pong, udp_handle, LatencyResult,
+    IDLE_LATENCY_INTERVAL, IDLE_LATENCY_SAMPLES, LATENCY_TIMEOUT,
+};
+use crate::protocol::{
+    receive, send, ClientMessage, Ping, ServerMessage, MAX_PING_SIZE, PING_SIZE,
+};
+use crate::transport::{PingSocket, Stream};
+use crate::{discovery, with_time};
 
 type UpdateFn = Arc<dyn Fn() + Send + Sync>;
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Config {
     pub port: u16,
     pub ping_interval: Duration,
+    /// Reconnect after the connection to the server is lost, recording the gap as packet loss.
+    pub reconnect: bool,
+    /// Appends each sample to this CSV file.
+    pub log: Option<PathBuf>,
 }
 
+/// How long to wait before a sample is considered final.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+const LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
 #[derive(Debug, Copy, Clone)]
 pub enum EventKind {
     Sent { sent: Duration },
@@ -63,6 +83,7 @@ pub enum State {
     Connecting,
     Syncing,
     Monitoring { at: String },
+    Reconnecting { error: String, delay: Duration },
 }
 
 pub struct Data {
@@ -89,7 +110,7 @@ async fn test_async(
     config: Config,
     server: Option<&str>,
     data: Arc<Data>,
-    stop: oneshot::Receiver<()>,
+    stop: impl Future<Output = Result<(), RecvError>>,
 ) -> Result<(), anyhow::Error> {
     let (control, at) = if let Some(server) = server {
         (
@@ -108,11 +129,10 @@ async fn test_async(
     *data.state.lock() = State::Syncing;
     (data.update_fn)();
 
-    let (rx, tx) = control.into_split();
-    let mut control_rx = FramedRead::new(rx, codec());
-    let mut control_tx = FramedWrite::new(tx, codec());
-
-    hello(&mut control_tx, &mut control_rx).await?;
+    let (mut control_rx, mut control_tx, _) = control_hello(control.into(), || async {
+        Ok(Stream::from(connect(server, "server").await?))
+    })
+    .await?;
 
     send(&mut control_tx, &ClientMessage::NewClient).await?;
 
@@ -122,6 +142,7 @@ async fn test_async(
     let id = match reply {
         ServerMessage::NewClient(Some(id)) => id,
         ServerMessage::NewClient(None) => bail!("Server was unable to create client"),
+        ServerMessage::Rejected(reason) => bail!("Server rejected the client: {reason}"),
         _ => bail!("Unexpected message {:?}", reply),
     };
 
@@ -144,8 +165,12 @@ async fn test_async(
         &mut control_tx,
         control_rx,
         server,
-        local_udp,
+        PingSocket::udp(local_udp, server).await?,
         setup_start,
+        PING_SIZE,
+        IDLE_LATENCY_SAMPLES,
+        IDLE_LATENCY_INTERVAL,
+        LATENCY_TIMEOUT,
     )
     .await?;
 
@@ -170,6 +195,32 @@ async fn test_async(
 
     let (event_tx, mut event_rx) = channel(1000);
 
+    // Continue the indices of points from a previous connection and mark
+    // the time spent reconnecting as lost pings.
+    let base = {
+        let mut points = data.points.lock().await;
+        if let Some(last) = points.front().cloned() {
+            let gap = setup_start.elapsed().saturating_sub(last.sent);
+            let lost =
+                ((gap.as_secs_f64() / ping_interval.as_secs_f64()) as u64).min(data.limit as u64);
+            for i in 1..lost {
+                points.push_front(Point {
+                    pending: false,
+                    index: last.index + i,
+                    sent: last.sent + ping_interval * i as u32,
+                    up: None,
+                    total: None,
+                    at_server: None,
+                    recv: None,
+                });
+            }
+            while points.len() > data.limit {
+                points.pop_back();
+            }
+        }
+        points.front().map(|point| point.index + 1).unwrap_or(0)
+    };
+
     send(&mut control_tx, &ClientMessage::GetMeasurements).await?;
 
     let event_tx_ = event_tx.clone();
@@ -195,6 +246,7 @@ async fn test_async(
                     overload_ = overload;
                     break;
                 }
+                ServerMessage::OverloadDiagnostic(_) => (),
                 _ => bail!("Unexpected message {:?}", reply),
             };
         }
@@ -202,7 +254,7 @@ async fn test_async(
         Ok(overload_)
     });
 
-    let ping_recv = tokio::spawn(ping_recv(
+    let mut ping_recv = tokio::spawn(ping_recv(
         event_tx.clone(),
         setup_start,
         udp_socket2.clone(),
@@ -213,7 +265,7 @@ async fn test_async(
     *data.state.lock() = State::Monitoring { at };
     (data.update_fn)();
 
-    let ping_send = tokio::spawn(ping_send(
+    let mut ping_send = tokio::spawn(ping_send(
         event_tx.clone(),
         ping_index,
         id,
@@ -258,10 +310,11 @@ async fn test_async(
         while let Some(event) = event_rx.recv().await {
             {
                 let mut points = data.points.lock().await;
+                let index = base + event.ping_index;
                 let i = points
                     .iter()
                     .enumerate()
-                    .find(|r| r.1.index == event.ping_index)
+                    .find(|r| r.1.index == index)
                     .map(|r| r.0);
                 match event.kind {
                     EventKind::Sent { sent } => {
@@ -270,7 +323,7 @@ async fn test_async(
                         }
                         points.push_front(Point {
                             pending: true,
-                            index: event.ping_index,
+                            index,
                             sent,
                             up: None,
                             total: None,
@@ -306,17 +359,28 @@ async fn test_async(
         }
     });
 
-    select! {
-        result = ping_recv.fuse() => {
-            result??;
-        },
-        result = ping_send.fuse() => {
-            result??;
-        },
-        result = stop.fuse() => {
-            result?;
-        },
+    let result = async {
+        select! {
+            result = (&mut ping_recv).fuse() => {
+                result??;
+            },
+            result = (&mut ping_send).fuse() => {
+                result??;
+            },
+            result = stop.fuse() => {
+                result?;
+            },
+        }
+        Ok::<(), anyhow::Error>(())
+    }
+    .await;
+
+    ping_recv.abort();
+    ping_send.abort();
+    if result.is_err() {
+        measures.abort();
     }
+    result?;
 
     send(&mut control_tx, &ClientMessage::StopMeasurements).await?;
     send(&mut control_tx, &ClientMessage::Done).await?;
@@ -326,6 +390,178 @@ async fn test_async(
     Ok(())
 }
 
+/// Calls `f` with the points after `last` which are no longer pending and older than `settle`,
+/// in order.
+fn for_settled(
+    data: &Data,
+    points: &VecDeque<Point>,
+    last: &mut Option<u64>,
+    settle: Duration,
+    mut f: impl FnMut(&Point),
+) {
+    let settled = data.start.elapsed().saturating_sub(settle);
+
+    for point in points.iter().rev() {
+        if last.is_some_and(|last| point.index <= last) {
+            continue;
+        }
+        if point.pending || point.sent > settled {
+            break;
+        }
+        *last = Some(point.index);
+        f(point);
+    }
+}
+
+/// The local time at which a point was sent.
+fn sent_time(data: &Data, point: &Point) -> chrono::DateTime<chrono::Local> {
+    let age = data.start.elapsed().saturating_sub(point.sent);
+    chrono::Local::now() - chrono::Duration::from_std(age).unwrap_or_default()
+}
+
+/// A CSV file which settled samples are appended to.
+struct Log {
+    file: BufWriter<File>,
+    last: Option<u64>,
+}
+
+impl Log {
+    fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Unable to open log file {}", path.display()))?;
+        let empty = file.metadata()?.len() == 0;
+        let mut file = BufWriter::new(file);
+        if empty {
+            writeln!(file, "timestamp,up,down,total,loss")?;
+        }
+        Ok(Self { file, last: None })
+    }
+
+    fn write(
+        &mut self,
+        data: &Data,
+        points: &VecDeque<Point>,
+        settle: Duration,
+    ) -> Result<(), anyhow::Error> {
+        let mut result = Ok(());
+        for_settled(data, points, &mut self.last, settle, |point| {
+            let ms = |duration: Duration| format!("{:.03}", duration.as_secs_f64() * 1000.0);
+            let (up, down, total) = match (point.up, point.total) {
+                (Some(up), Some(total)) => (ms(up), ms(total.saturating_sub(up)), ms(total)),
+                (None, Some(total)) => (String::new(), String::new(), ms(total)),
+                _ => Default::default(),
+            };
+            if result.is_ok() {
+                result = writeln!(
+                    self.file,
+                    "{},{},{},{},{}",
+                    sent_time(data, point).format("%Y-%m-%d %H:%M:%S%.3f"),
+                    up,
+                    down,
+                    total,
+                    point.total.is_none() as u8
+                );
+            }
+        });
+        result?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Writes samples as they settle until `stop` completes.
+    async fn run(
+        mut self,
+        data: Arc<Data>,
+        stop: oneshot::Receiver<()>,
+    ) -> Result<(), anyhow::Error> {
+        let mut interval = time::interval(LOG_INTERVAL);
+        let mut stop = stop.fuse();
+        loop {
+            select! {
+                _ = interval.tick().fuse() => {
+                    let points = data.points.lock().await;
+                    self.write(&data, &points, SETTLE_TIME)?;
+                }
+                _ = stop => break,
+            }
+        }
+
+        // Write the remaining samples which are no longer pending
+        let points = data.points.lock().await;
+        self.write(&data, &points, Duration::ZERO)
+    }
+}
+
+/// Runs `test_async`, reconnecting with a backoff if enabled and the connection is lost
+/// after monitoring started. Samples are logged if configured.
+async fn test_reconnecting(
+    config: Config,
+    server: Option<&str>,
+    data: Arc<Data>,
+    stop: oneshot::Receiver<()>,
+) -> Result<(), anyhow::Error> {
+    let log = config.log.as_deref().map(Log::open).transpose()?;
+
+    let (log_stop_tx, log_stop_rx) = oneshot::channel();
+    let log = log.map(|log| tokio::spawn(log.run(data.clone(), log_stop_rx)));
+
+    let result = reconnecting(config, server, data, stop).await;
+
+    log_stop_tx.send(()).ok();
+    if let Some(log) = log {
+        log.await??;
+    }
+
+    result
+}
+
+async fn reconnecting(
+    config: Config,
+    server: Option<&str>,
+    data: Arc<Data>,
+    stop: oneshot::Receiver<()>,
+) -> Result<(), anyhow::Error> {
+    let stop: Shared<_> = stop.shared();
+    let mut delay = RECONNECT_MIN_DELAY;
+    let mut monitored = false;
+
+    loop {
+        let started = Instant::now();
+        let error = match test_async(config.clone(), server, data.clone(), stop.clone()).await {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+
+        monitored |= matches!(*data.state.lock(), State::Monitoring { .. });
+        if !config.reconnect || !monitored || stop.peek().is_some() {
+            return Err(error);
+        }
+
+        if started.elapsed() > RECONNECT_MAX_DELAY {
+            delay = RECONNECT_MIN_DELAY;
+        }
+
+        *data.state.lock() = State::Reconnecting {
+            error: error.to_string(),
+            delay,
+        };
+        (data.update_fn)();
+
+        select! {
+            _ = time::sleep(delay).fuse() => (),
+            _ = stop.clone().fuse() => return Ok(()),
+        }
+
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+
+        *data.state.lock() = State::Connecting;
+        (data.update_fn)();
+    }
+}
+
 async fn ping_send(
     event_tx: Sender<Event>,
     mut ping_index: u64,
@@ -382,17 +618,16 @@ async fn ping_recv(
     setup_start: Instant,
     socket: Arc<UdpSocket>,
 ) -> Result<Vec<(Ping, Duration)>, anyhow::Error> {
-    let mut buf = [0; 64];
+    let mut buf = [0; MAX_PING_SIZE];
 
     loop {
         let result = socket.recv(&mut buf).await;
 
         let current = setup_start.elapsed();
         let len = result?;
-        let buf = buf
-            .get_mut(..len)
-            .ok_or_else(|| anyhow!("Pong too large"))?;
-        let ping: Ping = bincode::deserialize(buf)?;
+        let Some(ping) = parse_pong(&buf[..len]) else {
+            continue;
+        };
 
         event_tx
             .send(Event {
@@ -425,7 +660,7 @@ pub fn test_callback(
             });
 
             let mut result = task::spawn(async move {
-                test_async(config, host.as_deref(), data, rx)
+                test_reconnecting(config, host.as_deref(), data, rx)
                     .await
                     .map_err(|error| format!("{:?}", error))
             })
@@ -444,3 +679,77 @@ pub fn test_callback(
     });
     stop_tx
 }
+
+/// Monitors latency to a server and prints each sample until interrupted with Ctrl-C.
+pub fn monitor(config: Config, host: Option<&str>, history: Duration) -> Result<(), anyhow::Error> {
+    let limit =
+        ((history.as_secs_f64() / config.ping_interval.as_secs_f64()).round() as usize).max(1);
+    let data = Arc::new(Data::new(limit, Arc::new(|| {})));
+
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async move {
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let mut stop_tx = Some(stop_tx);
+
+        let test = test_reconnecting(config, host, data.clone(), stop_rx).fuse();
+        pin_mut!(test);
+
+        let mut interval = time::interval(Duration::from_millis(100));
+        let mut monitoring = false;
+        let mut printed = None;
+
+        loop {
+            select! {
+                result = test => return result,
+                _ = interval.tick().fuse() => {},
+                result = signal::ctrl_c().fuse() => {
+                    result?;
+                    println!("{}", with_time("Stopping..."));
+                    stop_tx.take().map(|stop| stop.send(()));
+                    continue;
+                },
+            }
+
+            match &*data.state.lock() {
+                State::Monitoring { at } if !monitoring => {
+                    println!("{}", with_time(&format!("Monitoring latency to {}", at)));
+                    monitoring = true;
+                }
+                State::Reconnecting { error, delay } if monitoring => {
+                    println!(
+                        "{}",
+                        with_time(&format!(
+                            "Connection lost, reconnecting in {} s: {}",
+                            delay.as_secs(),
+                            error
+                        ))
+                    );
+                    monitoring = false;
+                }
+                _ => (),
+            }
+
+            // Only print samples which can no longer change
+            let points = data.points.lock().await;
+            for_settled(&data, &points, &mut printed, SETTLE_TIME, |point| {
+                let sample = match point.total {
+                    Some(total) => {
+                        let up = point.up.unwrap_or_default();
+                        format!(
+                            "Latency: {:.02} ms ({:.02} ms down, {:.02} ms up)",
+                            total.as_secs_f64() * 1000.0,
+                            total.saturating_sub(up).as_secs_f64() * 1000.0,
+                            up.as_secs_f64() * 1000.0,
+                        )
+                    }
+                    None => "Latency: lost".to_owned(),
+                };
+                println!(
+                    "[{}] {}",
+                    sent_time(&data, point).format("%Y-%m-%d %H:%M:%S%.3f"),
+                    sample
+                );
+            });
+        }
+    })
+}
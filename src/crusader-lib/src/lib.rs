@@ -26,11 +26,19 @@ pub fn with_time(msg: &str) -> String {
 }
 
 mod common;
-mod discovery;
+pub mod discovery;
 #[cfg(feature = "client")]
-pub use common::Config;
+pub use common::{
+    Config, ConfigBuilder, LatencyError, CONNECT_TIMEOUT, IDLE_LATENCY_INTERVAL,
+    IDLE_LATENCY_SAMPLES, LATENCY_TIMEOUT,
+};
 #[cfg(feature = "client")]
 pub mod file_format;
+mod icmp;
+#[cfg(feature = "client")]
+mod iface;
+#[cfg(feature = "client")]
+pub use iface::Interface;
 #[cfg(feature = "client")]
 pub mod latency;
 mod peer;
@@ -38,7 +46,14 @@ mod peer;
 pub mod plot;
 pub mod protocol;
 #[cfg(feature = "client")]
+mod proxy;
+#[cfg(feature = "client")]
+pub use proxy::{Proxy, ProxyKind};
+#[cfg(feature = "client")]
 pub mod remote;
 pub mod serve;
 #[cfg(feature = "client")]
 pub mod test;
+mod transport;
+#[cfg(feature = "client")]
+mod tui;
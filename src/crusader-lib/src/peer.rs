@@ -5,9 +5,13 @@ use crate::common::{Config, Msg};
 use crate::discovery;
 use crate::protocol::PeerLatency;
 use crate::serve::State;
+use crate::transport::{PingSocket, ReadHalf, Stream, WriteHalf};
 use crate::{
-    common::{hello, measure_latency, ping_recv, ping_send, TestState},
-    protocol::{codec, receive, send, ClientMessage, RawLatency, ServerMessage},
+    common::{
+        control_hello, measure_latency, ping_recv, ping_send, TestState, IDLE_LATENCY_INTERVAL,
+        IDLE_LATENCY_SAMPLES, LATENCY_TIMEOUT,
+    },
+    protocol::{receive, send, ClientMessage, RawLatency, ServerMessage, PING_SIZE},
 };
 use anyhow::{bail, Context};
 use std::{
@@ -15,20 +19,18 @@ use std::{
     sync::Arc,
     time::Duration,
 };
-use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::sync::watch;
+use tokio::time;
 use tokio::time::Instant;
-use tokio::{
-    net::{self},
-    time,
-};
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 
 #[cfg(feature = "client")]
 pub struct Peer {
+    /// The address the peer was given by, or where it was found when located.
+    pub(crate) name: String,
     msg: Msg,
-    tx: FramedWrite<OwnedWriteHalf, LengthDelimitedCodec>,
-    rx: FramedRead<OwnedReadHalf, LengthDelimitedCodec>,
+    tx: FramedWrite<WriteHalf, LengthDelimitedCodec>,
+    rx: FramedRead<ReadHalf, LengthDelimitedCodec>,
 }
 
 #[cfg(feature = "client")]
@@ -40,7 +42,8 @@ impl Peer {
         match reply {
             ServerMessage::PeerReady { server_latency } => {
                 (self.msg)(&format!(
-                    "Peer idle latency to server {:.2} ms",
+                    "Peer {} idle latency to server {:.2} ms",
+                    self.name,
                     Duration::from_nanos(server_latency).as_secs_f64() * 1000.0
                 ));
             }
@@ -80,15 +83,18 @@ pub async fn connect_to_peer(
     estimated_duration: Duration,
     msg: Msg,
 ) -> Result<Peer, anyhow::Error> {
-    let control = if let Some(server) = latency_peer_server {
-        connect((server, config.port), "latency peer").await?
+    let (name, control) = if let Some(server) = latency_peer_server {
+        (
+            server.to_owned(),
+            connect((server, config.port), "latency peer").await?,
+        )
     } else {
         let server = discovery::locate(true).await?;
         msg(&format!(
             "Found peer at {} running version {}",
             server.at, server.software_version
         ));
-        connect(server.socket, "latency peer").await?
+        (server.at, connect(server.socket, "latency peer").await?)
     };
     control.set_nodelay(true)?;
 
@@ -96,11 +102,10 @@ pub async fn connect_to_peer(
 
     msg(&format!("Connected to peer {}", peer_server));
 
-    let (rx, tx) = control.into_split();
-    let mut control_rx = FramedRead::new(rx, codec());
-    let mut control_tx = FramedWrite::new(tx, codec());
-
-    hello(&mut control_tx, &mut control_rx).await?;
+    let (mut control_rx, mut control_tx, _) = control_hello(control.into(), || async {
+        Ok(Stream::from(connect(peer_server, "latency peer").await?))
+    })
+    .await?;
 
     send(
         &mut control_tx,
@@ -126,6 +131,7 @@ pub async fn connect_to_peer(
     };
 
     Ok(Peer {
+        name,
         msg,
         rx: control_rx,
         tx: control_tx,
@@ -137,8 +143,8 @@ pub async fn run_peer(
     server: SocketAddr,
     ping_interval: Duration,
     estimated_duration: Duration,
-    stream_rx: &mut FramedRead<OwnedReadHalf, LengthDelimitedCodec>,
-    stream_tx: &mut FramedWrite<OwnedWriteHalf, LengthDelimitedCodec>,
+    stream_rx: &mut FramedRead<ReadHalf, LengthDelimitedCodec>,
+    stream_tx: &mut FramedWrite<WriteHalf, LengthDelimitedCodec>,
 ) -> Result<(), anyhow::Error> {
     let control = connect(server, "server").await?;
     control.set_nodelay(true)?;
@@ -147,11 +153,10 @@ pub async fn run_peer(
 
     (state.msg)(&format!("Peer connected to server {}", server));
 
-    let (rx, tx) = control.into_split();
-    let mut control_rx = FramedRead::new(rx, codec());
-    let mut control_tx = FramedWrite::new(tx, codec());
-
-    hello(&mut control_tx, &mut control_rx).await?;
+    let (mut control_rx, mut control_tx, _) = control_hello(control.into(), || async {
+        Ok(Stream::from(connect(server, "server").await?))
+    })
+    .await?;
 
     send(&mut control_tx, &ClientMessage::NewClient).await?;
 
@@ -161,6 +166,7 @@ pub async fn run_peer(
     let id = match reply {
         ServerMessage::NewClient(Some(id)) => id,
         ServerMessage::NewClient(None) => bail!("Server was unable to create client"),
+        ServerMessage::Rejected(reason) => bail!("Server rejected the client: {reason}"),
         _ => bail!("Unexpected message {:?}", reply),
     };
 
@@ -186,8 +192,12 @@ pub async fn run_peer(
         &mut control_tx,
         control_rx,
         server,
-        local_udp,
+        PingSocket::udp(local_udp, server).await?,
         setup_start,
+        PING_SIZE,
+        IDLE_LATENCY_SAMPLES,
+        IDLE_LATENCY_INTERVAL,
+        LATENCY_TIMEOUT,
     )
     .await?;
 
@@ -196,8 +206,7 @@ pub async fn run_peer(
         latency.as_secs_f64() * 1000.0
     ));
 
-    let udp_socket = Arc::new(net::UdpSocket::bind(local_udp).await?);
-    udp_socket.connect(server).await?;
+    let udp_socket = Arc::new(PingSocket::udp(local_udp, server).await?);
     let udp_socket2 = udp_socket.clone();
 
     let (state_tx, state_rx) = watch::channel((TestState::Setup, setup_start));
@@ -218,6 +227,7 @@ pub async fn run_peer(
                     overload_ = overload;
                     break;
                 }
+                ServerMessage::OverloadDiagnostic(_) => (),
                 _ => bail!("Unexpected message {:?}", reply),
             };
         }
@@ -248,6 +258,10 @@ pub async fn run_peer(
         udp_socket2.clone(),
         ping_interval,
         estimated_duration,
+        PING_SIZE,
+        Duration::ZERO,
+        None,
+        None,
     ));
 
     let ping_recv = tokio::spawn(ping_recv(
@@ -256,6 +270,8 @@ pub async fn run_peer(
         udp_socket2.clone(),
         ping_interval,
         estimated_duration,
+        None,
+        None,
     ));
 
     send(stream_tx, &ServerMessage::PeerStarted).await?;
@@ -291,8 +307,12 @@ pub async fn run_peer(
         &mut control_tx,
         control_rx,
         server,
-        local_udp,
+        PingSocket::udp(local_udp, server).await?,
         setup_start,
+        PING_SIZE,
+        IDLE_LATENCY_SAMPLES,
+        IDLE_LATENCY_INTERVAL,
+        LATENCY_TIMEOUT,
     )
     .await?;
 
@@ -5,13 +5,17 @@ use plotters::coord::Shift;
 use plotters::prelude::*;
 use plotters::style::text_anchor::{HPos, Pos, VPos};
 use plotters::style::{register_font, RGBColor};
+use serde::Serialize;
 
 use std::collections::{HashMap, HashSet};
+use std::ops::{Range, RangeInclusive};
 use std::path::Path;
 use std::time::Duration;
 use std::{cmp, fmt::Write, mem};
 
-use crate::file_format::{RawPing, RawResult, TestData, TestKind};
+use crate::file_format::{
+    InterfaceCounters, RawPing, RawResult, RawStreamGroup, TestData, TestKind,
+};
 use crate::protocol::RawLatency;
 use crate::test::{unique, PlotConfig};
 
@@ -46,6 +50,7 @@ impl RawResult {
             .map(|group| TestStreamGroup {
                 download: group.download,
                 both: group.both,
+                throughput_cv: throughput_cv(group),
                 streams: (0..(group.streams.len()))
                     .map(|i| {
                         let bytes: Vec<_> = (0..=i)
@@ -60,20 +65,10 @@ impl RawResult {
             })
             .collect();
 
-        let process_bytes = |bytes: Vec<Vec<(u64, u64)>>| -> Vec<(u64, f64)> {
-            let bytes: Vec<_> = bytes.iter().map(|stream| to_float(stream)).collect();
-            let bytes: Vec<_> = bytes.iter().map(|stream| stream.as_slice()).collect();
-            sum_bytes(&bytes, throughput_interval)
-        };
-
         let groups: Vec<_> = self
             .stream_groups
             .iter()
-            .map(|group| {
-                let streams: Vec<_> = group.streams.iter().map(|stream| stream.to_vec()).collect();
-                let single = process_bytes(streams);
-                (group, single)
-            })
+            .map(|group| (group, group_bytes(group, throughput_interval)))
             .collect();
 
         let find = |download, both| {
@@ -122,18 +117,23 @@ impl RawResult {
 
         let mut throughputs = HashMap::new();
 
-        let mut add_throughput =
-            |stream: &Option<Vec<(u64, f64)>>, kind: TestKind, sub: TestKind| {
-                if let Some(stream) = stream {
-                    if let Some(t) = throughput(
-                        stream,
-                        self.test_data.iter().find(|d| d.kind == kind),
-                        self.config.load_duration,
-                    ) {
-                        throughputs.insert((kind, sub), t);
-                    }
+        let mut add_throughput = |stream: &Option<Vec<(u64, f64)>>,
+                                  kind: TestKind,
+                                  sub: TestKind| {
+            if let Some(stream) = stream {
+                let test_data = self.test_data.iter().find(|d| d.kind == kind);
+
+                // Loads limited by size end early, so the time they took is used instead
+                let load_duration = match (self.config.load_bytes, test_data) {
+                    (Some(_), Some(test_data)) => test_data.end.saturating_sub(test_data.start),
+                    _ => self.config.load_duration,
+                };
+
+                if let Some(t) = throughput(stream, test_data, load_duration, self.config.warmup) {
+                    throughputs.insert((kind, sub), t);
                 }
-            };
+            }
+        };
 
         add_throughput(&download_bytes_sum, TestKind::Download, TestKind::Download);
         add_throughput(&upload_bytes_sum, TestKind::Upload, TestKind::Upload);
@@ -153,35 +153,35 @@ impl RawResult {
             TestKind::Bidirectional,
         );
 
-        let add_latency = |map: &mut HashMap<Option<TestKind>, LatencySummary>,
-                           loss: &mut HashMap<Option<TestKind>, (f64, f64)>,
+        let add_latency = |summary: &mut LatencyLossSummary,
                            stream: &Option<Vec<(u64, f64)>>,
                            kind: TestKind,
                            smooth_pings: &[RawPing],
                            pings: &[RawPing]| {
             if let Some(stream) = stream {
-                if let Some(t) = ping_peak(
-                    stream,
-                    self.test_data.iter().find(|d| d.kind == kind),
-                    self.config.load_duration,
-                    smooth_pings,
-                ) {
-                    map.insert(Some(kind), t);
+                let test_data = self.test_data.iter().find(|d| d.kind == kind);
+                let load_duration = self.config.load_duration;
+                if let Some(t) =
+                    phase_pings(stream, test_data, load_duration, smooth_pings).and_then(ping_peak)
+                {
+                    summary.latencies.insert(Some(kind), t);
                 }
-                if let Some(t) = ping_loss(
-                    stream,
-                    self.test_data.iter().find(|d| d.kind == kind),
-                    self.config.load_duration,
-                    pings,
-                ) {
-                    loss.insert(Some(kind), t);
+                if let Some(values) = phase_pings(stream, test_data, load_duration, pings) {
+                    if let Some(t) = ping_loss(values) {
+                        summary.loss.insert(Some(kind), t);
+                    }
+                    if let Some(t) = ping_jitter(values) {
+                        summary.jitter.insert(Some(kind), t);
+                    }
+                    if let Some(t) = percentiles(values) {
+                        summary.percentiles.insert(Some(kind), t);
+                    }
                 }
             }
         };
 
         let latency_map = |pings: &[RawPing]| {
-            let mut latencies = HashMap::new();
-            let mut loss = HashMap::new();
+            let mut summary = LatencyLossSummary::default();
 
             let smooth_pings = smooth_ping(
                 pings,
@@ -189,24 +189,21 @@ impl RawResult {
             );
 
             add_latency(
-                &mut latencies,
-                &mut loss,
+                &mut summary,
                 &download_bytes_sum,
                 TestKind::Download,
                 &smooth_pings,
                 pings,
             );
             add_latency(
-                &mut latencies,
-                &mut loss,
+                &mut summary,
                 &upload_bytes_sum,
                 TestKind::Upload,
                 &smooth_pings,
                 pings,
             );
             add_latency(
-                &mut latencies,
-                &mut loss,
+                &mut summary,
                 &both_bytes,
                 TestKind::Bidirectional,
                 &smooth_pings,
@@ -220,24 +217,34 @@ impl RawResult {
                     end: self.start + self.duration,
                 };
 
-                if let Some(t) = ping_peak(&[], Some(&whole_data), self.duration, &smooth_pings) {
-                    latencies.insert(None, t);
+                if let Some(t) = phase_pings(&[], Some(&whole_data), self.duration, &smooth_pings)
+                    .and_then(ping_peak)
+                {
+                    summary.latencies.insert(None, t);
                 }
 
-                if let Some(t) = ping_loss(&[], Some(&whole_data), self.duration, pings) {
-                    loss.insert(None, t);
+                if let Some(values) = phase_pings(&[], Some(&whole_data), self.duration, pings) {
+                    if let Some(t) = ping_loss(values) {
+                        summary.loss.insert(None, t);
+                    }
+                    if let Some(t) = ping_jitter(values) {
+                        summary.jitter.insert(None, t);
+                    }
+                    if let Some(t) = percentiles(values) {
+                        summary.percentiles.insert(None, t);
+                    }
                 }
             }
 
-            LatencyLossSummary { latencies, loss }
+            summary
         };
 
         let latencies = latency_map(&pings);
         let peer_latencies = self
             .peer_pings
-            .as_ref()
-            .map(|peer_pings| latency_map(peer_pings))
-            .unwrap_or_default();
+            .iter()
+            .map(|(_, peer_pings)| latency_map(peer_pings))
+            .collect();
 
         TestResult {
             raw_result: self.clone(),
@@ -267,6 +274,44 @@ pub struct TestStreamGroup {
     pub download: bool,
     pub both: bool,
     pub streams: Vec<TestStream>,
+    /// Coefficient of variation of the throughput of the individual streams.
+    pub throughput_cv: Option<f64>,
+}
+
+impl TestStreamGroup {
+    pub fn name(&self) -> &'static str {
+        match (self.download, self.both) {
+            (true, false) => "Download",
+            (false, false) => "Upload",
+            (true, true) => "Bidirectional download",
+            (false, true) => "Bidirectional upload",
+        }
+    }
+}
+
+fn throughput_cv(group: &RawStreamGroup) -> Option<f64> {
+    let rates: Vec<f64> = group
+        .streams
+        .iter()
+        .filter_map(|stream| {
+            let first = stream.data.first()?;
+            let last = stream.data.last()?;
+            let duration = last.time.checked_sub(first.time)?.as_secs_f64();
+            (duration > 0.0).then(|| last.bytes.saturating_sub(first.bytes) as f64 / duration)
+        })
+        .collect();
+
+    if rates.len() < 2 {
+        return None;
+    }
+
+    let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+    if mean <= 0.0 {
+        return None;
+    }
+    let variance = rates.iter().map(|rate| (rate - mean).powi(2)).sum::<f64>() / rates.len() as f64;
+
+    Some(variance.sqrt() / mean)
 }
 
 #[derive(Debug)]
@@ -276,10 +321,97 @@ pub struct LatencySummary {
     pub up: Duration,
 }
 
+impl LatencySummary {
+    /// The one-way delay up minus the one-way delay down in milliseconds.
+    /// The clocks are synchronized assuming the idle latency is symmetric,
+    /// so this only shows asymmetry which appears relative to idle.
+    pub fn asymmetry_ms(&self) -> f64 {
+        (self.up.as_secs_f64() - self.down.as_secs_f64()) * 1000.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BufferbloatGrade {
+    APlus,
+    A,
+    B,
+    C,
+    D,
+    F,
+}
+
+impl BufferbloatGrade {
+    /// Grades the increase of latency under load using the thresholds of the Waveform bufferbloat test.
+    pub fn from_increase(increase: Duration) -> Self {
+        let ms = increase.as_secs_f64() * 1000.0;
+        if ms < 5.0 {
+            BufferbloatGrade::APlus
+        } else if ms < 30.0 {
+            BufferbloatGrade::A
+        } else if ms < 60.0 {
+            BufferbloatGrade::B
+        } else if ms < 200.0 {
+            BufferbloatGrade::C
+        } else if ms < 400.0 {
+            BufferbloatGrade::D
+        } else {
+            BufferbloatGrade::F
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BufferbloatGrade::APlus => "A+",
+            BufferbloatGrade::A => "A",
+            BufferbloatGrade::B => "B",
+            BufferbloatGrade::C => "C",
+            BufferbloatGrade::D => "D",
+            BufferbloatGrade::F => "F",
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct LatencyLossSummary {
     pub latencies: HashMap<Option<TestKind>, LatencySummary>,
     pub loss: HashMap<Option<TestKind>, (f64, f64)>,
+    /// The standard deviation of the round-trip latency.
+    pub jitter: HashMap<Option<TestKind>, Duration>,
+    pub percentiles: HashMap<Option<TestKind>, LatencyPercentiles>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LatencyPercentiles {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+/// Computes percentiles of the round-trip latency of `pings`, which are the pings of a single
+/// phase as selected by `phase_pings`. Lost pings are ignored.
+pub fn percentiles(pings: &[RawPing]) -> Option<LatencyPercentiles> {
+    let mut totals: Vec<Duration> = pings
+        .iter()
+        .filter_map(|ping| ping.latency.and_then(|latency| latency.total))
+        .collect();
+
+    if totals.is_empty() {
+        return None;
+    }
+
+    totals.sort_unstable();
+
+    // Uses the nearest-rank method
+    let rank = |percentile: f64| {
+        let index = (percentile / 100.0 * totals.len() as f64).ceil() as usize;
+        totals[index.saturating_sub(1).min(totals.len() - 1)]
+    };
+
+    Some(LatencyPercentiles {
+        p50: rank(50.0),
+        p95: rank(95.0),
+        p99: rank(99.0),
+    })
 }
 
 pub struct TestResult {
@@ -297,7 +429,8 @@ pub struct TestResult {
     pub stream_groups: Vec<TestStreamGroup>,
     pub throughputs: HashMap<(TestKind, TestKind), f64>,
     pub latencies: LatencyLossSummary,
-    pub peer_latencies: LatencyLossSummary,
+    /// One for each latency peer in `raw_result.peer_pings`.
+    pub peer_latencies: Vec<LatencyLossSummary>,
 }
 
 impl TestResult {
@@ -306,6 +439,58 @@ impl TestResult {
 
         let width = 20;
 
+        if self.raw_result.partial {
+            writeln!(
+                &mut o,
+                "Partial result: The connection to the server was lost before all measurements were received\n"
+            )?;
+        }
+
+        if let Some(coordination) = self.raw_result.coordination {
+            writeln!(
+                &mut o,
+                "Coordinated with {} clients in group {}\n",
+                coordination.clients, coordination.id
+            )?;
+        }
+
+        if !self.raw_result.idle() {
+            writeln!(
+                &mut o,
+                "Data transferred: {} down, {} up\n",
+                format_bytes(self.raw_result.total_bytes(true)),
+                format_bytes(self.raw_result.total_bytes(false))
+            )?;
+        }
+
+        if self.raw_result.duplicate_pings > 0 {
+            writeln!(
+                &mut o,
+                "Duplicated pings: {}\n",
+                self.raw_result.duplicate_pings
+            )?;
+        }
+
+        if let Some(counters) = &self.raw_result.interface_counters {
+            writeln!(&mut o, "Interface counters: {}\n", counters.describe())?;
+        }
+
+        let mut peers = false;
+        for (index, (address, _)) in self.raw_result.peer_pings.iter().enumerate() {
+            if !address.is_empty() {
+                peers = true;
+                writeln!(
+                    &mut o,
+                    "{} address: {}",
+                    self.raw_result.peer_label(index),
+                    address
+                )?;
+            }
+        }
+        if peers {
+            writeln!(&mut o)?;
+        }
+
         let mut kind = |kind: Option<TestKind>| -> Result<(), anyhow::Error> {
             writeln!(
                 &mut o,
@@ -340,27 +525,83 @@ impl TestResult {
             }
 
             let mut latency =
-                |latencies: &LatencyLossSummary, peer: bool| -> Result<(), anyhow::Error> {
+                |latencies: &LatencyLossSummary, peer: Option<&str>| -> Result<(), anyhow::Error> {
+                    let label_for = |name: &str| match peer {
+                        Some(peer) => format!("{peer} {}", name.to_lowercase()),
+                        None => name.to_owned(),
+                    };
                     if let Some(latency) = latencies.latencies.get(&kind) {
-                        let label = if peer { "Peer latency" } else { "Latency" };
+                        let label = label_for("Latency");
+                        if peer.is_none() && !self.raw_result.has_one_way_latency() {
+                            writeln!(
+                                &mut o,
+                                "{:>width$}: {:.01} ms",
+                                label,
+                                latency.total.as_secs_f64() * 1000.0,
+                                width = width
+                            )?;
+                        } else {
+                            writeln!(
+                                &mut o,
+                                "{:>width$}: {:.01} ms ({:.01} ms down, {:.01} ms up)",
+                                label,
+                                latency.total.as_secs_f64() * 1000.0,
+                                latency.down.as_secs_f64() * 1000.0,
+                                latency.up.as_secs_f64() * 1000.0,
+                                width = width
+                            )?;
+                            // The asymmetry is relative to idle, so it's only shown for loads
+                            if kind.is_some() {
+                                let label = label_for("Asymmetry");
+                                writeln!(
+                                    &mut o,
+                                    "{:>width$}: {:+.01} ms (up minus down){}",
+                                    label,
+                                    latency.asymmetry_ms(),
+                                    if self.raw_result.clock_suspect {
+                                        ", may be inaccurate due to clock drift"
+                                    } else {
+                                        ""
+                                    },
+                                    width = width
+                                )?;
+                            }
+                        }
+                    }
+                    if let Some(percentiles) = latencies.percentiles.get(&kind) {
+                        let label = label_for("Percentiles");
+                        writeln!(
+                            &mut o,
+                            "{:>width$}: {:.01} ms p50, {:.01} ms p95, {:.01} ms p99",
+                            label,
+                            percentiles.p50.as_secs_f64() * 1000.0,
+                            percentiles.p95.as_secs_f64() * 1000.0,
+                            percentiles.p99.as_secs_f64() * 1000.0,
+                            width = width
+                        )?;
+                    }
+                    if let Some(jitter) = latencies.jitter.get(&kind) {
+                        let label = label_for("Jitter");
                         writeln!(
                             &mut o,
-                            "{:>width$}: {:.01} ms ({:.01} ms down, {:.01} ms up)",
+                            "{:>width$}: {:.01} ms",
                             label,
-                            latency.total.as_secs_f64() * 1000.0,
-                            latency.down.as_secs_f64() * 1000.0,
-                            latency.up.as_secs_f64() * 1000.0,
+                            jitter.as_secs_f64() * 1000.0,
                             width = width
                         )?;
                     }
                     if let Some(&(down, up)) = latencies.loss.get(&kind) {
-                        let label = if peer {
-                            "Peer packet loss"
-                        } else {
-                            "Packet loss"
-                        };
+                        let label = label_for("Packet loss");
                         if down == 0.0 && up == 0.0 {
                             writeln!(&mut o, "{:>width$}: 0%", label)?;
+                        } else if peer.is_none() && !self.raw_result.has_loss_direction() {
+                            writeln!(
+                                &mut o,
+                                "{:>width$}: {:.02}%",
+                                label,
+                                (down + up) * 100.0,
+                                width = width
+                            )?;
                         } else {
                             writeln!(
                                 &mut o,
@@ -378,8 +619,20 @@ impl TestResult {
                     Ok(())
                 };
 
-            latency(&self.latencies, false)?;
-            latency(&self.peer_latencies, true)?;
+            latency(&self.latencies, None)?;
+            for (index, latencies) in self.peer_latencies.iter().enumerate() {
+                latency(latencies, Some(&self.raw_result.peer_label(index)))?;
+            }
+
+            if let Some(grade) = kind.and_then(|kind| self.bufferbloat_grade(kind)) {
+                writeln!(
+                    &mut o,
+                    "{:>width$}: {}",
+                    "Bufferbloat grade",
+                    grade.name(),
+                    width = width
+                )?;
+            }
 
             writeln!(&mut o)?;
 
@@ -404,6 +657,286 @@ impl TestResult {
 
         Ok(o)
     }
+
+    /// Grades the latency increase over the idle latency while the `kind` load was running.
+    pub fn bufferbloat_grade(&self, kind: TestKind) -> Option<BufferbloatGrade> {
+        let latency = self.latencies.latencies.get(&Some(kind))?;
+        Some(BufferbloatGrade::from_increase(
+            latency.total.saturating_sub(self.raw_result.server_latency),
+        ))
+    }
+
+    /// The share of the bidirectional throughput which was download, between 0 and 1.
+    /// A value of 0.5 means the link was split evenly under simultaneous load.
+    pub fn bidirectional_fairness(&self) -> Option<f64> {
+        let down = self
+            .throughputs
+            .get(&(TestKind::Bidirectional, TestKind::Download))?;
+        let up = self
+            .throughputs
+            .get(&(TestKind::Bidirectional, TestKind::Upload))?;
+        let total = down + up;
+        (total > 0.0).then(|| down / total)
+    }
+
+    pub fn json_summary(&self) -> JsonSummary {
+        let latency = |latencies: &LatencyLossSummary, kind: Option<TestKind>| {
+            latencies
+                .latencies
+                .get(&kind)
+                .map(|latency: &LatencySummary| JsonLatency {
+                    total_ms: latency.total.as_secs_f64() * 1000.0,
+                    down_ms: latency.down.as_secs_f64() * 1000.0,
+                    up_ms: latency.up.as_secs_f64() * 1000.0,
+                    asymmetry_ms: kind.is_some().then(|| latency.asymmetry_ms()),
+                    jitter_ms: latencies
+                        .jitter
+                        .get(&kind)
+                        .map(|jitter| jitter.as_secs_f64() * 1000.0),
+                    percentiles_ms: latencies.percentiles.get(&kind).map(|percentiles| {
+                        JsonPercentiles {
+                            p50: percentiles.p50.as_secs_f64() * 1000.0,
+                            p95: percentiles.p95.as_secs_f64() * 1000.0,
+                            p99: percentiles.p99.as_secs_f64() * 1000.0,
+                        }
+                    }),
+                })
+        };
+
+        let loss = |latencies: &LatencyLossSummary, kind| {
+            latencies
+                .loss
+                .get(&kind)
+                .map(|&(down, up)| JsonLoss { down, up })
+        };
+
+        let throughput = |kind: Option<TestKind>, sub: TestKind| {
+            kind.and_then(|kind| self.throughputs.get(&(kind, sub)).copied())
+        };
+
+        let phase = |kind: Option<TestKind>| JsonPhase {
+            throughput_mbps: kind.and_then(|kind| throughput(Some(kind), kind)),
+            download_throughput_mbps: (kind == Some(TestKind::Bidirectional))
+                .then(|| throughput(kind, TestKind::Download))
+                .flatten(),
+            upload_throughput_mbps: (kind == Some(TestKind::Bidirectional))
+                .then(|| throughput(kind, TestKind::Upload))
+                .flatten(),
+            latency: latency(&self.latencies, kind),
+            packet_loss: loss(&self.latencies, kind),
+            peer_latency: self
+                .peer_latencies
+                .first()
+                .and_then(|latencies| latency(latencies, kind)),
+            peer_packet_loss: self
+                .peer_latencies
+                .first()
+                .and_then(|latencies| loss(latencies, kind)),
+            peers: self
+                .raw_result
+                .peer_pings
+                .iter()
+                .zip(&self.peer_latencies)
+                .map(|((address, _), latencies)| JsonPeer {
+                    address: address.clone(),
+                    latency: latency(latencies, kind),
+                    packet_loss: loss(latencies, kind),
+                })
+                .collect(),
+            bufferbloat_grade: kind
+                .and_then(|kind| self.bufferbloat_grade(kind))
+                .map(|grade| grade.name().to_owned()),
+        };
+
+        JsonSummary {
+            generated_by: self.raw_result.generated_by.clone(),
+            server_version: self.raw_result.server_version.clone(),
+            server: (!self.raw_result.server.is_empty()).then(|| self.raw_result.server.clone()),
+            idle_latency_ms: self.raw_result.server_latency.as_secs_f64() * 1000.0,
+            downloaded_bytes: self.raw_result.total_bytes(true),
+            uploaded_bytes: self.raw_result.total_bytes(false),
+            duplicate_pings: self.raw_result.duplicate_pings,
+            interface_counters: self.raw_result.interface_counters.clone(),
+            partial: self.raw_result.partial,
+            download: self
+                .raw_result
+                .download()
+                .then(|| phase(Some(TestKind::Download))),
+            upload: self
+                .raw_result
+                .upload()
+                .then(|| phase(Some(TestKind::Upload))),
+            bidirectional: self
+                .raw_result
+                .both()
+                .then(|| phase(Some(TestKind::Bidirectional))),
+            idle: self.raw_result.idle().then(|| phase(None)),
+        }
+    }
+
+    /// Returns a description of each threshold the result doesn't meet.
+    pub fn threshold_violations(&self, thresholds: &Thresholds) -> Vec<String> {
+        let summary = self.json_summary();
+        let mut violations = Vec::new();
+
+        let phases = [
+            ("Download", &summary.download),
+            ("Upload", &summary.upload),
+            ("Bidirectional", &summary.bidirectional),
+            ("Idle", &summary.idle),
+        ];
+
+        for (name, phase) in phases {
+            let Some(phase) = phase else { continue };
+
+            if let (Some(max), Some(latency)) = (thresholds.max_latency, &phase.latency) {
+                if latency.total_ms > max {
+                    violations.push(format!(
+                        "{name} test latency of {:.1} ms exceeds the maximum of {max} ms",
+                        latency.total_ms
+                    ));
+                }
+            }
+
+            if let (Some(max), Some(loss)) = (thresholds.max_loss, &phase.packet_loss) {
+                let loss = loss.down.max(loss.up) * 100.0;
+                if loss > max {
+                    violations.push(format!(
+                        "{name} test packet loss of {loss:.2}% exceeds the maximum of {max}%"
+                    ));
+                }
+            }
+        }
+
+        // Fall back to the bidirectional test if the single direction test wasn't run
+        let throughput = |phase: &Option<JsonPhase>, both: Option<f64>| {
+            phase
+                .as_ref()
+                .and_then(|phase| phase.throughput_mbps)
+                .or(both)
+        };
+        let both = summary.bidirectional.as_ref();
+        let download = throughput(
+            &summary.download,
+            both.and_then(|both| both.download_throughput_mbps),
+        );
+        let upload = throughput(
+            &summary.upload,
+            both.and_then(|both| both.upload_throughput_mbps),
+        );
+
+        for (name, min, throughput) in [
+            ("Download", thresholds.min_download, download),
+            ("Upload", thresholds.min_upload, upload),
+        ] {
+            match (min, throughput) {
+                (Some(min), Some(throughput)) if throughput < min => violations.push(format!(
+                    "{name} throughput of {throughput:.2} Mbps is below the minimum of {min} Mbps"
+                )),
+                (Some(_), None) => violations.push(format!("{name} throughput was not measured")),
+                _ => (),
+            }
+        }
+
+        violations
+    }
+}
+
+/// Limits checked by [`TestResult::threshold_violations`]. Latency is in milliseconds,
+/// packet loss in percent and throughput in Mbps.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Thresholds {
+    pub max_latency: Option<f64>,
+    pub max_loss: Option<f64>,
+    pub min_download: Option<f64>,
+    pub min_upload: Option<f64>,
+}
+
+/// Machine readable summary of a test result. Throughput is in Mbps,
+/// latency in milliseconds and packet loss is a fraction between 0 and 1.
+/// Values which weren't measured are `null`.
+#[derive(Serialize)]
+pub struct JsonSummary {
+    pub generated_by: String,
+    pub server_version: Option<String>,
+    pub server: Option<String>,
+    pub idle_latency_ms: f64,
+    pub downloaded_bytes: u64,
+    pub uploaded_bytes: u64,
+    pub duplicate_pings: u64,
+    pub interface_counters: Option<InterfaceCounters>,
+    /// Set when the connection to the server was lost before all measurements were received.
+    pub partial: bool,
+    pub download: Option<JsonPhase>,
+    pub upload: Option<JsonPhase>,
+    pub bidirectional: Option<JsonPhase>,
+    pub idle: Option<JsonPhase>,
+}
+
+#[derive(Serialize)]
+pub struct JsonPhase {
+    pub throughput_mbps: Option<f64>,
+    /// Only present for the bidirectional test.
+    pub download_throughput_mbps: Option<f64>,
+    pub upload_throughput_mbps: Option<f64>,
+    pub latency: Option<JsonLatency>,
+    pub packet_loss: Option<JsonLoss>,
+    /// Of the first latency peer, kept for compatibility. See `peers` for all of them.
+    pub peer_latency: Option<JsonLatency>,
+    pub peer_packet_loss: Option<JsonLoss>,
+    pub peers: Vec<JsonPeer>,
+    pub bufferbloat_grade: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct JsonPeer {
+    /// Empty for results from older versions.
+    pub address: String,
+    pub latency: Option<JsonLatency>,
+    pub packet_loss: Option<JsonLoss>,
+}
+
+#[derive(Serialize)]
+pub struct JsonLatency {
+    pub total_ms: f64,
+    pub down_ms: f64,
+    pub up_ms: f64,
+    /// Up minus down. Only set for the load tests as it's relative to idle.
+    pub asymmetry_ms: Option<f64>,
+    pub jitter_ms: Option<f64>,
+    pub percentiles_ms: Option<JsonPercentiles>,
+}
+
+#[derive(Serialize)]
+pub struct JsonPercentiles {
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+#[derive(Serialize)]
+pub struct JsonLoss {
+    pub down: f64,
+    pub up: f64,
+}
+
+/// Formats a byte count using decimal units, like `1.23 GB`.
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["kB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = "bytes";
+    for next in units {
+        if value < 1000.0 {
+            break;
+        }
+        value /= 1000.0;
+        unit = next;
+    }
+    if unit == "bytes" {
+        format!("{} {}", bytes, unit)
+    } else {
+        format!("{:.2} {}", value, unit)
+    }
 }
 
 pub fn save_graph(
@@ -418,14 +951,23 @@ pub fn save_graph(
     Ok(file)
 }
 
+/// Saves the plot as SVG if `path` has a `.svg` extension and as PNG otherwise.
 pub fn save_graph_to_path(
     path: &Path,
     config: &PlotConfig,
     result: &TestResult,
 ) -> Result<(), anyhow::Error> {
-    let img = save_graph_to_mem(config, result).context("Unable to plot")?;
-    img.save_with_format(&path, ImageFormat::Png)
-        .context("Unable to write plot to file")
+    let svg = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+    if svg {
+        let svg = save_graph_to_svg(config, result).context("Unable to plot")?;
+        std::fs::write(path, svg).context("Unable to write plot to file")
+    } else {
+        let img = save_graph_to_mem(config, result).context("Unable to plot")?;
+        img.save_with_format(&path, ImageFormat::Png)
+            .context("Unable to write plot to file")
+    }
 }
 
 pub(crate) struct ThroughputPlot<'a> {
@@ -443,6 +985,31 @@ pub(crate) fn save_graph_to_mem(
     config: &PlotConfig,
     result: &TestResult,
 ) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>, anyhow::Error> {
+    graph(
+        config,
+        result,
+        &result.pings,
+        &throughput_plots(result),
+        result.start.as_secs_f64(),
+        result.duration.as_secs_f64(),
+    )
+}
+
+pub fn save_graph_to_svg(
+    config: &PlotConfig,
+    result: &TestResult,
+) -> Result<String, anyhow::Error> {
+    graph_svg(
+        config,
+        result,
+        &result.pings,
+        &throughput_plots(result),
+        result.start.as_secs_f64(),
+        result.duration.as_secs_f64(),
+    )
+}
+
+fn throughput_plots(result: &TestResult) -> Vec<ThroughputPlot<'_>> {
     let mut throughput = Vec::new();
 
     let smooth_interval = cmp::min(
@@ -456,7 +1023,7 @@ pub(crate) fn save_graph_to_mem(
             name: "Download",
             color: DOWN_COLOR,
             rates: to_rates(bytes),
-            smooth: smooth(bytes, interval, smooth_interval),
+            smooth: smooth(bytes, interval, SmoothingMode::Window(smooth_interval)),
             bytes: vec![bytes.as_slice()],
             rate: result
                 .throughputs
@@ -472,7 +1039,7 @@ pub(crate) fn save_graph_to_mem(
             name: "Upload",
             color: UP_COLOR,
             rates: to_rates(bytes),
-            smooth: smooth(bytes, interval, smooth_interval),
+            smooth: smooth(bytes, interval, SmoothingMode::Window(smooth_interval)),
             bytes: vec![bytes.as_slice()],
             rate: result
                 .throughputs
@@ -488,7 +1055,7 @@ pub(crate) fn save_graph_to_mem(
             name: "Download",
             color: DOWN_COLOR,
             rates: to_rates(bytes),
-            smooth: smooth(bytes, interval, smooth_interval),
+            smooth: smooth(bytes, interval, SmoothingMode::Window(smooth_interval)),
             bytes: vec![bytes.as_slice()],
             rate: None,
             dual_rates: None,
@@ -501,7 +1068,7 @@ pub(crate) fn save_graph_to_mem(
             name: "Upload",
             color: UP_COLOR,
             rates: to_rates(bytes),
-            smooth: smooth(bytes, interval, smooth_interval),
+            smooth: smooth(bytes, interval, SmoothingMode::Window(smooth_interval)),
             bytes: vec![bytes.as_slice()],
             rate: None,
             dual_rates: None,
@@ -514,7 +1081,7 @@ pub(crate) fn save_graph_to_mem(
             name: "Aggregate",
             color: RGBColor(149, 96, 153),
             rates: to_rates(both_bytes),
-            smooth: smooth(both_bytes, interval, smooth_interval),
+            smooth: smooth(both_bytes, interval, SmoothingMode::Window(smooth_interval)),
             bytes: vec![both_bytes.as_slice()],
             rate: result
                 .throughputs
@@ -535,30 +1102,50 @@ pub(crate) fn save_graph_to_mem(
         });
     });
 
-    graph(
-        config,
-        result,
-        &result.pings,
-        &throughput,
-        result.start.as_secs_f64(),
-        result.duration.as_secs_f64(),
-    )
+    throughput
 }
 
+/// The maximum of the values for use as a plot bound. Non-finite values are ignored and 100 is
+/// used if there are no positive values, so the bound never degenerates to an empty range.
 pub fn float_max(iter: impl Iterator<Item = f64>) -> f64 {
-    let mut max = iter.fold(f64::NAN, f64::max);
+    let max = iter
+        .filter(|value| value.is_finite())
+        .fold(f64::NAN, f64::max);
 
-    if max.is_nan() {
-        max = 100.0;
+    if max > 0.0 {
+        max
+    } else {
+        100.0
     }
-
-    max
 }
 
+/// The shortest time range plotted, so very short tests still get a usable time axis.
+pub const MIN_PLOT_DURATION: f64 = 1.0;
+
 fn to_float(stream: &[(u64, u64)]) -> Vec<(u64, f64)> {
     stream.iter().map(|(t, v)| (*t, *v as f64)).collect()
 }
 
+/// Returns the bytes transferred by each stream of `group`, sampled at `interval`.
+pub fn stream_bytes(group: &RawStreamGroup, interval: Duration) -> Vec<Vec<(u64, f64)>> {
+    group
+        .streams
+        .iter()
+        .map(|stream| sum_bytes(&[&to_float(&stream.to_vec())], interval))
+        .collect()
+}
+
+/// Returns the bytes transferred by all the streams of `group` together, sampled at `interval`.
+pub fn group_bytes(group: &RawStreamGroup, interval: Duration) -> Vec<(u64, f64)> {
+    let bytes: Vec<_> = group
+        .streams
+        .iter()
+        .map(|stream| to_float(&stream.to_vec()))
+        .collect();
+    let bytes: Vec<_> = bytes.iter().map(|stream| stream.as_slice()).collect();
+    sum_bytes(&bytes, interval)
+}
+
 pub fn to_rates(stream: &[(u64, f64)]) -> Vec<(u64, f64)> {
     let mut result: Vec<(u64, f64)> = (0..stream.len())
         .map(|i| {
@@ -566,7 +1153,12 @@ pub fn to_rates(stream: &[(u64, f64)]) -> Vec<(u64, f64)> {
                 let bytes = stream[i].1 - stream[i - 1].1;
                 let duration = Duration::from_micros(stream[i].0 - stream[i - 1].0);
                 let mbits = (bytes * 8.0) / (1000.0 * 1000.0);
-                mbits / duration.as_secs_f64()
+                // Samples at the same time have no rate
+                if duration.is_zero() {
+                    0.0
+                } else {
+                    mbits / duration.as_secs_f64()
+                }
             } else {
                 0.0
             };
@@ -585,17 +1177,25 @@ pub fn to_rates(stream: &[(u64, f64)]) -> Vec<(u64, f64)> {
     result
 }
 
+/// The offset into a load at which the average throughput stops being measured.
+pub(crate) fn throughput_end(load_duration: Duration) -> Duration {
+    load_duration - cmp::min(load_duration / 10, Duration::from_millis(500))
+}
+
 fn throughput(
     stream: &[(u64, f64)],
     test_data: Option<&TestData>,
     load_duration: Duration,
+    warmup: Option<Duration>,
 ) -> Option<f64> {
     if stream.is_empty() {
         return None;
     }
 
-    let start_offset = (load_duration.as_secs_f64() * 0.2).min(2.0);
-    let end_offset = load_duration.as_secs_f64() - (load_duration.as_secs_f64() * 0.1).min(0.5);
+    let start_offset = warmup
+        .map(|warmup| warmup.as_secs_f64())
+        .unwrap_or((load_duration.as_secs_f64() * 0.2).min(2.0));
+    let end_offset = throughput_end(load_duration).as_secs_f64();
 
     let test_start = if let Some(test_data) = test_data {
         test_data.start
@@ -633,12 +1233,13 @@ fn throughput(
     Some(mbits / duration)
 }
 
-fn ping_peak(
+/// Returns the pings sent while the load described by `stream` or `test_data` was running.
+fn phase_pings<'a>(
     stream: &[(u64, f64)],
     test_data: Option<&TestData>,
     load_duration: Duration,
-    pings: &[RawPing],
-) -> Option<LatencySummary> {
+    pings: &'a [RawPing],
+) -> Option<&'a [RawPing]> {
     if pings.is_empty() {
         return None;
     }
@@ -662,8 +1263,10 @@ fn ping_peak(
 
     let start = pings.partition_point(|p| (p.sent.as_micros() as u64) < start);
     let end = pings.partition_point(|p| (p.sent.as_micros() as u64) <= end);
-    let values = pings.get(start..end)?;
+    pings.get(start..end)
+}
 
+fn ping_peak(values: &[RawPing]) -> Option<LatencySummary> {
     let point = values
         .iter()
         .max_by_key(|v| v.latency.unwrap().total.unwrap())?;
@@ -675,37 +1278,7 @@ fn ping_peak(
     })
 }
 
-fn ping_loss(
-    stream: &[(u64, f64)],
-    test_data: Option<&TestData>,
-    load_duration: Duration,
-    pings: &[RawPing],
-) -> Option<(f64, f64)> {
-    if pings.is_empty() {
-        return None;
-    }
-
-    let test_start = if let Some(test_data) = test_data {
-        test_data.start
-    } else {
-        Duration::from_micros(stream.iter().find(|e| e.1 > 0.0)?.0)
-    };
-    let start = test_start.as_micros() as u64;
-    let end = (test_start + load_duration).as_micros() as u64;
-    let end = if let Some(test_data) = test_data {
-        cmp::min(test_data.end.as_micros() as u64, end)
-    } else {
-        end
-    };
-
-    if start >= end {
-        return None;
-    }
-
-    let start = pings.partition_point(|p| (p.sent.as_micros() as u64) < start);
-    let end = pings.partition_point(|p| (p.sent.as_micros() as u64) <= end);
-    let values = pings.get(start..end)?;
-
+fn ping_loss(values: &[RawPing]) -> Option<(f64, f64)> {
     let loss_up = values.iter().filter(|v| v.latency.is_none()).count();
 
     let loss_down = values
@@ -718,15 +1291,55 @@ fn ping_loss(
     Some(((loss_down as f64) / count, (loss_up as f64) / count))
 }
 
-pub fn smooth(
-    stream: &[(u64, f64)],
-    interval: Duration,
-    smoothing_interval: Duration,
-) -> Vec<(u64, f64)> {
+fn ping_jitter(values: &[RawPing]) -> Option<Duration> {
+    let totals: Vec<f64> = values
+        .iter()
+        .filter_map(|ping| ping.latency.and_then(|latency| latency.total))
+        .map(|total| total.as_secs_f64())
+        .collect();
+
+    if totals.len() < 2 {
+        return None;
+    }
+
+    let count = totals.len() as f64;
+    let mean = totals.iter().sum::<f64>() / count;
+    let variance = totals
+        .iter()
+        .map(|total| (total - mean).powi(2))
+        .sum::<f64>()
+        / count;
+
+    Some(Duration::from_secs_f64(variance.sqrt()))
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SmoothingMode {
+    /// A moving average over a window of the given duration.
+    Window(Duration),
+    /// An exponentially weighted moving average with the given smoothing factor between 0 and 1.
+    Ewma(f64),
+}
+
+pub fn smooth(stream: &[(u64, f64)], interval: Duration, mode: SmoothingMode) -> Vec<(u64, f64)> {
     if stream.is_empty() {
         return Vec::new();
     }
 
+    let smoothing_interval = match mode {
+        SmoothingMode::Window(smoothing_interval) => smoothing_interval,
+        SmoothingMode::Ewma(alpha) => {
+            let mut average = 0.0;
+            return to_rates(stream)
+                .into_iter()
+                .map(|(time, rate)| {
+                    average = alpha * rate + (1.0 - alpha) * average;
+                    (time, average)
+                })
+                .collect();
+        }
+    };
+
     let interval = interval.as_micros() as u64;
     let smoothing_interval = smoothing_interval.as_micros() as u64;
 
@@ -883,11 +1496,11 @@ fn interpolate(input: &[(u64, f64)], interval: u64) -> Vec<(u64, f64)> {
     data
 }
 
-fn draw_centered(
+fn draw_centered<DB: DrawingBackend>(
     x: i32,
     y: i32,
     text: &[(String, RGBColor)],
-    area: &DrawingArea<BitMapBackend<'_>, Shift>,
+    area: &DrawingArea<DB, Shift>,
 ) {
     let small_style: TextStyle = (FontFamily::SansSerif, 14).into();
 
@@ -913,14 +1526,35 @@ fn draw_centered(
     }
 }
 
-fn new_chart<'a, 'c>(
-    duration: f64,
+/// Maps test times to the x-axis of the charts.
+struct TimeAxis {
+    /// The start of the test in seconds.
+    start: f64,
+    /// The range of the x-axis, including space for the legend.
+    range: Range<f64>,
+    /// Only data within this range is drawn, if set.
+    clip: Option<RangeInclusive<f64>>,
+}
+
+impl TimeAxis {
+    /// Returns the position of `time` on the x-axis, if it's drawn.
+    fn x(&self, time: f64) -> Option<f64> {
+        let x = time - self.start;
+        match &self.clip {
+            Some(clip) if !clip.contains(&x) => None,
+            _ => Some(x),
+        }
+    }
+}
+
+fn new_chart<'a, DB: DrawingBackend>(
+    axis: &TimeAxis,
     padding_bottom: Option<i32>,
     max: f64,
     label: &str,
     x_labels: bool,
-    area: &'a DrawingArea<BitMapBackend<'c>, Shift>,
-) -> ChartContext<'a, BitMapBackend<'c>, Cartesian2d<RangedCoordf64, RangedCoordf64>> {
+    area: &'a DrawingArea<DB, Shift>,
+) -> ChartContext<'a, DB, Cartesian2d<RangedCoordf64, RangedCoordf64>> {
     let font = (FontFamily::SansSerif, 16);
 
     let mut chart = ChartBuilder::on(area)
@@ -928,7 +1562,7 @@ fn new_chart<'a, 'c>(
         .set_label_area_size(LabelAreaPosition::Left, 100)
         .set_label_area_size(LabelAreaPosition::Right, 100)
         .set_label_area_size(LabelAreaPosition::Bottom, padding_bottom.unwrap_or(20))
-        .build_cartesian_2d(0.0..duration, 0.0..max)
+        .build_cartesian_2d(axis.range.clone(), 0.0..max)
         .unwrap();
 
     chart
@@ -953,8 +1587,8 @@ fn new_chart<'a, 'c>(
     chart
 }
 
-fn legends<'a, 'b: 'a>(
-    chart: &mut ChartContext<'a, BitMapBackend<'b>, Cartesian2d<RangedCoordf64, RangedCoordf64>>,
+fn legends<'a, DB: DrawingBackend + 'a>(
+    chart: &mut ChartContext<'a, DB, Cartesian2d<RangedCoordf64, RangedCoordf64>>,
 ) {
     let font = (FontFamily::SansSerif, 16);
 
@@ -969,17 +1603,16 @@ fn legends<'a, 'b: 'a>(
 
 const PACKET_LOSS_AREA_SIZE: f64 = 70.0;
 
-fn latency<'a>(
+fn latency<DB: DrawingBackend>(
     config: &PlotConfig,
     result: &TestResult,
     pings: &[RawPing],
     throughputs: &[ThroughputPlot],
     summary: &LatencyLossSummary,
-    start: f64,
-    duration: f64,
-    area: &DrawingArea<BitMapBackend<'a>, Shift>,
-    packet_loss_area: Option<&DrawingArea<BitMapBackend<'a>, Shift>>,
-    peer: bool,
+    axis: &TimeAxis,
+    area: &DrawingArea<DB, Shift>,
+    packet_loss_area: Option<&DrawingArea<DB, Shift>>,
+    peer: Option<&str>,
 ) {
     let new_area;
     let new_packet_loss_area;
@@ -991,6 +1624,9 @@ fn latency<'a>(
         (&new_packet_loss_area, &new_area)
     };
 
+    // Peers always measure with UDP
+    let one_way = peer.is_some() || result.raw_result.has_one_way_latency();
+
     // Draw latency summaries
 
     let small_style: TextStyle = (FontFamily::SansSerif, 14).into();
@@ -1043,17 +1679,19 @@ fn latency<'a>(
                 RGBColor(0, 0, 0),
             ));
 
-            text.push((
-                format!("  ({:.01} ", latency.down.as_secs_f64() * 1000.0),
-                RGBColor(0, 0, 0),
-            ));
-            text.push(("down".to_owned(), darken(DOWN_COLOR, 0.5)));
-            text.push((
-                format!(", {:.01} ", latency.up.as_secs_f64() * 1000.0),
-                RGBColor(0, 0, 0),
-            ));
-            text.push(("up".to_owned(), darken(UP_COLOR, 0.5)));
-            text.push((")".to_owned(), RGBColor(0, 0, 0)));
+            if one_way {
+                text.push((
+                    format!("  ({:.01} ", latency.down.as_secs_f64() * 1000.0),
+                    RGBColor(0, 0, 0),
+                ));
+                text.push(("down".to_owned(), darken(DOWN_COLOR, 0.5)));
+                text.push((
+                    format!(", {:.01} ", latency.up.as_secs_f64() * 1000.0),
+                    RGBColor(0, 0, 0),
+                ));
+                text.push(("up".to_owned(), darken(UP_COLOR, 0.5)));
+                text.push((")".to_owned(), RGBColor(0, 0, 0)));
+            }
 
             let x = side as f64 + width * (i as f64) + width / 2.0;
 
@@ -1121,14 +1759,12 @@ fn latency<'a>(
     }
 
     let mut chart = new_chart(
-        duration,
+        axis,
         None,
         max_latency,
-        if peer {
-            "Peer latency (ms)"
-        } else {
-            "Latency (ms)"
-        },
+        &peer.map_or("Latency (ms)".to_owned(), |peer| {
+            format!("{peer} latency (ms)")
+        }),
         true,
         &area,
     );
@@ -1157,10 +1793,11 @@ fn latency<'a>(
                 match &ping.latency {
                     Some(latency) => match get_latency(latency) {
                         Some(latency) => {
-                            let x = ping.sent.as_secs_f64() - start;
-                            let y = latency.as_secs_f64() * 1000.0;
+                            if let Some(x) = axis.x(ping.sent.as_secs_f64()) {
+                                let y = latency.as_secs_f64() * 1000.0;
 
-                            data.push((x, y));
+                                data.push((x, y));
+                            }
                         }
                         None => {
                             flush(&mut data);
@@ -1183,9 +1820,11 @@ fn latency<'a>(
                 });
         };
 
-    draw_latency(UP_COLOR, "Up", |latency| Some(latency.up));
+    if one_way {
+        draw_latency(UP_COLOR, "Up", |latency| Some(latency.up));
 
-    draw_latency(DOWN_COLOR, "Down", |latency| latency.down());
+        draw_latency(DOWN_COLOR, "Down", |latency| latency.down());
+    }
 
     draw_latency(RGBColor(50, 50, 50), "Round-trip", |latency| latency.total);
 
@@ -1194,19 +1833,21 @@ fn latency<'a>(
     // Packet loss
 
     let chart = new_chart(
-        duration,
+        axis,
         Some(30),
         1.0,
-        if peer { "Peer loss" } else { "Packet loss" },
+        &peer.map_or("Packet loss".to_owned(), |peer| format!("{peer} loss")),
         false,
         &packet_loss_area,
     );
 
     for ping in pings {
-        let x = ping.sent.as_secs_f64() - start;
+        let Some(x) = axis.x(ping.sent.as_secs_f64()) else {
+            continue;
+        };
         if ping.latency.and_then(|latency| latency.total).is_none() {
             let bold_size = 0.1111;
-            let (color, s, e, bold) = if result.raw_result.version >= 2 {
+            let (color, s, e, bold) = if result.raw_result.has_loss_direction() {
                 if ping.latency.is_none() {
                     (UP_COLOR, 0.0, 0.5, Some(0.0 + bold_size))
                 } else {
@@ -1233,17 +1874,19 @@ fn latency<'a>(
 
     chart
         .plotting_area()
-        .draw(&PathElement::new(vec![(0.0, 1.0), (duration, 1.0)], BLACK))
+        .draw(&PathElement::new(
+            vec![(axis.range.start, 1.0), (axis.range.end, 1.0)],
+            BLACK,
+        ))
         .unwrap();
 }
 
-fn plot_split_throughput(
+fn plot_split_throughput<DB: DrawingBackend>(
     config: &PlotConfig,
     download: bool,
     result: &TestResult,
-    start: f64,
-    duration: f64,
-    area: &DrawingArea<BitMapBackend, Shift>,
+    axis: &TimeAxis,
+    area: &DrawingArea<DB, Shift>,
 ) {
     let groups: Vec<_> = result
         .stream_groups
@@ -1252,6 +1895,7 @@ fn plot_split_throughput(
         .map(|group| TestStreamGroup {
             download,
             both: group.both,
+            throughput_cv: group.throughput_cv,
             streams: group
                 .streams
                 .iter()
@@ -1278,7 +1922,7 @@ fn plot_split_throughput(
     }
 
     let mut chart = new_chart(
-        duration,
+        axis,
         None,
         max_throughput,
         if download {
@@ -1316,8 +1960,8 @@ fn plot_split_throughput(
             };
             chart
                 .draw_series(LineSeries::new(
-                    group.streams[i].data.iter().map(|(time, rate)| {
-                        (Duration::from_micros(*time).as_secs_f64() - start, *rate)
+                    group.streams[i].data.iter().filter_map(|(time, rate)| {
+                        Some((axis.x(Duration::from_micros(*time).as_secs_f64())?, *rate))
                     }),
                     color,
                 ))
@@ -1326,12 +1970,11 @@ fn plot_split_throughput(
     }
 }
 
-fn plot_throughput(
+fn plot_throughput<DB: DrawingBackend>(
     config: &PlotConfig,
     throughputs: &[ThroughputPlot],
-    start: f64,
-    duration: f64,
-    area: &DrawingArea<BitMapBackend<'_>, Shift>,
+    axis: &TimeAxis,
+    area: &DrawingArea<DB, Shift>,
 ) {
     let max_throughput = float_max(
         throughputs
@@ -1384,21 +2027,14 @@ fn plot_throughput(
         }
     }
 
-    let mut chart = new_chart(
-        duration,
-        None,
-        max_throughput,
-        "Throughput (Mbps)",
-        true,
-        &area,
-    );
+    let mut chart = new_chart(axis, None, max_throughput, "Throughput (Mbps)", true, &area);
 
     let mut seen = HashSet::new();
     for throughput in throughputs {
         let series = chart
             .draw_series(LineSeries::new(
-                throughput.rates.iter().map(|(time, rate)| {
-                    (Duration::from_micros(*time).as_secs_f64() - start, *rate)
+                throughput.rates.iter().filter_map(|(time, rate)| {
+                    Some((axis.x(Duration::from_micros(*time).as_secs_f64())?, *rate))
                 }),
                 throughput.color,
             ))
@@ -1413,8 +2049,8 @@ fn plot_throughput(
     for throughput in throughputs {
         chart
             .draw_series(LineSeries::new(
-                throughput.smooth.iter().map(|(time, rate)| {
-                    (Duration::from_micros(*time).as_secs_f64() - start, *rate)
+                throughput.smooth.iter().filter_map(|(time, rate)| {
+                    Some((axis.x(Duration::from_micros(*time).as_secs_f64())?, *rate))
                 }),
                 ShapeStyle {
                     color: darken(throughput.color, 0.5).mix(0.5),
@@ -1428,11 +2064,10 @@ fn plot_throughput(
     legends(&mut chart);
 }
 
-pub(crate) fn bytes_transferred(
+fn bytes_transferred<DB: DrawingBackend>(
     throughputs: &[ThroughputPlot],
-    start: f64,
-    duration: f64,
-    area: &DrawingArea<BitMapBackend, Shift>,
+    axis: &TimeAxis,
+    area: &DrawingArea<DB, Shift>,
 ) {
     let max_bytes = float_max(
         throughputs
@@ -1447,7 +2082,7 @@ pub(crate) fn bytes_transferred(
     let max_bytes = max_bytes * 1.05;
 
     let mut chart = new_chart(
-        duration,
+        axis,
         Some(50),
         max_bytes,
         "Data transferred (GiB)",
@@ -1460,11 +2095,11 @@ pub(crate) fn bytes_transferred(
         for (i, bytes) in throughput.bytes.iter().enumerate() {
             let series = chart
                 .draw_series(LineSeries::new(
-                    bytes.iter().map(|(time, bytes)| {
-                        (
-                            Duration::from_micros(*time).as_secs_f64() - start,
+                    bytes.iter().filter_map(|(time, bytes)| {
+                        Some((
+                            axis.x(Duration::from_micros(*time).as_secs_f64())?,
                             *bytes / (1024.0 * 1024.0 * 1024.0),
-                        )
+                        ))
                     }),
                     &throughput.color,
                 ))
@@ -1481,6 +2116,22 @@ pub(crate) fn bytes_transferred(
     legends(&mut chart);
 }
 
+fn graph_size(config: &PlotConfig, result: &TestResult) -> (u32, u32) {
+    let width = config.width.unwrap_or(1280) as u32;
+
+    let peers = result.raw_result.peer_pings.len() as u64;
+
+    let mut def_height = 720 + peers * 380;
+
+    if config.transferred {
+        def_height += 320;
+    }
+
+    let height = config.height.unwrap_or(def_height) as u32;
+
+    (width, height)
+}
+
 pub(crate) fn graph(
     config: &PlotConfig,
     result: &TestResult,
@@ -1489,23 +2140,50 @@ pub(crate) fn graph(
     start: f64,
     duration: f64,
 ) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>, anyhow::Error> {
-    let width = config.width.unwrap_or(1280) as u32;
-
-    let peer_latency = result.raw_result.peer_pings.is_some();
+    let (width, height) = graph_size(config, result);
 
-    let mut def_height = 720;
+    let mut data = vec![0; 3 * (width as usize * height as usize)];
 
-    if peer_latency {
-        def_height += 380;
+    {
+        let root = BitMapBackend::with_buffer(&mut data, (width, height)).into_drawing_area();
+        draw_graph(root, config, result, pings, throughput, start, duration)?;
     }
 
-    if config.transferred {
-        def_height += 320;
+    ImageBuffer::from_raw(width, height, data).ok_or(anyhow!("Failed to create image"))
+}
+
+pub(crate) fn graph_svg(
+    config: &PlotConfig,
+    result: &TestResult,
+    pings: &[RawPing],
+    throughput: &[ThroughputPlot],
+    start: f64,
+    duration: f64,
+) -> Result<String, anyhow::Error> {
+    let (width, height) = graph_size(config, result);
+
+    let mut svg = String::new();
+
+    {
+        let root = SVGBackend::with_string(&mut svg, (width, height)).into_drawing_area();
+        draw_graph(root, config, result, pings, throughput, start, duration)?;
     }
 
-    let height = config.height.unwrap_or(def_height) as u32;
+    Ok(svg)
+}
 
-    let mut data = vec![0; 3 * (width as usize * height as usize)];
+fn draw_graph<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    config: &PlotConfig,
+    result: &TestResult,
+    pings: &[RawPing],
+    throughput: &[ThroughputPlot],
+    start: f64,
+    duration: f64,
+) -> Result<(), anyhow::Error> {
+    let width = root.dim_in_pixel().0;
+
+    let peer_latency = !result.raw_result.peer_pings.is_empty();
 
     let idle = result.raw_result.idle();
 
@@ -1515,81 +2193,102 @@ pub(crate) fn graph(
         "Latency under load"
     });
 
-    {
-        let root = BitMapBackend::with_buffer(&mut data, (width, height)).into_drawing_area();
+    root.fill(&WHITE).unwrap();
 
-        root.fill(&WHITE).unwrap();
+    let style: TextStyle = (FontFamily::SansSerif, 26).into();
 
-        let style: TextStyle = (FontFamily::SansSerif, 26).into();
+    let medium_style: TextStyle = (FontFamily::SansSerif, 16).into();
 
-        let medium_style: TextStyle = (FontFamily::SansSerif, 16).into();
+    let small_style: TextStyle = (FontFamily::SansSerif, 14).into();
 
-        let small_style: TextStyle = (FontFamily::SansSerif, 14).into();
+    let lines = 2;
 
-        let lines = 2;
+    let text_height = (root.estimate_text_size("Wg", &small_style).unwrap().1 as i32 + 5) * lines;
 
-        let text_height =
-            (root.estimate_text_size("Wg", &small_style).unwrap().1 as i32 + 5) * lines;
+    let center = text_height / 2 + 10;
 
-        let center = text_height / 2 + 10;
+    root.draw_text(
+        title,
+        &style.pos(Pos::new(HPos::Center, VPos::Center)),
+        (width as i32 / 2, center),
+    )
+    .unwrap();
 
+    if result.raw_result.has_test_details() {
+        let top_margin = 10;
         root.draw_text(
-            title,
-            &style.pos(Pos::new(HPos::Center, VPos::Center)),
-            (width as i32 / 2, center),
+            &format!(
+                "Connections: {} over IPv{}",
+                result.raw_result.streams(),
+                if result.raw_result.ipv6 { 6 } else { 4 },
+            ),
+            &small_style.pos(Pos::new(HPos::Left, VPos::Top)),
+            (100, top_margin + text_height / lines),
         )
         .unwrap();
 
-        if result.raw_result.version >= 1 {
-            let top_margin = 10;
-            root.draw_text(
-                &format!(
-                    "Connections: {} over IPv{}",
-                    result.raw_result.streams(),
-                    if result.raw_result.ipv6 { 6 } else { 4 },
-                ),
-                &small_style.pos(Pos::new(HPos::Left, VPos::Top)),
-                (100, top_margin + text_height / lines),
-            )
-            .unwrap();
+        root.draw_text(
+            &format!(
+                "Stagger: {} s",
+                result.raw_result.config.stagger.as_secs_f64(),
+            ),
+            &small_style.pos(Pos::new(HPos::Left, VPos::Top)),
+            (100 + 180, top_margin + text_height / lines),
+        )
+        .unwrap();
 
-            root.draw_text(
-                &format!(
-                    "Stagger: {} s",
-                    result.raw_result.config.stagger.as_secs_f64(),
-                ),
-                &small_style.pos(Pos::new(HPos::Left, VPos::Top)),
-                (100 + 180, top_margin + text_height / lines),
-            )
-            .unwrap();
+        root.draw_text(
+            &if idle {
+                // The idle test only runs the initial grace
+                let config = &result.raw_result.config;
+                format!(
+                    "Idle duration: {:.2} s",
+                    config
+                        .initial_grace
+                        .unwrap_or(config.grace_duration)
+                        .as_secs_f64(),
+                )
+            } else if let Some(bytes) = result.raw_result.config.load_bytes {
+                format!("Load size: {:.2} MB", bytes as f64 / 1000.0 / 1000.0)
+            } else {
+                format!(
+                    "Load duration: {:.2} s",
+                    result.raw_result.config.load_duration.as_secs_f64(),
+                )
+            },
+            &small_style.pos(Pos::new(HPos::Left, VPos::Top)),
+            (100, top_margin),
+        )
+        .unwrap();
+
+        root.draw_text(
+            &format!(
+                "Server latency: {:.2} ms",
+                result.raw_result.server_latency.as_secs_f64() * 1000.0,
+            ),
+            &small_style.pos(Pos::new(HPos::Left, VPos::Top)),
+            (100 + 180, top_margin),
+        )
+        .unwrap();
 
+        if let Some(coordination) = result.raw_result.coordination {
             root.draw_text(
-                &if idle {
-                    format!(
-                        "Grace duration: {:.2} s",
-                        result.raw_result.config.grace_duration.as_secs_f64(),
-                    )
-                } else {
-                    format!(
-                        "Load duration: {:.2} s",
-                        result.raw_result.config.load_duration.as_secs_f64(),
-                    )
-                },
-                &small_style.pos(Pos::new(HPos::Left, VPos::Top)),
-                (100, top_margin),
+                &result.raw_result.generated_by,
+                &small_style.pos(Pos::new(HPos::Right, VPos::Top)),
+                (width as i32 - 100, top_margin),
             )
             .unwrap();
 
             root.draw_text(
                 &format!(
-                    "Server latency: {:.2} ms",
-                    result.raw_result.server_latency.as_secs_f64() * 1000.0,
+                    "Group {}: {} clients",
+                    coordination.id, coordination.clients
                 ),
-                &small_style.pos(Pos::new(HPos::Left, VPos::Top)),
-                (100 + 180, top_margin),
+                &small_style.pos(Pos::new(HPos::Right, VPos::Top)),
+                (width as i32 - 100, top_margin + text_height / lines),
             )
             .unwrap();
-
+        } else {
             root.draw_text(
                 &result.raw_result.generated_by,
                 &small_style.pos(Pos::new(HPos::Right, VPos::Center)),
@@ -1597,127 +2296,127 @@ pub(crate) fn graph(
             )
             .unwrap();
         }
+    }
 
-        let (root, textarea) = root.split_vertically(root.dim_in_pixel().1 - 24);
+    let (root, textarea) = root.split_vertically(root.dim_in_pixel().1 - 24);
 
-        textarea
-            .draw_text(
-                "Elapsed time (seconds)",
-                &medium_style.pos(Pos::new(HPos::Center, VPos::Center)),
-                ((width as i32) / 2, 12),
-            )
-            .unwrap();
+    textarea
+        .draw_text(
+            "Elapsed time (seconds)",
+            &medium_style.pos(Pos::new(HPos::Center, VPos::Center)),
+            ((width as i32) / 2, 12),
+        )
+        .unwrap();
 
-        let mut root = root.split_vertically(text_height + 10).1;
+    let mut root = root.split_vertically(text_height + 10).1;
 
-        let loss = if !peer_latency {
-            let loss;
-            (root, loss) =
-                root.split_vertically(root.relative_to_height(1.0) - PACKET_LOSS_AREA_SIZE);
-            Some(loss)
-        } else {
-            None
-        };
+    let loss = if !peer_latency {
+        let loss;
+        (root, loss) = root.split_vertically(root.relative_to_height(1.0) - PACKET_LOSS_AREA_SIZE);
+        Some(loss)
+    } else {
+        None
+    };
 
-        let mut charts = 1;
+    let mut charts = 1;
 
-        if peer_latency {
-            charts += 1;
-        }
+    charts += result.raw_result.peer_pings.len();
 
-        if result.raw_result.streams() > 0 {
-            if config.split_throughput {
-                if result.raw_result.download() || result.raw_result.both() {
-                    charts += 1
-                }
-                if result.raw_result.upload() || result.raw_result.both() {
-                    charts += 1
-                }
-            } else {
+    if result.raw_result.streams() > 0 {
+        if config.split_throughput {
+            if result.raw_result.download() || result.raw_result.both() {
                 charts += 1
             }
-            if config.transferred {
+            if result.raw_result.upload() || result.raw_result.both() {
                 charts += 1
             }
+        } else {
+            charts += 1
+        }
+        if config.transferred {
+            charts += 1
         }
+    }
 
-        let areas = root.split_evenly((charts, 1));
+    let areas = root.split_evenly((charts, 1));
 
+    let clip = config
+        .time_range
+        .filter(|(from, to)| from < to)
+        .map(|(from, to)| from..=to);
+    let (from, to) = clip
+        .as_ref()
+        .map_or((0.0, duration.max(MIN_PLOT_DURATION)), |clip| {
+            (*clip.start(), *clip.end())
+        });
+    let axis = TimeAxis {
+        start,
         // Scale to fit the legend
-        let duration = duration * 1.12;
-
-        let mut chart_index = 0;
-
-        if result.raw_result.streams() > 0 {
-            if config.split_throughput {
-                if result.raw_result.download() || result.raw_result.both() {
-                    plot_split_throughput(
-                        config,
-                        true,
-                        result,
-                        start,
-                        duration,
-                        &areas[chart_index],
-                    );
-                    chart_index += 1;
-                }
-                if result.raw_result.upload() || result.raw_result.both() {
-                    plot_split_throughput(
-                        config,
-                        false,
-                        result,
-                        start,
-                        duration,
-                        &areas[chart_index],
-                    );
-                    chart_index += 1;
-                }
-            } else {
-                plot_throughput(config, throughput, start, duration, &areas[chart_index]);
+        range: from..(from + (to - from) * 1.12),
+        clip,
+    };
+
+    let mut chart_index = 0;
+
+    if result.raw_result.streams() > 0 {
+        if config.split_throughput {
+            if result.raw_result.download() || result.raw_result.both() {
+                plot_split_throughput(config, true, result, &axis, &areas[chart_index]);
                 chart_index += 1;
             }
+            if result.raw_result.upload() || result.raw_result.both() {
+                plot_split_throughput(config, false, result, &axis, &areas[chart_index]);
+                chart_index += 1;
+            }
+        } else {
+            plot_throughput(config, throughput, &axis, &areas[chart_index]);
+            chart_index += 1;
         }
+    }
+
+    latency(
+        config,
+        result,
+        pings,
+        throughput,
+        &result.latencies,
+        &axis,
+        &areas[chart_index],
+        loss.as_ref(),
+        None,
+    );
+    chart_index += 1;
 
+    for (index, ((_, peer_pings), latencies)) in result
+        .raw_result
+        .peer_pings
+        .iter()
+        .zip(&result.peer_latencies)
+        .enumerate()
+    {
         latency(
             config,
             result,
-            pings,
+            peer_pings,
             throughput,
-            &result.latencies,
-            start,
-            duration,
+            latencies,
+            &axis,
             &areas[chart_index],
-            loss.as_ref(),
-            false,
+            None,
+            Some(&result.raw_result.peer_label(index)),
         );
         chart_index += 1;
+    }
 
-        if let Some(peer_pings) = result.raw_result.peer_pings.as_ref() {
-            latency(
-                config,
-                result,
-                peer_pings,
-                throughput,
-                &result.peer_latencies,
-                start,
-                duration,
-                &areas[chart_index],
-                None,
-                true,
-            );
+    if result.raw_result.streams() > 0 && config.transferred {
+        bytes_transferred(throughput, &axis, &areas[chart_index]);
+        #[allow(unused_assignments)]
+        {
             chart_index += 1;
         }
-
-        if result.raw_result.streams() > 0 && config.transferred {
-            bytes_transferred(throughput, start, duration, &areas[chart_index]);
-            #[allow(unused_assignments)]
-            {
-                chart_index += 1;
-            }
-        }
-
-        root.present().map_err(|_| anyhow!("Unable to plot"))?;
     }
 
-    ImageBuffer::from_raw(width, height, data).ok_or(anyhow!("Failed to create image"))
+    root.present().map_err(|_| anyhow!("Unable to plot"))?;
+
+    Ok(())
 }
@@ -1,4 +1,4 @@
-use anyhow::Context;
+use anyhow::{anyhow, bail, Context};
 use bytes::{Bytes, BytesMut};
 use futures::{Sink, SinkExt, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
@@ -21,8 +21,18 @@ impl RawLatency {
 pub const PORT: u16 = 35481;
 
 pub const MAGIC: u64 = 0x5372ab82ae7c59cb;
-pub const VERSION: u64 = 3;
+pub const VERSION: u64 = 5;
 
+// The oldest protocol version which is still supported.
+pub const MIN_VERSION: u64 = 3;
+
+// The version of servers released before the negotiation.
+pub const LEGACY_VERSION: u64 = 3;
+
+// The client sends the newest version it supports and the server replies with the version
+// that will be used. The server must support at least `MIN_VERSION`. Servers released before
+// this negotiation reply with `LEGACY_VERSION` and only accept clients using exactly it,
+// so the client reconnects offering that version instead.
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
 pub struct Hello {
     magic: u64,
@@ -31,11 +41,60 @@ pub struct Hello {
 
 impl Hello {
     pub fn new() -> Self {
+        Hello::offering(VERSION)
+    }
+
+    /// Returns a client hello offering protocol versions up to `version`.
+    pub fn offering(version: u64) -> Self {
         Hello {
             magic: MAGIC,
-            version: VERSION,
+            version,
         }
     }
+
+    /// Returns the hello a server should reply with to this client hello,
+    /// along with the negotiated protocol version.
+    pub fn server_reply(&self) -> (Hello, Result<u64, anyhow::Error>) {
+        let version = self.version.min(VERSION);
+        if self.magic != MAGIC {
+            (Hello::new(), Err(anyhow!("Invalid hello {:?}", self)))
+        } else if version < MIN_VERSION {
+            (
+                Hello::new(),
+                Err(anyhow!(
+                    "Unsupported protocol version {}, expected {} to {}",
+                    self.version,
+                    MIN_VERSION,
+                    VERSION
+                )),
+            )
+        } else {
+            (
+                Hello {
+                    magic: MAGIC,
+                    version,
+                },
+                Ok(version),
+            )
+        }
+    }
+
+    /// Checks a hello received from a server and returns the protocol version to use.
+    pub fn negotiate(&self) -> Result<u64, anyhow::Error> {
+        if self.magic != MAGIC {
+            bail!("Invalid server hello {:?}", self);
+        }
+        if !(MIN_VERSION..=VERSION).contains(&self.version) {
+            bail!(
+                "Server uses protocol version {}, but this client supports versions {} to {}. \
+                Make sure the client and server run compatible versions of Crusader",
+                self.version,
+                MIN_VERSION,
+                VERSION
+            );
+        }
+        Ok(self.version)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
@@ -90,6 +149,24 @@ pub enum ServerMessage {
         overload: bool,
         latencies: Vec<PeerLatency>,
     },
+    // Added in protocol version 4
+    OverloadDiagnostic(String),
+    // Added in protocol version 4
+    Rejected(String),
+    // Added in protocol version 4
+    Status {
+        clients: u64,
+        throughput_mbps: f64,
+    },
+    // Added in protocol version 4
+    Coordinated {
+        clients: u32,
+    },
+    // Added in protocol version 5
+    ServerInfo {
+        version: String,
+        uptime: u64, // In seconds
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -126,6 +203,20 @@ pub enum ClientMessage {
     },
     PeerStart,
     PeerStop,
+    // Added in protocol version 4
+    Abort,
+    // Added in protocol version 4
+    Status,
+    // Added in protocol version 4
+    Coordinate {
+        id: u64,
+        clients: u32,
+    },
+    // Added in protocol version 5
+    ServerInfo,
+    // Added in protocol version 5. Echoes the pings of a client on this connection, for
+    // connections over a Unix domain socket which have no UDP.
+    Pings(u64),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -134,6 +225,12 @@ pub struct Ping {
     pub index: u64,
 }
 
+// The size of an unpadded `Ping` packet.
+pub const PING_SIZE: usize = 16;
+
+// The largest `Ping` packet, including padding, that will be sent or received.
+pub const MAX_PING_SIZE: usize = 1400;
+
 pub fn codec() -> LengthDelimitedCodec {
     length_delimited::Builder::new()
         .little_endian()
@@ -0,0 +1,141 @@
+use anyhow::{anyhow, bail, Context};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+use crate::common::connect;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ProxyKind {
+    /// An HTTP proxy supporting the `CONNECT` method.
+    Http,
+    Socks5,
+}
+
+/// A proxy which TCP connections to the server are tunneled through.
+/// UDP can't pass through it, so latency can't be measured.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    pub address: SocketAddr,
+}
+
+impl Proxy {
+    /// Parses a proxy URL of the form `http://host:port` or `socks5://host:port`.
+    pub fn parse(url: &str) -> Result<Self, anyhow::Error> {
+        let (scheme, rest) = url
+            .split_once("://")
+            .ok_or(anyhow!("Expected a proxy URL like `socks5://host:port`"))?;
+        let kind = match scheme.to_ascii_lowercase().as_str() {
+            "http" => ProxyKind::Http,
+            "socks5" => ProxyKind::Socks5,
+            _ => bail!("Unsupported proxy scheme `{scheme}`. Use `http` or `socks5`"),
+        };
+        let host = rest.trim_end_matches('/');
+        let address = host
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve proxy {host}"))?
+            .next()
+            .ok_or_else(|| anyhow!("No address found for proxy {host}"))?;
+        Ok(Proxy { kind, address })
+    }
+
+    /// Connects to the proxy and asks it to open a tunnel to `target`.
+    pub(crate) async fn connect(&self, target: SocketAddr) -> Result<TcpStream, anyhow::Error> {
+        let mut stream = connect(self.address, "proxy").await?;
+        self.handshake(&mut stream, target).await?;
+        Ok(stream)
+    }
+
+    /// Asks the proxy at the other end of `stream` to open a tunnel to `target`.
+    pub(crate) async fn handshake(
+        &self,
+        stream: &mut TcpStream,
+        target: SocketAddr,
+    ) -> Result<(), anyhow::Error> {
+        let handshake = async {
+            match self.kind {
+                ProxyKind::Http => http_connect(stream, target).await,
+                ProxyKind::Socks5 => socks5_connect(stream, target).await,
+            }
+        };
+        match timeout(Duration::from_secs(8), handshake).await {
+            Ok(result) => result.with_context(|| format!("Proxy failed to connect to {target}")),
+            Err(_) => bail!("Timed out waiting for the proxy to connect to {target}"),
+        }
+    }
+}
+
+async fn http_connect(stream: &mut TcpStream, target: SocketAddr) -> Result<(), anyhow::Error> {
+    stream
+        .write_all(format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n").as_bytes())
+        .await?;
+
+    // Read the response a byte at a time so no tunneled data is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8 * 1024 {
+            bail!("Proxy response is too long");
+        }
+        response.push(stream.read_u8().await?);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => bail!("Proxy responded with `{status}`"),
+    }
+}
+
+async fn socks5_connect(stream: &mut TcpStream, target: SocketAddr) -> Result<(), anyhow::Error> {
+    // Offer only the "no authentication" method
+    stream.write_all(&[5, 1, 0]).await?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 5 {
+        bail!("Proxy is not a SOCKS5 proxy");
+    }
+    if reply[1] != 0 {
+        bail!("Proxy requires authentication, which is unsupported");
+    }
+
+    let mut request = vec![5, 1, 0];
+    match target.ip() {
+        IpAddr::V4(ip) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        bail!(
+            "Proxy refused the connection with SOCKS5 error {}",
+            reply[1]
+        );
+    }
+
+    // Skip the bound address and port
+    let address = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => stream.read_u8().await? as usize,
+        _ => bail!("Invalid SOCKS5 address type {}", reply[3]),
+    };
+    let mut bound = vec![0; address + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use crate::common::{interface_ips, Config};
 use crate::plot::save_graph_to_mem;
+use crate::protocol::PORT;
 use crate::test::{test_async, timed, PlotConfig};
 use crate::{version, with_time};
 use anyhow::anyhow;
@@ -7,11 +8,11 @@ use anyhow::bail;
 use anyhow::Error;
 use axum::body::Body;
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
-use axum::http::{header, HeaderValue, Response};
+use axum::http::{header, HeaderMap, HeaderValue, Response, StatusCode};
 use axum::{
-    extract::{ConnectInfo, State},
+    extract::{ConnectInfo, RawQuery, State},
     response::{Html, IntoResponse},
-    routing::get,
+    routing::{get, post},
     Router,
 };
 use image::ImageFormat;
@@ -31,22 +32,70 @@ use tokio::sync::mpsc::unbounded_channel;
 use tokio::sync::oneshot;
 use tokio::{net::TcpListener, signal, task};
 
+/// The default TCP port of the web server.
+pub const REMOTE_PORT: u16 = PORT + 1;
+
 struct Env {
     live_reload: bool,
+    /// Requests to run tests must include this token, if set.
+    token: Option<String>,
     msg: Box<dyn Fn(&str) + Send + Sync>,
 }
 
+impl Env {
+    /// Checks the token given as a bearer token or a `token` query parameter.
+    fn authorized(&self, headers: &HeaderMap, query: Option<&str>) -> bool {
+        let Some(token) = self.token.as_deref() else {
+            return true;
+        };
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let param = query.and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .find_map(|(name, value)| (name == "token").then_some(value))
+        });
+        // Compare all bytes so the time taken doesn't reveal how much of the token matched
+        let matches = |given: &str| {
+            given.len() == token.len()
+                && given
+                    .bytes()
+                    .zip(token.bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        };
+        bearer.is_some_and(matches) || param.is_some_and(|param| matches(&param))
+    }
+}
+
+fn unauthorized(state: &Env, who: SocketAddr) -> Response<Body> {
+    (state.msg)(&format!(
+        "Remote client ({}) rejected due to a missing or invalid token",
+        who.ip()
+    ));
+    (StatusCode::UNAUTHORIZED, "A valid token is required").into_response()
+}
+
 async fn ws_client(
     State(state): State<Arc<Env>>,
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> impl IntoResponse {
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+) -> Response<Body> {
+    if !state.authorized(&headers, query.as_deref()) {
+        return unauthorized(&state, addr);
+    }
     ws.on_upgrade(move |socket| async move {
         handle_client(state, socket, addr).await.ok();
     })
 }
 
+/// Missing fields use the defaults of the `test` command, so API requests only need the
+/// fields they want to change.
 #[derive(Deserialize, Debug)]
+#[serde(default)]
 struct TestArgs {
     server: Option<String>,
     download: bool,
@@ -67,6 +116,51 @@ struct TestArgs {
     latency_peer_server: Option<String>,
 }
 
+impl Default for TestArgs {
+    fn default() -> Self {
+        let config = Config::default();
+        Self {
+            server: None,
+            download: config.download,
+            upload: config.upload,
+            bidirectional: config.bidirectional,
+            port: config.port,
+            streams: config.streams,
+            stream_stagger: config.stream_stagger.as_secs_f64(),
+            load_duration: config.load_duration.as_secs_f64(),
+            grace_duration: config.grace_duration.as_secs_f64(),
+            latency_sample_interval: config.ping_interval.as_millis() as u64,
+            throughput_sample_interval: config.throughput_interval.as_millis() as u64,
+            latency_peer: false,
+            latency_peer_server: None,
+        }
+    }
+}
+
+impl TestArgs {
+    /// Returns the test configuration, rejecting durations which aren't valid as the
+    /// request comes from an untrusted client.
+    fn config(&self) -> Result<Config, Error> {
+        let seconds = |name: &str, seconds: f64| {
+            Duration::try_from_secs_f64(seconds)
+                .map_err(|_| anyhow!("`{name}` must be a non-negative number of seconds"))
+        };
+
+        Ok(Config::builder()
+            .port(self.port)
+            .streams(self.streams)
+            .stream_stagger(seconds("stream_stagger", self.stream_stagger)?)
+            .grace_duration(seconds("grace_duration", self.grace_duration)?)
+            .load_duration(seconds("load_duration", self.load_duration)?)
+            .download(self.download)
+            .upload(self.upload)
+            .bidirectional(self.bidirectional)
+            .ping_interval(Duration::from_millis(self.latency_sample_interval))
+            .throughput_interval(Duration::from_millis(self.throughput_sample_interval))
+            .build())
+    }
+}
+
 async fn handle_client(
     state: Arc<Env>,
     mut socket: WebSocket,
@@ -76,17 +170,20 @@ async fn handle_client(
         Message::Text(request) => serde_json::from_str(&request)?,
         _ => bail!("unexpected message"),
     };
-    let config = Config {
-        port: args.port,
-        streams: args.streams,
-        stream_stagger: Duration::from_secs_f64(args.stream_stagger),
-        grace_duration: Duration::from_secs_f64(args.grace_duration),
-        load_duration: Duration::from_secs_f64(args.load_duration),
-        download: args.download,
-        upload: args.upload,
-        bidirectional: args.bidirectional,
-        ping_interval: Duration::from_millis(args.latency_sample_interval),
-        throughput_interval: Duration::from_millis(args.throughput_sample_interval),
+    let config = match args.config() {
+        Ok(config) => config,
+        Err(error) => {
+            socket
+                .send(Message::Text(
+                    json!({
+                        "type": "log",
+                        "message": format!("Invalid request: {error}"),
+                    })
+                    .to_string(),
+                ))
+                .await?;
+            return Err(error);
+        }
     };
 
     (state.msg)(&format!("Remote client ({}) test started", who.ip()));
@@ -103,8 +200,12 @@ async fn handle_client(
             config,
             args.server.as_deref(),
             args.latency_peer
-                .then_some(args.latency_peer_server.as_deref()),
+                .then_some(args.latency_peer_server.as_deref())
+                .as_slice(),
             msg.clone(),
+            None,
+            None,
+            None,
         )
         .await
         .map_err(|err| {
@@ -163,6 +264,87 @@ async fn handle_client(
     Ok(())
 }
 
+/// Runs a test described by a JSON `TestArgs` body and replies with the JSON summary.
+async fn api_test(
+    State(state): State<Arc<Env>>,
+    ConnectInfo(who): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+    body: String,
+) -> Response<Body> {
+    if !state.authorized(&headers, query.as_deref()) {
+        return unauthorized(&state, who);
+    }
+
+    let json_response = |status: StatusCode, body: String| {
+        (
+            status,
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )],
+            body,
+        )
+            .into_response()
+    };
+    let error = |status: StatusCode, error: String| {
+        json_response(status, json!({ "error": error }).to_string())
+    };
+
+    let args: TestArgs =
+        match serde_json::from_str(if body.trim().is_empty() { "{}" } else { &body }) {
+            Ok(args) => args,
+            Err(err) => return error(StatusCode::BAD_REQUEST, format!("Invalid request: {err}")),
+        };
+    let config = match args.config() {
+        Ok(config) => config,
+        Err(err) => return error(StatusCode::BAD_REQUEST, format!("Invalid request: {err}")),
+    };
+
+    (state.msg)(&format!("API client ({}) test started", who.ip()));
+
+    let msg = Arc::new(|msg: &str| {
+        let msg = with_time(msg);
+        task::spawn_blocking(move || println!("{}", msg));
+    });
+    let result = test_async(
+        config,
+        args.server.as_deref(),
+        args.latency_peer
+            .then_some(args.latency_peer_server.as_deref())
+            .as_slice(),
+        msg,
+        None,
+        None,
+        None,
+    )
+    .await;
+
+    let result = match result {
+        Ok(result) => result,
+        Err(err) => {
+            (state.msg)(&format!("API client ({}) test failed", who.ip()));
+            return error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+        }
+    };
+
+    let summary = task::spawn_blocking(move || {
+        serde_json::to_string_pretty(&result.to_test_result().json_summary())
+    })
+    .await;
+
+    match summary {
+        Ok(Ok(summary)) => {
+            (state.msg)(&format!("API client ({}) test complete", who.ip()));
+            json_response(StatusCode::OK, summary)
+        }
+        _ => error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unable to summarize the result".to_owned(),
+        ),
+    }
+}
+
 async fn listen(state: Arc<Env>, listener: TcpListener) {
     async fn root(State(state): State<Arc<Env>>) -> Html<String> {
         if state.live_reload {
@@ -193,6 +375,7 @@ async fn listen(state: Arc<Env>, listener: TcpListener) {
         .route("/", get(root))
         .route("/assets/vue.js", get(vue))
         .route("/api/client", get(ws_client))
+        .route("/api/test", post(api_test))
         .with_state(state);
 
     axum::serve(
@@ -203,7 +386,11 @@ async fn listen(state: Arc<Env>, listener: TcpListener) {
     .unwrap();
 }
 
-async fn serve_async(port: u16, msg: Box<dyn Fn(&str) + Send + Sync>) -> Result<(), Error> {
+async fn serve_async(
+    port: u16,
+    token: Option<String>,
+    msg: Box<dyn Fn(&str) + Send + Sync>,
+) -> Result<(), Error> {
     let live_reload = cfg!(debug_assertions)
         && std::fs::read_to_string("crusader-lib/src/remote.html")
             .map(|file| *file == *include_str!("remote.html"))
@@ -215,7 +402,11 @@ async fn serve_async(port: u16, msg: Box<dyn Fn(&str) + Send + Sync>) -> Result<
         ));
     }
 
-    let state = Arc::new(Env { live_reload, msg });
+    let state = Arc::new(Env {
+        live_reload,
+        token,
+        msg,
+    });
 
     let v6 = Socket::new(Domain::IPV6, socket2::Type::STREAM, Some(Protocol::TCP))?;
     v6.set_only_v6(true)?;
@@ -257,11 +448,16 @@ async fn serve_async(port: u16, msg: Box<dyn Fn(&str) + Send + Sync>) -> Result<
         (state.msg)(&format!("Address on `{name}`: http://{addr}:{port}"));
     }
 
+    if state.token.is_some() {
+        (state.msg)("Tests require a token, which web clients pass as `?token=<TOKEN>`");
+    }
+
     Ok(())
 }
 
 pub fn serve_until(
     port: u16,
+    token: Option<String>,
     msg: Box<dyn Fn(&str) + Send + Sync>,
     started: Box<dyn FnOnce(Result<(), String>) + Send>,
     done: Box<dyn FnOnce() + Send>,
@@ -272,7 +468,7 @@ pub fn serve_until(
 
     thread::spawn(move || {
         rt.block_on(async move {
-            match serve_async(port, msg).await {
+            match serve_async(port, token, msg).await {
                 Ok(()) => {
                     started(Ok(()));
                     rx.await.ok();
@@ -287,11 +483,12 @@ pub fn serve_until(
     Ok(tx)
 }
 
-pub fn run(port: u16) -> Result<(), anyhow::Error> {
+pub fn run(port: u16, token: Option<String>) -> Result<(), anyhow::Error> {
     let rt = tokio::runtime::Runtime::new()?;
     rt.block_on(async move {
         serve_async(
             port,
+            token,
             Box::new(|msg: &str| {
                 let msg = msg.to_owned();
                 task::spawn_blocking(move || println!("{}", with_time(&msg)));
@@ -1,15 +1,18 @@
 use anyhow::{anyhow, bail, Context};
-use futures::{pin_mut, select, FutureExt};
+use futures::{pin_mut, select, FutureExt, SinkExt, StreamExt};
 use parking_lot::Mutex;
 use socket2::{Domain, Protocol, Socket};
 use std::collections::HashMap;
 use std::io::ErrorKind;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpSocket, TcpStream, UdpSocket};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::net::{TcpListener, TcpSocket, UdpSocket};
 use tokio::sync::mpsc::{
     channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender,
 };
@@ -23,6 +26,7 @@ use crate::peer::run_peer;
 use crate::protocol::{
     self, codec, receive, send, ClientMessage, LatencyMeasure, ServerMessage, TestStream,
 };
+use crate::transport::Stream;
 use crate::{discovery, version, with_time};
 
 use std::thread;
@@ -36,16 +40,57 @@ struct SlotUpdate {
 
 #[derive(Debug)]
 struct Client {
-    ip: Ipv6Addr,
+    ip: Option<Ipv6Addr>, // `None` for clients on the Unix domain socket
     tx_message: UnboundedSender<ServerMessage>,
     tx_latency: Sender<LatencyMeasure>,
     rx_latency: Mutex<Receiver<LatencyMeasure>>,
     overload: AtomicBool,
+    max_timer_lag: AtomicU64,  // In microseconds
+    max_send_stall: AtomicU64, // In microseconds
     loads: Mutex<HashMap<u32, watch::Sender<Option<Instant>>>>,
     uploads: Mutex<HashMap<TestStream, oneshot::Sender<()>>>,
+    aborted: watch::Sender<bool>,
 }
 
+// How late timers can fire or how long sending measurements can stall before
+// the server is considered to be falling behind.
+const TIMER_LAG_LIMIT: Duration = Duration::from_millis(50);
+const SEND_STALL_LIMIT: Duration = Duration::from_millis(250);
+
+/// How long a client waits for the rest of its coordinated group to join.
+const COORDINATION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How much a load may exceed the maximum load duration. Clients extend upload loads slightly
+/// to cover the delay before their measurement starts.
+const LOAD_DURATION_SLACK: Duration = Duration::from_secs(1);
+
 impl Client {
+    fn record_timer_lag(&self, scheduled: Instant) {
+        let lag = Instant::now().saturating_duration_since(scheduled);
+        self.max_timer_lag
+            .fetch_max(lag.as_micros() as u64, Ordering::SeqCst);
+    }
+
+    /// Explains why the server may have been unable to keep up during the test.
+    fn overload_diagnostic(&self) -> Option<String> {
+        let timer_lag = Duration::from_micros(self.max_timer_lag.load(Ordering::SeqCst));
+        let send_stall = Duration::from_micros(self.max_send_stall.load(Ordering::SeqCst));
+
+        if timer_lag > TIMER_LAG_LIMIT || self.overload.load(Ordering::SeqCst) {
+            Some(format!(
+                "Server CPU-bound: its tasks ran up to {:.1} ms behind schedule",
+                timer_lag.as_secs_f64() * 1000.0
+            ))
+        } else if send_stall > SEND_STALL_LIMIT {
+            Some(format!(
+                "Server network-bound: sending measurements stalled for up to {:.1} ms",
+                send_stall.as_secs_f64() * 1000.0
+            ))
+        } else {
+            None
+        }
+    }
+
     fn forward_latency_msgs(&self) {
         let mut rx = self.rx_latency.lock();
 
@@ -96,16 +141,130 @@ struct Pong {
     updates: UnboundedSender<SlotUpdate>,
 }
 
+/// Limits a server places on its clients. `None` means unlimited.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct ServerLimits {
+    pub max_load_duration: Option<Duration>,
+    pub max_streams: Option<u32>,
+    pub max_clients: Option<usize>,
+}
+
+impl ServerLimits {
+    fn check_load(&self, stream: TestStream, duration: u64) -> Result<(), String> {
+        let duration = Duration::from_micros(duration);
+        if let Some(max) = self.max_load_duration {
+            if duration > max.saturating_add(LOAD_DURATION_SLACK) {
+                return Err(format!(
+                    "Load duration of {:.1} s exceeds the server limit of {:.1} s",
+                    duration.as_secs_f64(),
+                    max.as_secs_f64()
+                ));
+            }
+        }
+        if let Some(max) = self.max_streams {
+            if stream.id >= max {
+                return Err(format!(
+                    "Stream count exceeds the server limit of {max} streams"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
 pub(crate) struct State {
     port: u16,
+    limits: ServerLimits,
     started: Instant,
     dummy_data: Vec<u8>,
+    bytes: AtomicU64,
     clients: Mutex<Vec<Option<Arc<Client>>>>,
     pong_servers: Mutex<HashMap<SocketAddr, Arc<Pong>>>,
+    coordinations: Mutex<HashMap<u64, Coordination>>,
     pub(crate) msg: Box<dyn Fn(&str) + Send + Sync>,
     pub(crate) peer_server: bool,
 }
 
+/// Clients waiting for the rest of their coordinated group to join.
+struct Coordination {
+    clients: u32,
+    waiting: Vec<oneshot::Sender<()>>,
+}
+
+impl State {
+    /// Adds `client` to the coordinated group `id`. Every client of the group is told
+    /// to start once `clients` have joined.
+    fn coordinate(self: &Arc<Self>, client: &Arc<Client>, id: u64, clients: u32) {
+        let mut coordinations = self.coordinations.lock();
+        let coordination = coordinations.entry(id).or_insert_with(|| Coordination {
+            clients,
+            waiting: Vec::new(),
+        });
+
+        // Forget clients which disconnected while waiting
+        coordination.waiting.retain(|waiting| !waiting.is_closed());
+        if coordination.waiting.is_empty() {
+            coordination.clients = clients;
+        }
+
+        if coordination.clients != clients {
+            client
+                .tx_message
+                .send(ServerMessage::Rejected(format!(
+                    "Group {id} is waiting for {} clients, not {clients}",
+                    coordination.clients
+                )))
+                .ok();
+            return;
+        }
+
+        let (tx, rx) = oneshot::channel();
+        coordination.waiting.push(tx);
+
+        if coordination.waiting.len() >= clients as usize {
+            for waiting in coordinations.remove(&id).unwrap().waiting {
+                waiting.send(()).ok();
+            }
+            (self.msg)(&format!("Starting group {id} with {clients} clients"));
+        }
+
+        let state = self.clone();
+        let client = client.clone();
+        tokio::spawn(async move {
+            select! {
+                ready = time::timeout(COORDINATION_TIMEOUT, rx).fuse() => match ready {
+                    Ok(Ok(())) => {
+                        client.tx_message.send(ServerMessage::Coordinated { clients }).ok();
+                    }
+                    Ok(Err(_)) => (),
+                    Err(_) => {
+                        client
+                            .tx_message
+                            .send(ServerMessage::Rejected(format!(
+                                "Timed out waiting for group {id} to fill"
+                            )))
+                            .ok();
+                    }
+                },
+                _ = client.tx_message.closed().fuse() => (),
+            }
+            state.leave_coordination(id);
+        });
+    }
+
+    /// Forgets the clients of group `id` which are no longer waiting,
+    /// removing the group once none are left.
+    fn leave_coordination(&self, id: u64) {
+        let mut coordinations = self.coordinations.lock();
+        if let Some(coordination) = coordinations.get_mut(&id) {
+            coordination.waiting.retain(|waiting| !waiting.is_closed());
+            if coordination.waiting.is_empty() {
+                coordinations.remove(&id);
+            }
+        }
+    }
+}
+
 fn ip_to_ipv6_mapped(ip: IpAddr) -> Ipv6Addr {
     match ip {
         IpAddr::V4(ip) => ip.to_ipv6_mapped(),
@@ -121,26 +280,27 @@ impl<F: Fn()> Drop for OnDrop<F> {
     }
 }
 
-async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Error> {
+async fn client(state: Arc<State>, stream: Stream) -> Result<(), anyhow::Error> {
     stream.set_nodelay(true)?;
 
     let addr = stream.peer_addr()?;
+    let ip = stream.peer_ip()?.map(ip_to_ipv6_mapped);
     let local_addr = fresh_socket_addr(stream.local_addr()?, state.port);
 
     let (rx, tx) = stream.into_split();
     let mut stream_rx = FramedRead::new(rx, codec());
     let mut stream_tx = FramedWrite::new(tx, codec());
 
-    let hello = protocol::Hello::new();
-
     let client_hello: protocol::Hello = receive(&mut stream_rx).await?;
 
+    let (hello, version) = client_hello.server_reply();
+
     send(&mut stream_tx, &hello).await?;
 
-    if hello != client_hello {
+    if let Err(error) = version {
         (state.msg)(&format!(
-            "Client {} had invalid hello {:?}, expected {:?}",
-            addr, client_hello, hello
+            "Client {} had incompatible hello: {}",
+            addr, error
         ));
         return Ok(());
     }
@@ -183,6 +343,20 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
                 return Ok(());
             }
             ClientMessage::NewClient => {
+                if let Some(max) = state.limits.max_clients {
+                    let clients = state.clients.lock().iter().flatten().count();
+                    if clients >= max {
+                        let reason = format!("Server is busy with the maximum of {max} clients");
+                        (state.msg)(&format!("Rejected client {}: {}", addr, reason));
+                        if hello.version >= 4 {
+                            send(&mut stream_tx, &ServerMessage::Rejected(reason)).await?;
+                        } else {
+                            send(&mut stream_tx, &ServerMessage::NewClient(None)).await?;
+                        }
+                        return Ok(());
+                    }
+                }
+
                 (state.msg)(&format!("Serving {}, version {}", addr, hello.version));
 
                 let pong = start_pong_server(&state, local_addr)
@@ -201,13 +375,16 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
                             let (tx_latency, rx_latency) = channel(200);
                             let slot = slot as u64;
                             let new_client = Arc::new(Client {
-                                ip: ip_to_ipv6_mapped(addr.ip()),
+                                ip,
                                 tx_message,
                                 tx_latency,
                                 rx_latency: Mutex::new(rx_latency),
                                 overload: AtomicBool::new(false),
+                                max_timer_lag: AtomicU64::new(0),
+                                max_send_stall: AtomicU64::new(0),
                                 loads: Mutex::new(HashMap::new()),
                                 uploads: Mutex::new(HashMap::new()),
+                                aborted: watch::channel(false).0,
                             });
                             *data = Some(new_client.clone());
 
@@ -227,7 +404,8 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
                         })?;
                         tx.await.ok();
 
-                        _client_dropper = Some(move || {
+                        let state = state.clone();
+                        _client_dropper = Some(OnDrop(move || {
                             pong.updates
                                 .send(SlotUpdate {
                                     slot,
@@ -235,7 +413,8 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
                                     reply: None,
                                 })
                                 .ok();
-                        });
+                            state.clients.lock()[slot as usize] = None;
+                        }));
 
                         Some(slot)
                     } else {
@@ -253,12 +432,33 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
                         .get(id as usize)
                         .and_then(|client| client.as_ref())
                         .cloned()
-                        .and_then(|client| {
-                            (client.ip == ip_to_ipv6_mapped(addr.ip())).then_some(client)
-                        })
+                        .and_then(|client| (client.ip == ip).then_some(client))
                         .ok_or(anyhow!("Unable to assoicate client"))?,
                 );
             }
+            ClientMessage::Pings(id) => {
+                let client = state
+                    .clients
+                    .lock()
+                    .get(id as usize)
+                    .and_then(|client| client.as_ref())
+                    .cloned()
+                    .filter(|client| client.ip == ip)
+                    .ok_or(anyhow!("Unable to find the client for pings"))?;
+
+                // Echo pings until the client closes the connection
+                while let Some(packet) = stream_rx.next().await {
+                    let packet = packet?;
+                    let ping = bincode::deserialize::<protocol::Ping>(&packet)
+                        .ok()
+                        .filter(|ping| ping.id == id);
+                    if let Some(ping) = ping {
+                        record_ping(&state, &client, &ping);
+                        stream_tx.send(packet.freeze()).await?;
+                    }
+                }
+                return Ok(());
+            }
             ClientMessage::GetMeasurements => {
                 let receiver = receiver.as_mut().ok_or(anyhow!("Not the main client"))?;
 
@@ -271,7 +471,8 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
                 let get_pings = tokio::spawn(async move {
                     let mut interval = time::interval(Duration::from_millis(20));
                     loop {
-                        interval.tick().await;
+                        let scheduled = interval.tick().await;
+                        client.record_timer_lag(scheduled);
 
                         client.forward_latency_msgs();
 
@@ -297,7 +498,11 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
 
                     match message {
                         Ok(Some(message)) => {
+                            let sending = Instant::now();
                             send(&mut stream_tx, &message).await?;
+                            client_
+                                .max_send_stall
+                                .fetch_max(sending.elapsed().as_micros() as u64, Ordering::SeqCst);
                         }
                         Ok(None) | Err(ClientMessage::StopMeasurements) => {
                             done.store(true, Ordering::Release);
@@ -308,6 +513,16 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
                                 send(&mut stream_tx, &message).await?;
                             }
 
+                            if hello.version >= 4 {
+                                if let Some(diagnostic) = client_.overload_diagnostic() {
+                                    send(
+                                        &mut stream_tx,
+                                        &ServerMessage::OverloadDiagnostic(diagnostic),
+                                    )
+                                    .await?;
+                                }
+                            }
+
                             send(
                                 &mut stream_tx,
                                 &ServerMessage::MeasurementsDone { overload },
@@ -330,6 +545,9 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
                             let reply = client_.schedule_loads(&state, groups, delay).await?;
                             send(&mut stream_tx, &reply).await?;
                         }
+                        Err(ClientMessage::Coordinate { id, clients }) => {
+                            state.coordinate(&client_, id, clients);
+                        }
                         Err(msg) => {
                             bail!("Unexpected message during measurement {:?}", msg)
                         }
@@ -344,6 +562,14 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
             } => {
                 let client = client.ok_or(anyhow!("No associated client"))?;
 
+                if let Err(reason) = state.limits.check_load(test_stream, duration) {
+                    (state.msg)(&format!("Rejected load from {}: {}", addr, reason));
+                    if hello.version >= 4 {
+                        send(&mut stream_tx, &ServerMessage::Rejected(reason)).await?;
+                    }
+                    return Ok(());
+                }
+
                 let mut stream_rx = stream_rx.into_inner();
 
                 send(&mut stream_tx, &ServerMessage::WaitingForByte).await?;
@@ -370,12 +596,24 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
 
                 time::sleep_until(start).await;
 
-                write_data(
+                let mut aborted = client.aborted.subscribe();
+                let write = write_data(
                     stream,
                     state.dummy_data.as_ref(),
                     start + Duration::from_micros(duration),
+                    Some(&state.bytes),
+                    &[],
+                    None,
                 )
-                .await?;
+                .fuse();
+                pin_mut!(write);
+                let abort = aborted.wait_for(|&aborted| aborted).fuse();
+                pin_mut!(abort);
+
+                select! {
+                    result = write => result?,
+                    _ = abort => return Ok(()),
+                }
 
                 client
                     .tx_message
@@ -394,6 +632,14 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
             } => {
                 let client = client.ok_or(anyhow!("No associated client"))?;
 
+                if let Err(reason) = state.limits.check_load(test_stream, duration) {
+                    (state.msg)(&format!("Rejected load from {}: {}", addr, reason));
+                    if hello.version >= 4 {
+                        send(&mut stream_tx, &ServerMessage::Rejected(reason)).await?;
+                    }
+                    return Ok(());
+                }
+
                 send(&mut stream_tx, &ServerMessage::WaitingForLoad).await?;
 
                 let reply: ClientMessage = receive(&mut stream_rx).await.unwrap();
@@ -413,6 +659,8 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
 
                 client.uploads.lock().insert(test_stream, reading_done_tx);
 
+                let mut aborted = client.aborted.subscribe();
+
                 let bytes = Arc::new(AtomicU64::new(0));
                 let bytes_ = bytes.clone();
                 let (done_tx, mut done_rx) = oneshot::channel();
@@ -424,10 +672,12 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
 
                 time::sleep_until(start).await;
 
+                let started = state.started;
                 tokio::spawn(async move {
                     let mut interval = time::interval(Duration::from_micros(throughput_interval));
                     loop {
-                        interval.tick().await;
+                        let scheduled = interval.tick().await;
+                        client.record_timer_lag(scheduled);
 
                         let current_time = Instant::now();
                         let current_bytes = bytes_.load(Ordering::Acquire);
@@ -436,9 +686,8 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
                             .tx_message
                             .send(ServerMessage::Measure {
                                 stream: test_stream,
-                                time: current_time
-                                    .saturating_duration_since(state.started)
-                                    .as_micros() as u64,
+                                time: current_time.saturating_duration_since(started).as_micros()
+                                    as u64,
                                 bytes: current_bytes,
                             })
                             .ok();
@@ -456,14 +705,25 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
                     }
                 });
 
-                let timeout = read_data(
+                let read = read_data(
                     stream,
                     &mut buffer,
                     bytes,
                     start + Duration::from_micros(duration),
                     reading_done_rx,
+                    Some(&state.bytes),
+                    None,
+                    None,
                 )
-                .await?;
+                .fuse();
+                pin_mut!(read);
+                let abort = aborted.wait_for(|&aborted| aborted).fuse();
+                pin_mut!(abort);
+
+                let timeout = select! {
+                    result = read => result?,
+                    _ = abort => false,
+                };
 
                 done_tx
                     .send(timeout)
@@ -476,12 +736,53 @@ async fn client(state: Arc<State>, stream: TcpStream) -> Result<(), anyhow::Erro
 
                 return Ok(());
             }
+            ClientMessage::Abort => {
+                let client = client.ok_or(anyhow!("No associated client"))?;
+
+                (state.msg)(&format!("Test aborted by {}", addr));
+
+                client.aborted.send_replace(true);
+
+                return Ok(());
+            }
+            ClientMessage::Status => {
+                // Measure the aggregate throughput over a short window
+                let window = Duration::from_millis(500);
+                let before = state.bytes.load(Ordering::Relaxed);
+                time::sleep(window).await;
+                let bytes = state.bytes.load(Ordering::Relaxed) - before;
+
+                let clients = state.clients.lock().iter().flatten().count() as u64;
+                let throughput_mbps = (bytes as f64 * 8.0) / 1000.0 / 1000.0 / window.as_secs_f64();
+
+                send(
+                    &mut stream_tx,
+                    &ServerMessage::Status {
+                        clients,
+                        throughput_mbps,
+                    },
+                )
+                .await?;
+
+                return Ok(());
+            }
+            ClientMessage::ServerInfo => {
+                send(
+                    &mut stream_tx,
+                    &ServerMessage::ServerInfo {
+                        version: crate::version(),
+                        uptime: state.started.elapsed().as_secs(),
+                    },
+                )
+                .await?;
+            }
             msg @ (ClientMessage::StopMeasurements
             | ClientMessage::ScheduleLoads { .. }
             | ClientMessage::LoadComplete { .. }
             | ClientMessage::SendByte
             | ClientMessage::PeerStart
-            | ClientMessage::PeerStop) => {
+            | ClientMessage::PeerStop
+            | ClientMessage::Coordinate { .. }) => {
                 bail!("Unexpected message {:?}", msg);
             }
         };
@@ -494,7 +795,7 @@ async fn listen(state: Arc<State>, listener: TcpListener) {
             Ok((socket, addr)) => {
                 let state = state.clone();
                 tokio::spawn(async move {
-                    client(state.clone(), socket).await.map_err(|error| {
+                    client(state.clone(), socket.into()).await.map_err(|error| {
                         (state.msg)(&format!("Error serving client {}: {:?}", addr, error));
                     })
                 });
@@ -506,6 +807,69 @@ async fn listen(state: Arc<State>, listener: TcpListener) {
     }
 }
 
+#[cfg(unix)]
+async fn listen_unix(state: Arc<State>, listener: UnixListener) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, _)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    client(state.clone(), Stream::Unix(socket))
+                        .await
+                        .map_err(|error| {
+                            (state.msg)(&format!(
+                                "Error serving client on Unix domain socket: {:?}",
+                                error
+                            ));
+                        })
+                });
+            }
+            Err(error) => {
+                (state.msg)(&format!("Error accepting client: {}", error));
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> Result<UnixListener, anyhow::Error> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // Remove a socket left behind by a previous server, but not one a server is still listening on
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        match std::os::unix::net::UnixStream::connect(path) {
+            Ok(_) => bail!("Unix domain socket {} is already in use", path.display()),
+            Err(error) if error.kind() == std::io::ErrorKind::ConnectionRefused => {
+                std::fs::remove_file(path)?;
+            }
+            Err(error) => {
+                return Err(error).with_context(|| {
+                    format!("Failed to check Unix domain socket {}", path.display())
+                })
+            }
+        }
+    }
+
+    UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind Unix domain socket {}", path.display()))
+}
+
+/// Records the time a ping from `client` arrived at.
+fn record_ping(state: &State, client: &Client, ping: &protocol::Ping) {
+    let time = Instant::now()
+        .saturating_duration_since(state.started)
+        .as_micros() as u64;
+
+    let measure = LatencyMeasure {
+        time,
+        index: ping.index,
+    };
+
+    if client.tx_latency.try_send(measure).is_err() {
+        client.overload.store(true, Ordering::SeqCst);
+    }
+}
+
 async fn handle_ping(
     state: &State,
     slots: &[Option<Arc<Client>>],
@@ -520,23 +884,12 @@ async fn handle_ping(
                 .get(ping.id as usize)
                 .and_then(|client| client.as_ref())
                 .and_then(|client| {
-                    (ip_to_ipv6_mapped(src.ip()) == client.ip).then_some((client, ping))
+                    (Some(ip_to_ipv6_mapped(src.ip())) == client.ip).then_some((client, ping))
                 })
         });
 
     if let Some((client, ping)) = valid_ping {
-        let time = Instant::now()
-            .saturating_duration_since(state.started)
-            .as_micros() as u64;
-
-        let measure = LatencyMeasure {
-            time,
-            index: ping.index,
-        };
-
-        if client.tx_latency.try_send(measure).is_err() {
-            client.overload.store(true, Ordering::SeqCst);
-        }
+        record_ping(state, client, &ping);
 
         socket
             .send_to(packet, &src)
@@ -557,7 +910,7 @@ async fn pong(
     (state.msg)(&format!("Starting UDP server ({})", addr));
 
     let mut slots: Vec<_> = (0..SLOTS).map(|_| None).collect();
-    let mut buf = [0; 128];
+    let mut buf = [0; protocol::MAX_PING_SIZE];
 
     loop {
         let packet = {
@@ -623,51 +976,122 @@ async fn start_pong_server(
     .clone())
 }
 
+/// Binds and closes a UDP socket to check that the address is available.
+fn check_udp(addr: SocketAddr) -> Result<(), std::io::Error> {
+    let socket = Socket::new(
+        Domain::for_address(addr),
+        socket2::Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.bind(&addr.into())
+}
+
 async fn serve_async(
     port: u16,
+    bind: Option<IpAddr>,
     peer_server: bool,
+    limits: ServerLimits,
+    unix: Option<&Path>,
     msg: Box<dyn Fn(&str) + Send + Sync>,
 ) -> Result<(), anyhow::Error> {
     let state = Arc::new(State {
         port,
+        limits,
         started: Instant::now(),
-        dummy_data: crate::common::data(),
+        dummy_data: crate::common::data(crate::common::DEFAULT_WRITE_SIZE, false),
+        bytes: AtomicU64::new(0),
         clients: Mutex::new((0..SLOTS).map(|_| None).collect()),
         pong_servers: Default::default(),
+        coordinations: Default::default(),
         msg,
         peer_server,
     });
 
-    let v6 = Socket::new(Domain::IPV6, socket2::Type::STREAM, Some(Protocol::TCP))?;
-    v6.set_only_v6(true)?;
-    let v6: std::net::TcpStream = v6.into();
-    v6.set_nonblocking(true)?;
-    let v6 = TcpSocket::from_std_stream(v6);
-    v6.bind(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port))
-        .map_err(|error| {
+    let bind_error = |protocol: &'static str, ip: IpAddr| {
+        move |error: std::io::Error| {
             if let ErrorKind::AddrInUse = error.kind() {
                 anyhow!(
-                    "Failed to bind TCP port, maybe another Crusader instance is already running"
+                    "Failed to bind {protocol} port {port} on {ip}, \
+                    maybe another Crusader instance is already running"
                 )
             } else {
-                error.into()
+                anyhow::Error::new(error)
+                    .context(format!("Failed to bind {protocol} port {port} on {ip}"))
             }
-        })?;
-    let v6 = v6.listen(1024)?;
+        }
+    };
+
+    // UDP pong servers are only bound once clients connect, so check that the UDP port
+    // is available before accepting clients.
+    let ips = match bind {
+        Some(ip) => vec![ip],
+        None => vec![
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        ],
+    };
+    for ip in ips {
+        check_udp(SocketAddr::new(ip, port)).map_err(bind_error("UDP", ip))?;
+    }
 
-    let v4 = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).await?;
+    // UDP pong servers are bound to the local address of each control connection,
+    // so they use the same address as the TCP listeners.
+    if let Some(ip) = bind {
+        let listener = TcpListener::bind((ip, port))
+            .await
+            .map_err(bind_error("TCP", ip))?;
+
+        task::spawn(listen(state.clone(), listener));
+    } else {
+        let v6 = Socket::new(Domain::IPV6, socket2::Type::STREAM, Some(Protocol::TCP))?;
+        v6.set_only_v6(true)?;
+        let v6: std::net::TcpStream = v6.into();
+        v6.set_nonblocking(true)?;
+        let v6 = TcpSocket::from_std_stream(v6);
+        v6.bind(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port))
+            .map_err(bind_error("TCP", IpAddr::V6(Ipv6Addr::UNSPECIFIED)))?;
+        let v6 = v6.listen(1024)?;
+
+        let v4 = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+            .await
+            .map_err(bind_error("TCP", IpAddr::V4(Ipv4Addr::UNSPECIFIED)))?;
+
+        task::spawn(listen(state.clone(), v6));
+        task::spawn(listen(state.clone(), v4));
 
-    task::spawn(listen(state.clone(), v6));
-    task::spawn(listen(state.clone(), v4));
+        if let Err(error) = discovery::serve(state.clone(), port) {
+            (state.msg)(&format!("Failed to run discovery: {:?}", error));
+        }
+    }
 
-    if let Err(error) = discovery::serve(state.clone(), port) {
-        (state.msg)(&format!("Failed to run discovery: {:?}", error));
+    #[cfg(unix)]
+    if let Some(path) = unix {
+        task::spawn(listen_unix(state.clone(), bind_unix(path)?));
+    }
+    #[cfg(not(unix))]
+    if unix.is_some() {
+        bail!("Unix domain sockets are unsupported on this platform");
     }
 
     (state.msg)(&format!("Server version {} running...", version()));
+    (state.msg)(&format!("Listening on TCP and UDP port {port}"));
 
-    for (name, ip) in interface_ips() {
-        (state.msg)(&format!("Address on `{name}`: {ip}"));
+    if let Some(path) = unix {
+        (state.msg)(&format!(
+            "Listening on Unix domain socket {}",
+            path.display()
+        ));
+    }
+
+    if let Some(ip) = bind {
+        (state.msg)(&format!("Bound to address {ip}"));
+    } else {
+        for (name, ip) in interface_ips() {
+            (state.msg)(&format!("Address on `{name}`: {ip}"));
+        }
     }
 
     if peer_server {
@@ -679,7 +1103,9 @@ async fn serve_async(
 
 pub fn serve_until(
     port: u16,
+    bind: Option<IpAddr>,
     peer_server: bool,
+    limits: ServerLimits,
     msg: Box<dyn Fn(&str) + Send + Sync>,
     started: Box<dyn FnOnce(Result<(), String>) + Send>,
     done: Box<dyn FnOnce() + Send>,
@@ -690,12 +1116,12 @@ pub fn serve_until(
 
     thread::spawn(move || {
         rt.block_on(async move {
-            match serve_async(port, peer_server, msg).await {
+            match serve_async(port, bind, peer_server, limits, None, msg).await {
                 Ok(()) => {
                     started(Ok(()));
                     rx.await.ok();
                 }
-                Err(error) => started(Err(error.to_string())),
+                Err(error) => started(Err(format!("{error:#}"))),
             }
         });
 
@@ -705,12 +1131,22 @@ pub fn serve_until(
     Ok(tx)
 }
 
-pub fn serve(port: u16, peer_server: bool) -> Result<(), anyhow::Error> {
+/// Runs the server until interrupted. `unix` additionally accepts clients on a Unix domain socket.
+pub fn serve(
+    port: u16,
+    bind: Option<IpAddr>,
+    peer_server: bool,
+    limits: ServerLimits,
+    unix: Option<PathBuf>,
+) -> Result<(), anyhow::Error> {
     let rt = tokio::runtime::Runtime::new()?;
     rt.block_on(async move {
         serve_async(
             port,
+            bind,
             peer_server,
+            limits,
+            unix.as_deref(),
             Box::new(|msg: &str| {
                 let msg = msg.to_owned();
                 task::spawn_blocking(move || println!("{}", with_time(&msg)));
@@ -1,23 +1,31 @@
 use crate::common::{
-    connect, data, fresh_socket_addr, hello, measure_latency, ping_recv, ping_send, read_data,
-    wait_for_state, write_data, Config, LatencyResult, Msg, TestState,
+    connect, connect_with_timeout, control_hello, data, fresh_socket_addr, hello, measure_latency,
+    ping_recv, ping_send, read_data, set_dscp, wait_for_state, write_data, Config, LatencyResult,
+    LiveLatency, Msg, Pacer, TestState, DEFAULT_READ_SIZE, DEFAULT_WRITE_SIZE,
 };
 use crate::file_format::{
-    RawConfig, RawHeader, RawPing, RawPoint, RawResult, RawStream, RawStreamGroup, TestData,
-    TestKind,
+    Compression, RawConfig, RawHeader, RawPing, RawPoint, RawResult, RawStream, RawStreamGroup,
+    TestData, TestKind,
 };
+use crate::icmp::{self, Echo};
+use crate::iface;
 use crate::peer::connect_to_peer;
-use crate::plot::save_graph;
+use crate::plot::{save_graph, throughput_end, TestResult};
 use crate::protocol::{
     codec, receive, send, ClientMessage, Hello, RawLatency, ServerMessage, TestStream,
 };
+use crate::proxy::Proxy;
+use crate::serve::OnDrop;
+use crate::transport::{self, connect_unix, unix_path, PingSocket};
+use crate::tui::Tui;
 use crate::{discovery, version, with_time};
 use anyhow::{anyhow, bail, Context};
 use bytes::{Bytes, BytesMut};
 use futures::future::FutureExt;
-use futures::{select, Sink, Stream};
+use futures::{select, select_biased, Future, Sink, Stream};
 use futures::{stream, StreamExt};
 use parking_lot::Mutex;
+use socket2::SockRef;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
@@ -29,48 +37,305 @@ use std::{
     time::Duration,
 };
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::sync::mpsc::{channel, Sender};
+use tokio::net::TcpSocket;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::{oneshot, watch, Semaphore};
 use tokio::task::{self, JoinHandle};
 use tokio::time::Instant;
 use tokio::{
     net::{self},
-    time,
+    signal, time,
 };
 use tokio_util::codec::{Framed, FramedRead, FramedWrite, LengthDelimitedCodec};
 
 const MEASURE_DELAY: Duration = Duration::from_millis(50);
 
+const CLOCK_TOLERANCE: Duration = Duration::from_millis(1);
+
 #[derive(Debug)]
-struct ScheduledLoads;
+struct ScheduledLoads {
+    /// The server time the loads start at.
+    time: u64,
+}
 
 struct State {
     downloads: Mutex<HashMap<TestStream, oneshot::Sender<()>>>,
     timeout: AtomicBool,
+    download_bytes: AtomicU64,
+    upload_bytes: AtomicU64,
+    latency: Arc<LiveLatency>,
+}
+
+/// A live sample of a running test.
+#[derive(Clone, Copy, Debug)]
+pub struct ProgressSample {
+    /// The load currently being tested, `None` during grace periods.
+    pub load: Option<TestKind>,
+    /// Time since the first grace period started.
+    pub elapsed: Duration,
+    /// The expected duration of the whole test.
+    pub duration: Duration,
+    /// Aggregate download throughput in Mbps since the previous sample.
+    pub download: f64,
+    /// Aggregate upload throughput in Mbps since the previous sample.
+    pub upload: f64,
+    /// The round-trip latency of the latest ping reply, `None` if there's none yet.
+    pub latency: Option<Duration>,
+}
+
+pub type Progress = Arc<dyn Fn(ProgressSample) + Send + Sync>;
+
+/// Calls `progress` every throughput interval until the test ends.
+async fn report_progress(
+    progress: Progress,
+    state: Arc<State>,
+    mut state_rx: watch::Receiver<(TestState, Instant)>,
+    interval: Duration,
+    duration: Duration,
+) {
+    let start = Instant::now();
+    let mut ticks = time::interval(interval);
+    let mut last = (start, 0, 0);
+    loop {
+        ticks.tick().await;
+
+        let load = match state_rx.borrow_and_update().0 {
+            TestState::LoadFromServer => Some(TestKind::Download),
+            TestState::LoadFromClient => Some(TestKind::Upload),
+            TestState::LoadFromBoth => Some(TestKind::Bidirectional),
+            TestState::End | TestState::EndPingRecv => break,
+            _ => None,
+        };
+
+        let now = Instant::now();
+        let download = state.download_bytes.load(Ordering::Relaxed);
+        let upload = state.upload_bytes.load(Ordering::Relaxed);
+        let seconds = now.duration_since(last.0).as_secs_f64().max(f64::EPSILON);
+        let mbps = |bytes: u64| (bytes as f64 * 8.0) / 1000.0 / 1000.0 / seconds;
+
+        progress(ProgressSample {
+            load,
+            elapsed: now.duration_since(start),
+            duration,
+            download: mbps(download - last.1),
+            upload: mbps(upload - last.2),
+            latency: state.latency.latest(),
+        });
+
+        last = (now, download, upload);
+    }
 }
 
 async fn hello_combined<S: Sink<Bytes> + Stream<Item = Result<BytesMut, S::Error>> + Unpin>(
     stream: &mut S,
-) -> Result<(), anyhow::Error>
+    version: u64,
+) -> Result<u64, anyhow::Error>
 where
     S::Error: Error + Send + Sync + 'static,
 {
-    let hello = Hello::new();
-
-    send(stream, &hello).await?;
+    send(stream, &Hello::offering(version)).await?;
     let server_hello: Hello = receive(stream).await?;
 
-    if hello != server_hello {
-        panic!(
-            "Mismatched server hello, got {:?}, expected {:?}",
-            server_hello, hello
-        );
+    server_hello.negotiate()
+}
+
+/// Identifies a running test on a server, so that it can be aborted over another connection.
+#[derive(Clone)]
+pub(crate) struct Session {
+    server: SocketAddr,
+    proxy: Option<Proxy>,
+    unix: Option<Arc<Path>>,
+    id: u64,
+    version: u64,
+}
+
+/// Tells the server to stop the loaders of an aborted test.
+async fn abort_session(session: Session) -> Result<(), anyhow::Error> {
+    if session.version < 4 {
+        return Ok(());
     }
 
+    let control = connect_again(session.unix.as_deref(), session.proxy, session.server).await?;
+    let (rx, tx) = control.into_split();
+    let mut control_rx = FramedRead::new(rx, codec());
+    let mut control_tx = FramedWrite::new(tx, codec());
+
+    hello(&mut control_tx, &mut control_rx).await?;
+    send(&mut control_tx, &ClientMessage::Associate(session.id)).await?;
+    send(&mut control_tx, &ClientMessage::Abort).await?;
+
+    // Wait for the server to close the connection, so the abort is handled before the test's
+    // control connection closes
+    control_rx.next().await;
+
     Ok(())
 }
 
+/// Connects to a server `connect_control` already connected to.
+async fn connect_again(
+    unix: Option<&Path>,
+    proxy: Option<Proxy>,
+    server: SocketAddr,
+) -> Result<transport::Stream, anyhow::Error> {
+    let control = match (unix, proxy) {
+        (Some(path), _) => connect_unix(path).await?,
+        (None, Some(proxy)) => proxy.connect(server).await?.into(),
+        (None, None) => transport::Stream::from(connect(server, "server").await?),
+    };
+    control.set_nodelay(true)?;
+    Ok(control)
+}
+
+/// The activity of a server.
+#[derive(Clone, Copy, Debug)]
+pub struct ServerStatus {
+    pub clients: u64,
+    pub throughput_mbps: f64,
+}
+
+/// Asks a server how many clients it's serving and its current aggregate throughput.
+pub fn server_status(server: &str, port: u16) -> Result<ServerStatus, anyhow::Error> {
+    tokio::runtime::Runtime::new()?.block_on(server_status_async(server, port))
+}
+
+async fn server_status_async(server: &str, port: u16) -> Result<ServerStatus, anyhow::Error> {
+    let control = connect((server, port), "server").await?;
+    let (rx, tx) = transport::Stream::from(control).into_split();
+    let mut control_rx = FramedRead::new(rx, codec());
+    let mut control_tx = FramedWrite::new(tx, codec());
+
+    let version = hello(&mut control_tx, &mut control_rx).await?;
+    if version < 4 {
+        bail!("The server is too old to report its status");
+    }
+
+    send(&mut control_tx, &ClientMessage::Status).await?;
+
+    match receive(&mut control_rx).await? {
+        ServerMessage::Status {
+            clients,
+            throughput_mbps,
+        } => Ok(ServerStatus {
+            clients,
+            throughput_mbps,
+        }),
+        reply => bail!("Unexpected message {:?}", reply),
+    }
+}
+
+/// The version and uptime of a server.
+#[derive(Clone, Debug)]
+pub struct ServerInfo {
+    pub version: String,
+    pub uptime: Duration,
+}
+
+/// Asks the server for its version and uptime, returning `None` if it's too old to report them.
+async fn server_info<T: Sink<Bytes> + Unpin, R: Stream<Item = Result<BytesMut, RE>> + Unpin, RE>(
+    tx: &mut T,
+    rx: &mut R,
+    protocol_version: u64,
+) -> Result<Option<ServerInfo>, anyhow::Error>
+where
+    T::Error: Error + Send + Sync + 'static,
+    RE: Error + Send + Sync + 'static,
+{
+    if protocol_version < 5 {
+        return Ok(None);
+    }
+
+    send(tx, &ClientMessage::ServerInfo).await?;
+
+    match receive(rx).await? {
+        ServerMessage::ServerInfo { version, uptime } => Ok(Some(ServerInfo {
+            version,
+            uptime: Duration::from_secs(uptime),
+        })),
+        reply => bail!("Unexpected message {:?}", reply),
+    }
+}
+
+/// The outcome of checking that a server can be tested, without loading the network.
+#[derive(Clone, Debug)]
+pub struct CheckResult {
+    pub server: String,
+    pub address: SocketAddr,
+    pub protocol_version: u64,
+    /// `None` when the server is too old to report it.
+    pub server_info: Option<ServerInfo>,
+    /// `None` when connecting through a proxy, as UDP can't pass through it.
+    pub latency: Option<Duration>,
+}
+
+/// Connects to a server and measures the idle latency, checking that both TCP and UDP work.
+pub fn check(config: Config, server: Option<&str>) -> Result<CheckResult, anyhow::Error> {
+    let msg: Msg = Arc::new(|msg| println!("{}", with_time(msg)));
+    tokio::runtime::Runtime::new()?.block_on(check_async(config, server, msg))
+}
+
+async fn check_async(
+    config: Config,
+    server: Option<&str>,
+    msg: Msg,
+) -> Result<CheckResult, anyhow::Error> {
+    let unix = server.and_then(unix_path);
+
+    let (control, server_name, server) = connect_control(&config, server, &msg).await?;
+
+    let (mut control_rx, mut control_tx, protocol_version) =
+        control_hello(control, || connect_again(unix, config.proxy, server))
+            .await
+            .context("Failed protocol handshake")?;
+
+    let server_info = server_info(&mut control_tx, &mut control_rx, protocol_version)
+        .await
+        .context("Failed to get server info")?;
+
+    send(&mut control_tx, &ClientMessage::NewClient).await?;
+
+    let setup_start = Instant::now();
+
+    let reply: ServerMessage = receive(&mut control_rx)
+        .await
+        .context("Failed to create a new client id")?;
+    let id = match reply {
+        ServerMessage::NewClient(Some(id)) => id,
+        ServerMessage::NewClient(None) => bail!("Server was unable to create client"),
+        ServerMessage::Rejected(reason) => bail!("Server rejected the client: {reason}"),
+        _ => bail!("Unexpected message {:?}", reply),
+    };
+
+    let latency = if config.proxy.is_none() {
+        let result = measure_latency(
+            id,
+            &mut 0,
+            &mut control_tx,
+            control_rx,
+            server,
+            ping_socket(unix, id, server).await?,
+            setup_start,
+            config.ping_payload_size,
+            config.idle_latency_samples,
+            config.idle_latency_interval,
+            config.setup_timeout,
+        )
+        .await?;
+        Some(result.latency)
+    } else {
+        None
+    };
+
+    send(&mut control_tx, &ClientMessage::Done).await?;
+
+    Ok(CheckResult {
+        server: server_name,
+        address: server,
+        protocol_version,
+        server_info,
+        latency,
+    })
+}
+
 #[derive(Default)]
 pub struct PlotConfig {
     pub split_throughput: bool,
@@ -80,41 +345,254 @@ pub struct PlotConfig {
     pub width: Option<u64>,
     pub height: Option<u64>,
     pub title: Option<String>,
+    /// Only plots this range of seconds from the start of the test, if set.
+    pub time_range: Option<(f64, f64)>,
 }
 
-pub(crate) async fn test_async(
-    config: Config,
+/// Calls `attempt` until it succeeds, retrying up to `config.connect_retries` times
+/// with an increasing delay.
+async fn retry<T, F: Future<Output = Result<T, anyhow::Error>>>(
+    config: &Config,
+    msg: &Msg,
+    mut attempt: impl FnMut() -> F,
+) -> Result<T, anyhow::Error> {
+    let mut delay = Duration::from_millis(500);
+    for _ in 0..config.connect_retries {
+        match attempt().await {
+            Ok(result) => return Ok(result),
+            Err(error) => {
+                msg(&format!(
+                    "{error:#}. Retrying in {:.1} s",
+                    delay.as_secs_f64()
+                ));
+                time::sleep(delay).await;
+                delay = (delay * 2).min(Duration::from_secs(8));
+            }
+        }
+    }
+    attempt().await
+}
+
+/// Connects to the control port of `server`, or of a server found by discovery if `None`.
+/// Returns the connection, the server name and its address.
+async fn connect_control(
+    config: &Config,
     server: Option<&str>,
-    latency_peer_server: Option<Option<&str>>,
-    msg: Msg,
-) -> Result<RawResult, anyhow::Error> {
-    msg(&format!("Client version {} running", version()));
+    msg: &Msg,
+) -> Result<(transport::Stream, String, SocketAddr), anyhow::Error> {
+    if config.force_ipv4 && config.force_ipv6 {
+        bail!("Cannot force both IPv4 and IPv6");
+    }
+
+    let family_allowed = |addr: &SocketAddr| {
+        !(config.force_ipv4 && addr.is_ipv6() || config.force_ipv6 && addr.is_ipv4())
+    };
 
-    let control = if let Some(server) = server {
-        connect((server, config.port), "server").await?
+    // The server as named by the user, or as found by discovery
+    let server_name;
+
+    // The server address when connecting through a proxy, as the peer address is the proxy's then
+    let mut proxy_target = None;
+
+    let control = if let Some(path) = server.and_then(unix_path) {
+        if config.proxy.is_some() {
+            bail!("A proxy can't be used with a Unix domain socket");
+        }
+        server_name = path.display().to_string();
+        retry(config, msg, || connect_unix(path)).await?
+    } else if let Some(proxy) = config.proxy {
+        let server = server.ok_or(anyhow!("A server address is required when using a proxy"))?;
+        server_name = server.to_owned();
+        // The server name is resolved locally so the streams all reach the same address
+        let target = net::lookup_host((server, config.port))
+            .await
+            .with_context(|| format!("Failed to resolve {server}"))?
+            .find(family_allowed)
+            .ok_or_else(|| anyhow!("No usable address found for {server}"))?;
+        proxy_target = Some(target);
+        msg(&format!("Connecting through proxy {}", proxy.address));
+        retry(config, msg, || proxy.connect(target)).await?.into()
+    } else if let Some(server) = server {
+        server_name = server.to_owned();
+        if config.force_ipv4 || config.force_ipv6 {
+            let addrs: Vec<_> = net::lookup_host((server, config.port))
+                .await
+                .with_context(|| format!("Failed to resolve {server}"))?
+                .filter(family_allowed)
+                .collect();
+            if addrs.is_empty() {
+                bail!(
+                    "No {} address found for {server}",
+                    if config.force_ipv4 { "IPv4" } else { "IPv6" }
+                );
+            }
+            retry(config, msg, || {
+                connect_with_timeout(addrs.as_slice(), "server", config.connect_timeout)
+            })
+            .await?
+            .into()
+        } else {
+            retry(config, msg, || {
+                connect_with_timeout((server, config.port), "server", config.connect_timeout)
+            })
+            .await?
+            .into()
+        }
     } else {
         let server = discovery::locate(false).await?;
         msg(&format!(
             "Found server at {} running version {}",
             server.at, server.software_version
         ));
-        connect(server.socket, "server").await?
+        if !family_allowed(&server.socket) {
+            bail!("Located server doesn't use the requested address family");
+        }
+        server_name = server.at.clone();
+        connect(server.socket, "server").await?.into()
     };
 
     control.set_nodelay(true)?;
 
-    let server = control.peer_addr()?;
+    let server = match proxy_target {
+        Some(target) => target,
+        None => control.peer_addr()?,
+    };
     let server = fresh_socket_addr(server, server.port());
 
-    msg(&format!("Connected to server {}", server));
+    Ok((control, server_name, server))
+}
 
-    let (rx, tx) = control.into_split();
-    let mut control_rx = FramedRead::new(rx, codec());
-    let mut control_tx = FramedWrite::new(tx, codec());
+/// Opens the socket pings are exchanged with the server on. A Unix domain socket has no UDP,
+/// so they're carried on a separate stream instead.
+async fn ping_socket(
+    unix: Option<&Path>,
+    id: u64,
+    server: SocketAddr,
+) -> Result<PingSocket, anyhow::Error> {
+    let Some(path) = unix else {
+        let local_udp = if server.is_ipv6() {
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0)
+        } else {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)
+        };
+        return Ok(PingSocket::udp(local_udp, server).await?);
+    };
+
+    let (rx, tx) = connect_unix(path).await?.into_split();
+    let mut rx = FramedRead::new(rx, codec());
+    let mut tx = FramedWrite::new(tx, codec());
+    hello(&mut tx, &mut rx).await?;
+    send(&mut tx, &ClientMessage::Pings(id)).await?;
+    Ok(PingSocket::stream(rx, tx))
+}
+
+pub(crate) async fn test_async(
+    config: Config,
+    server: Option<&str>,
+    latency_peers: &[Option<&str>],
+    msg: Msg,
+    session: Option<&watch::Sender<Option<Session>>>,
+    progress: Option<Progress>,
+    warn: Option<Msg>,
+) -> Result<RawResult, anyhow::Error> {
+    // Warnings are shown as messages unless they're handled separately
+    let warn = warn.unwrap_or_else(|| {
+        let msg = msg.clone();
+        Arc::new(move |warning| msg(&format!("Warning: {warning}")))
+    });
+
+    msg(&format!("Client version {} running", version()));
+
+    let unix = server.and_then(unix_path).map(Arc::<Path>::from);
+
+    if unix.is_some() {
+        if !latency_peers.is_empty() {
+            bail!("A latency peer can't be used with a Unix domain socket");
+        }
+        if config.icmp {
+            bail!("ICMP latency can't be measured over a Unix domain socket");
+        }
+        if config.dscp.is_some() {
+            bail!("DSCP marking can't be used with a Unix domain socket");
+        }
+    }
 
-    hello(&mut control_tx, &mut control_rx)
+    if config.proxy.is_some() {
+        if !latency_peers.is_empty() {
+            bail!("A latency peer can't be used when testing through a proxy");
+        }
+        if !(config.download || config.upload || config.bidirectional) {
+            bail!("The idle test can't be run through a proxy as it only measures latency");
+        }
+        warn("UDP can't pass through the proxy. Latency will be unavailable");
+    }
+
+    if config.icmp {
+        if config.proxy.is_some() {
+            bail!("ICMP latency can't be measured when testing through a proxy");
+        }
+        msg("Measuring latency with ICMP. Up and down latency will be unavailable");
+    }
+
+    // Whether pings can reach the server, which they can't through a proxy
+    let pings = config.proxy.is_none();
+
+    // Whether the clocks are synchronized with the server using pings
+    let udp_sync = pings && !config.icmp;
+
+    let idle = !(config.download || config.upload || config.bidirectional);
+
+    if config.ping_interval.is_zero() && idle {
+        bail!("Latency measurement can't be disabled for the idle test");
+    }
+
+    // The average throughput is measured from the end of the warmup until shortly before the load ends
+    let measured_until = throughput_end(config.load_duration);
+    if config.warmup.is_some_and(|warmup| warmup >= measured_until) {
+        bail!(
+            "The warmup must be shorter than {:.2} s, where the average throughput stops being measured",
+            measured_until.as_secs_f64()
+        );
+    }
+
+    if config
+        .upload_rate
+        .is_some_and(|rate| !rate.is_finite() || rate <= 0.0)
+    {
+        bail!("The upload rate must be positive");
+    }
+
+    if config
+        .total_rate
+        .is_some_and(|rate| !rate.is_finite() || rate <= 0.0)
+    {
+        bail!("The total rate must be positive");
+    }
+
+    if config.load_bytes == Some(0) {
+        bail!("The load size must be positive");
+    }
+
+    let (control, server_name, server) = connect_control(&config, server, &msg).await?;
+
+    if unix.is_some() {
+        msg(&format!("Connected to server {}", server_name));
+    } else {
+        msg(&format!("Connected to server {}", server));
+    }
+
+    let (mut control_rx, mut control_tx, protocol_version) = control_hello(control, || {
+        connect_again(unix.as_deref(), config.proxy, server)
+    })
+    .await
+    .context("Failed protocol handshake")?;
+
+    let server_version = server_info(&mut control_tx, &mut control_rx, protocol_version)
         .await
-        .context("Failed protocol handshake")?;
+        .context("Failed to get server info")?
+        .map(|info| info.version);
+
+    let request_start = Instant::now();
 
     send(&mut control_tx, &ClientMessage::NewClient).await?;
 
@@ -126,64 +604,161 @@ pub(crate) async fn test_async(
     let id = match reply {
         ServerMessage::NewClient(Some(id)) => id,
         ServerMessage::NewClient(None) => bail!("Server was unable to create client"),
+        ServerMessage::Rejected(reason) => bail!("Server rejected the client: {reason}"),
         _ => bail!("Unexpected message {:?}", reply),
     };
 
+    // Only used as the latency estimate when testing through a proxy
+    let control_rtt = request_start.elapsed();
+
+    if let Some(session) = session {
+        session.send_replace(Some(Session {
+            server,
+            proxy: config.proxy,
+            unix: unix.clone(),
+            id,
+            version: protocol_version,
+        }));
+    }
+
     let loading_streams: u32 = config.streams.try_into()?;
 
     let grace = config.grace_duration;
+    let initial_grace = config.initial_grace.unwrap_or(grace);
+    let final_grace = config.final_grace.unwrap_or(grace);
     let load_duration = config.load_duration;
     let ping_interval = config.ping_interval;
 
+    // A zero ping interval disables latency measurement during the test
+    let measure_pings = !ping_interval.is_zero() && pings;
+
     let loads = config.bidirectional as u32 + config.download as u32 + config.upload as u32;
 
-    let estimated_duration = load_duration * loads + grace * 2;
+    let estimated_duration = load_duration * loads + initial_grace + final_grace;
 
-    let mut peer = if let Some(peer) = latency_peer_server {
-        Some(connect_to_peer(config, server, peer, estimated_duration, msg.clone()).await?)
+    let test_duration = if loads == 0 {
+        initial_grace
     } else {
-        None
+        initial_grace + grace * (loads - 1) + final_grace + load_duration * loads
     };
 
-    let local_udp = if server.is_ipv6() {
-        SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0)
-    } else {
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)
+    // The grace after a test, which is longer or shorter after the last one
+    let grace_after = |later_tests: bool| if later_tests { grace } else { final_grace };
+
+    let interface_start = config.interface.map(iface::read).transpose()?;
+
+    // Reports how long a load took to transfer its bytes when limited by size
+    let report_transfer = |name: &str, start: Instant, end: Instant| {
+        if let Some(bytes) = config.load_bytes {
+            msg(&format!(
+                "{name} of {bytes} bytes took {:.2} s",
+                end.duration_since(start).as_secs_f64()
+            ));
+        }
     };
 
+    let selected: Vec<_> = [
+        (config.download, "download"),
+        (config.upload, "upload"),
+        (config.bidirectional, "bidirectional"),
+    ]
+    .into_iter()
+    .filter(|test| test.0)
+    .map(|test| test.1)
+    .collect();
+    if selected.is_empty() {
+        msg("Running idle test");
+    } else {
+        msg(&format!("Running tests: {}", selected.join(", ")));
+    }
+
+    let mut peers = Vec::new();
+    for &peer in latency_peers {
+        peers.push(connect_to_peer(config, server, peer, estimated_duration, msg.clone()).await?);
+    }
+
     let mut ping_index = 0;
 
-    let LatencyResult {
-        latency,
-        server_pong: pre_server_pong,
-        server_time: pre_server_time,
-        mut control_rx,
-        ..
-    } = measure_latency(
+    let (latency, pre_server_pong, pre_server_time, mut control_rx) = if config.icmp {
+        let latency = icmp::idle_latency(
+            Echo {
+                server: server.ip(),
+                id,
+            },
+            config.idle_latency_samples,
+            config.idle_latency_interval,
+            config.setup_timeout,
+        )
+        .await?;
+
+        msg(&format!(
+            "Idle latency to server {:.2} ms",
+            latency.as_secs_f64() * 1000.0
+        ));
+
+        // The clocks are synchronized with the first scheduled load instead
+        (latency, Duration::ZERO, 0, control_rx)
+    } else if !pings {
+        // The clocks are synchronized with the first scheduled load instead
+        (control_rtt, Duration::ZERO, 0, control_rx)
+    } else {
+        let LatencyResult {
+            latency,
+            server_pong,
+            server_time,
+            control_rx,
+            ..
+        } = measure_latency(
+            id,
+            &mut ping_index,
+            &mut control_tx,
+            control_rx,
+            server,
+            ping_socket(unix.as_deref(), id, server).await?,
+            setup_start,
+            config.ping_payload_size,
+            config.idle_latency_samples,
+            config.idle_latency_interval,
+            config.setup_timeout,
+        )
+        .await?;
+
+        msg(&format!(
+            "Idle latency to server {:.2} ms",
+            latency.as_secs_f64() * 1000.0
+        ));
+
+        (latency, server_pong, server_time, control_rx)
+    };
+
+    let icmp = config.icmp.then_some(Echo {
+        server: server.ip(),
         id,
-        &mut ping_index,
-        &mut control_tx,
-        control_rx,
-        server,
-        local_udp,
-        setup_start,
-    )
-    .await?;
+    });
 
-    msg(&format!(
-        "Idle latency to server {:.2} ms",
-        latency.as_secs_f64() * 1000.0
-    ));
+    let udp_socket = if !measure_pings {
+        None
+    } else if config.icmp {
+        Some(Arc::new(PingSocket::Udp(icmp::socket(server.ip())?)))
+    } else {
+        Some(Arc::new(ping_socket(unix.as_deref(), id, server).await?))
+    };
 
-    let udp_socket = Arc::new(net::UdpSocket::bind(local_udp).await?);
-    udp_socket.connect(server).await?;
-    let udp_socket2 = udp_socket.clone();
+    if let (Some(PingSocket::Udp(socket)), Some(dscp)) = (udp_socket.as_deref(), config.dscp) {
+        set_dscp(SockRef::from(socket), server.is_ipv6(), dscp)?;
+    }
 
-    let data = Arc::new(data());
+    let data = Arc::new(data(
+        config.chunk_size.unwrap_or(DEFAULT_WRITE_SIZE),
+        config.incompressible,
+    ));
 
     let state = Arc::new(State {
         downloads: Mutex::new(HashMap::new()),
         timeout: AtomicBool::new(false),
+        download_bytes: AtomicU64::new(0),
+        upload_bytes: AtomicU64::new(0),
+        latency: Arc::new(LiveLatency::default()),
     });
 
     let (state_tx, state_rx) = watch::channel((TestState::Setup, setup_start));
@@ -193,12 +768,22 @@ pub(crate) async fn test_async(
 
     let (upload_done_tx, mut upload_done_rx) = channel(config.streams as usize);
 
+    // Only the first loader failure is kept
+    let (loader_error_tx, mut loader_error_rx) = channel(1);
+
+    // Each load is paced separately, but both directions of the bidirectional test share a pacer
+    let pacer = || config.total_rate.map(|rate| Arc::new(Pacer::new(rate)));
+    let both_pacer = pacer();
+
     if config.upload {
         loader_count += config.streams;
         upload_loaders(
+            state.clone(),
             all_loaders.clone(),
             id,
+            protocol_version,
             server,
+            unix.clone(),
             0,
             config,
             Duration::ZERO,
@@ -206,15 +791,20 @@ pub(crate) async fn test_async(
             state_rx.clone(),
             TestState::LoadFromClient,
             upload_done_tx.clone(),
+            loader_error_tx.clone(),
+            pacer(),
         );
     }
 
     if config.bidirectional {
         loader_count += config.streams;
         upload_loaders(
+            state.clone(),
             all_loaders.clone(),
             id,
+            protocol_version,
             server,
+            unix.clone(),
             1,
             config,
             config.stream_stagger / 2,
@@ -222,6 +812,8 @@ pub(crate) async fn test_async(
             state_rx.clone(),
             TestState::LoadFromBoth,
             upload_done_tx.clone(),
+            loader_error_tx.clone(),
+            both_pacer.clone(),
         );
     }
 
@@ -231,12 +823,16 @@ pub(crate) async fn test_async(
             state.clone(),
             all_loaders.clone(),
             id,
+            protocol_version,
             server,
+            unix.clone(),
             2,
             config,
             setup_start,
             state_rx.clone(),
             TestState::LoadFromServer,
+            loader_error_tx.clone(),
+            pacer(),
         )
     });
 
@@ -246,19 +842,27 @@ pub(crate) async fn test_async(
             state.clone(),
             all_loaders.clone(),
             id,
+            protocol_version,
             server,
+            unix.clone(),
             3,
             config,
             setup_start,
             state_rx.clone(),
             TestState::LoadFromBoth,
+            loader_error_tx.clone(),
+            both_pacer.clone(),
         )
     });
 
     send(&mut control_tx, &ClientMessage::GetMeasurements).await?;
 
     // Wait for all loaders to setup
-    let _ = all_loaders.acquire_many(loader_count as u32).await?;
+    let _ = or_loader_error(
+        &mut loader_error_rx,
+        all_loaders.acquire_many(loader_count as u32),
+    )
+    .await??;
 
     let upload_semaphore = Arc::new(Semaphore::new(0));
     let upload_semaphore_ = upload_semaphore.clone();
@@ -267,14 +871,27 @@ pub(crate) async fn test_async(
 
     let (scheduled_load_tx, mut scheduled_load_rx) = channel(4);
 
+    let (coordinated_tx, mut coordinated_rx) = channel(1);
+
     let state_ = state.clone();
+    let measure_state = state_rx.clone();
     let measures = tokio::spawn(async move {
         let mut throughput = Vec::new();
         let mut latencies = Vec::new();
-        let overload_;
+        let mut diagnostic = None;
+        let mut overload_ = false;
+        let mut disconnect = None;
 
         loop {
-            let reply: ServerMessage = receive(&mut control_rx).await?;
+            let reply: ServerMessage = match receive(&mut control_rx).await {
+                Ok(reply) => reply,
+                // Keep what was measured if the server went away after the test ended
+                Err(error) if measure_state.borrow().0 >= TestState::End => {
+                    disconnect = Some(error);
+                    break;
+                }
+                Err(error) => return Err(error),
+            };
             match reply {
                 ServerMessage::MeasureStreamDone { stream, timeout } => {
                     if timeout {
@@ -297,6 +914,9 @@ pub(crate) async fn test_async(
                 ServerMessage::LatencyMeasures(measures) => {
                     latencies.extend(measures.into_iter());
                 }
+                ServerMessage::OverloadDiagnostic(message) => {
+                    diagnostic = Some(message);
+                }
                 ServerMessage::MeasurementsDone { overload } => {
                     overload_ = overload;
                     break;
@@ -310,45 +930,100 @@ pub(crate) async fn test_async(
                         .send(())
                         .map_err(|_| anyhow!("Failed to notify downloader"))?;
                 }
-                ServerMessage::ScheduledLoads { groups: _, time: _ } => {
-                    scheduled_load_tx.send(ScheduledLoads).await?
+                ServerMessage::ScheduledLoads { groups: _, time } => {
+                    scheduled_load_tx.send(ScheduledLoads { time }).await?
                 }
+                ServerMessage::Coordinated { clients } => coordinated_tx.send(Ok(clients)).await?,
+                ServerMessage::Rejected(reason) => coordinated_tx.send(Err(reason)).await?,
                 _ => bail!("Unexpected message {:?}", reply),
             };
         }
 
-        Ok((latencies, throughput, overload_, control_rx))
+        Ok((
+            latencies, throughput, overload_, diagnostic, disconnect, control_rx,
+        ))
     });
 
-    if let Some(peer) = peer.as_mut() {
+    if let Some(coordination) = config.coordination {
+        if protocol_version < 4 {
+            bail!("The server doesn't support coordinated tests");
+        }
+        msg(&format!(
+            "Waiting for {} clients to join group {}...",
+            coordination.clients, coordination.id
+        ));
+        send(
+            &mut control_tx,
+            &ClientMessage::Coordinate {
+                id: coordination.id,
+                clients: coordination.clients,
+            },
+        )
+        .await?;
+        let clients = coordinated_rx
+            .recv()
+            .await
+            .ok_or(anyhow!("Failed to receive"))?
+            .map_err(|reason| anyhow!("Server rejected joining the group: {reason}"))?;
+        msg(&format!("All {clients} clients of the group joined"));
+    }
+
+    for peer in &mut peers {
         peer.start().await?;
     }
 
     let ping_start_index = ping_index;
-    let ping_send = tokio::spawn(ping_send(
-        ping_index,
-        id,
-        state_rx.clone(),
-        setup_start,
-        udp_socket2.clone(),
-        ping_interval,
-        estimated_duration,
-    ));
+    let ping_send = udp_socket.clone().map(|udp_socket| {
+        tokio::spawn(ping_send(
+            ping_index,
+            id,
+            state_rx.clone(),
+            setup_start,
+            udp_socket,
+            ping_interval,
+            estimated_duration,
+            config.ping_payload_size,
+            config.ping_jitter,
+            icmp,
+            Some(state.latency.clone()),
+        ))
+    });
 
-    let ping_recv = tokio::spawn(ping_recv(
-        state_rx.clone(),
-        setup_start,
-        udp_socket2.clone(),
-        ping_interval,
-        estimated_duration,
-    ));
+    let ping_recv = udp_socket.map(|udp_socket| {
+        tokio::spawn(ping_recv(
+            state_rx.clone(),
+            setup_start,
+            udp_socket,
+            ping_interval,
+            estimated_duration,
+            icmp,
+            Some(state.latency.clone()),
+        ))
+    });
 
     time::sleep(Duration::from_millis(50)).await;
 
     let start = Instant::now();
 
+    let progress_reporter = progress.map(|progress| {
+        tokio::spawn(report_progress(
+            progress,
+            state.clone(),
+            state_rx.clone(),
+            config.throughput_interval,
+            test_duration,
+        ))
+    });
+
+    // Stop reporting progress when the test ends, including on errors
+    let _stop_progress = OnDrop(|| {
+        if let Some(reporter) = &progress_reporter {
+            reporter.abort();
+        }
+    });
+
     state_tx.send((TestState::Grace1, start))?;
-    time::sleep(grace).await;
+    time::sleep(initial_grace).await;
 
     let load_delay_pure = Duration::from_millis(50);
     let load_delay = (load_delay_pure + latency / 2).as_micros() as u64;
@@ -363,6 +1038,9 @@ pub(crate) async fn test_async(
 
     let mut test_data = Vec::new();
 
+    // The server and client time of the first load start
+    let mut first_load = None;
+
     if let Some((semaphore, _)) = download.as_ref() {
         send(
             &mut control_tx,
@@ -372,22 +1050,28 @@ pub(crate) async fn test_async(
             },
         )
         .await?;
-        scheduled_load_rx
+        let scheduled = scheduled_load_rx
             .recv()
             .await
             .ok_or(anyhow!("Failed to receive"))?;
         let start = start_time()?;
+        first_load.get_or_insert((scheduled.time, start));
         state_tx.send((TestState::LoadFromServer, start))?;
         msg(&format!("Testing download..."));
-        let _ = semaphore.acquire_many(loading_streams).await?;
+        let _ = or_loader_error(
+            &mut loader_error_rx,
+            semaphore.acquire_many(loading_streams),
+        )
+        .await??;
         let end = Instant::now();
+        report_transfer("Download", start, end);
         test_data.push(TestData {
             start: start.duration_since(setup_start),
             end: end.duration_since(setup_start),
             kind: TestKind::Download,
         });
         state_tx.send((TestState::Grace2, end))?;
-        time::sleep(grace).await;
+        time::sleep(grace_after(config.upload || config.bidirectional)).await;
     }
 
     if config.upload {
@@ -399,18 +1083,18 @@ pub(crate) async fn test_async(
             },
         )
         .await?;
-        scheduled_load_rx
+        let scheduled = scheduled_load_rx
             .recv()
             .await
             .ok_or(anyhow!("Failed to receive"))?;
         let start = start_time()?;
+        first_load.get_or_insert((scheduled.time, start));
         state_tx.send((TestState::LoadFromClient, start))?;
         msg(&format!("Testing upload..."));
 
         for _ in 0..config.streams {
-            let stream = upload_done_rx
-                .recv()
-                .await
+            let stream = or_loader_error(&mut loader_error_rx, upload_done_rx.recv())
+                .await?
                 .ok_or(anyhow!("Expected stream"))?;
             send(&mut control_tx, &ClientMessage::LoadComplete { stream }).await?;
         }
@@ -418,6 +1102,7 @@ pub(crate) async fn test_async(
         let _ = upload_semaphore.acquire_many(loading_streams).await?;
 
         let end = Instant::now();
+        report_transfer("Upload", start, end);
         test_data.push(TestData {
             start: start.duration_since(setup_start),
             end: end.duration_since(setup_start),
@@ -425,7 +1110,7 @@ pub(crate) async fn test_async(
         });
 
         state_tx.send((TestState::Grace3, end))?;
-        time::sleep(grace).await;
+        time::sleep(grace_after(config.bidirectional)).await;
     }
 
     if let Some((semaphore, _)) = both_download.as_ref() {
@@ -437,26 +1122,31 @@ pub(crate) async fn test_async(
             },
         )
         .await?;
-        scheduled_load_rx
+        let scheduled = scheduled_load_rx
             .recv()
             .await
             .ok_or(anyhow!("Failed to receive"))?;
         let start = start_time()?;
+        first_load.get_or_insert((scheduled.time, start));
         state_tx.send((TestState::LoadFromBoth, start))?;
         msg(&format!("Testing both download and upload..."));
 
         for _ in 0..config.streams {
-            let stream = upload_done_rx
-                .recv()
-                .await
+            let stream = or_loader_error(&mut loader_error_rx, upload_done_rx.recv())
+                .await?
                 .ok_or(anyhow!("Expected stream"))?;
             send(&mut control_tx, &ClientMessage::LoadComplete { stream }).await?;
         }
 
-        let _ = semaphore.acquire_many(loading_streams).await?;
+        let _ = or_loader_error(
+            &mut loader_error_rx,
+            semaphore.acquire_many(loading_streams),
+        )
+        .await??;
         let _ = both_upload_semaphore.acquire_many(loading_streams).await?;
 
         let end = Instant::now();
+        report_transfer("Bidirectional test", start, end);
         test_data.push(TestData {
             start: start.duration_since(setup_start),
             end: end.duration_since(setup_start),
@@ -464,59 +1154,103 @@ pub(crate) async fn test_async(
         });
 
         state_tx.send((TestState::Grace4, end))?;
-        time::sleep(grace).await;
+        time::sleep(final_grace).await;
     }
 
     state_tx.send((TestState::End, Instant::now()))?;
 
-    if let Some(peer) = peer.as_mut() {
+    for peer in &mut peers {
         peer.stop().await?;
     }
 
     // Wait for pings to return
-    time::sleep(Duration::from_millis(500)).await;
+    if measure_pings {
+        time::sleep(Duration::from_millis(500)).await;
+    }
     state_tx.send((TestState::EndPingRecv, Instant::now()))?;
 
-    let peer = if let Some(peer) = peer {
-        Some(
-            peer.complete()
-                .await
-                .context("Failed to wait for peer completion")?,
-        )
-    } else {
-        None
+    let interface_counters = match (config.interface, interface_start) {
+        (Some(interface), Some(start)) => Some(iface::delta(&start, &iface::read(interface)?)),
+        _ => None,
     };
 
+    let mut peer_results = Vec::new();
+    for peer in peers {
+        let name = peer.name.clone();
+        let result = peer
+            .complete()
+            .await
+            .context("Failed to wait for peer completion")?;
+        peer_results.push((name, result));
+    }
+
     let duration = start.elapsed();
 
-    let (pings_sent, mut ping_index) = ping_send.await??;
-    let mut pongs = ping_recv.await??;
+    let (pings_sent, mut ping_index) = match ping_send {
+        Some(ping_send) => ping_send.await??,
+        None => (Vec::new(), ping_index),
+    };
+    let mut pongs = match ping_recv {
+        Some(ping_recv) => ping_recv.await??,
+        None => Vec::new(),
+    };
 
-    send(&mut control_tx, &ClientMessage::StopMeasurements).await?;
+    let stop = send(&mut control_tx, &ClientMessage::StopMeasurements).await;
 
-    let (mut latencies, throughput, server_overload, control_rx) = measures.await??;
+    let (mut latencies, throughput, server_overload, server_diagnostic, disconnect, control_rx) =
+        measures.await??;
 
-    let LatencyResult {
-        server_pong: post_server_pong,
-        server_time: post_server_time,
-        ..
-    } = measure_latency(
-        id,
-        &mut ping_index,
-        &mut control_tx,
-        control_rx,
-        server,
-        local_udp,
-        setup_start,
-    )
-    .await?;
+    let partial = disconnect.is_some();
 
-    send(&mut control_tx, &ClientMessage::Done).await?;
+    if let Some(error) = disconnect {
+        warn(&format!(
+            "Lost the connection to the server before all measurements were received \
+            ({error:#}). The result is partial."
+        ));
+    } else {
+        stop?;
+    }
 
-    let server_time = post_server_time.wrapping_sub(pre_server_time);
-    let client_time = post_server_pong.saturating_sub(pre_server_pong);
-    let client_time_micros = client_time.as_micros() as f64;
-    let ratio = client_time_micros / server_time as f64;
+    let (pre_server_pong, pre_server_time, ratio) = if !udp_sync || partial {
+        if !partial {
+            send(&mut control_tx, &ClientMessage::Done).await?;
+        }
+
+        // Assume the clocks run at the same rate and align them at the first load start
+        let (server_time, start) = first_load.ok_or(anyhow!("No load was scheduled"))?;
+        let client_time = start.duration_since(setup_start).as_micros() as u64;
+        (Duration::ZERO, server_time.saturating_sub(client_time), 1.0)
+    } else {
+        let LatencyResult {
+            server_pong: post_server_pong,
+            server_time: post_server_time,
+            ..
+        } = measure_latency(
+            id,
+            &mut ping_index,
+            &mut control_tx,
+            control_rx,
+            server,
+            ping_socket(unix.as_deref(), id, server).await?,
+            setup_start,
+            config.ping_payload_size,
+            config.idle_latency_samples,
+            config.idle_latency_interval,
+            config.setup_timeout,
+        )
+        .await?;
+
+        send(&mut control_tx, &ClientMessage::Done).await?;
+
+        let server_time = post_server_time.wrapping_sub(pre_server_time);
+        let client_time = post_server_pong.saturating_sub(pre_server_pong);
+        let client_time_micros = client_time.as_micros() as f64;
+        (
+            pre_server_pong,
+            pre_server_time,
+            client_time_micros / server_time as f64,
+        )
+    };
 
     let to_client_time = |server_time: u64| -> u64 {
         let time = server_time.wrapping_sub(pre_server_time);
@@ -524,37 +1258,83 @@ pub(crate) async fn test_async(
         (pre_server_pong.as_micros() as u64).saturating_add(time)
     };
 
-    let server_overload = server_overload || peer.as_ref().map(|p| p.0).unwrap_or_default();
+    let server_overload =
+        server_overload || peer_results.iter().any(|(_, (overload, _))| *overload);
 
-    let peer_latencies = peer.map(|(_, latencies)| {
-        latencies
-            .into_iter()
-            .enumerate()
-            .map(|(i, p)| RawPing {
-                index: i as u64,
-                sent: Duration::from_micros(to_client_time(p.sent)),
-                latency: p.latency,
-            })
-            .collect::<Vec<_>>()
-    });
+    let peer_latencies = peer_results
+        .into_iter()
+        .map(|(name, (_, latencies))| {
+            let pings = latencies
+                .into_iter()
+                .enumerate()
+                .map(|(i, p)| RawPing {
+                    index: i as u64,
+                    sent: Duration::from_micros(to_client_time(p.sent)),
+                    latency: p.latency,
+                })
+                .collect::<Vec<_>>();
+            (name, pings)
+        })
+        .collect();
 
-    let download_bytes = wait_on_download_loaders(download).await?;
-    let both_download_bytes = wait_on_download_loaders(both_download).await?;
+    let download_bytes =
+        or_loader_error(&mut loader_error_rx, wait_on_download_loaders(download)).await??;
+    let both_download_bytes = or_loader_error(
+        &mut loader_error_rx,
+        wait_on_download_loaders(both_download),
+    )
+    .await??;
 
     latencies.sort_by_key(|d| d.index);
-    pongs.sort_by_key(|d| d.0.index);
+    latencies.dedup_by_key(|d| d.index);
+    pongs.sort_by_key(|d| (d.0.index, d.1));
+
+    // The network may duplicate packets, which results in multiple pongs for a ping.
+    // Only the earliest is kept.
+    let pongs_received = pongs.len();
+    pongs.dedup_by_key(|d| d.0.index);
+    let duplicate_pings = (pongs_received - pongs.len()) as u64;
+
+    // Count pings which arrived at the server before they were sent or after
+    // the pong was received, which indicates the clocks drifted during the test.
+    let mut clock_samples = 0;
+    let mut clock_errors = 0;
+
     let pings: Vec<_> = pings_sent
         .into_iter()
         .enumerate()
         .map(|(index, sent)| {
             let index = index as u64 + ping_start_index;
+
+            // ICMP pings only have the round-trip latency and lost pings have no direction
+            if config.icmp {
+                let latency = pongs
+                    .binary_search_by_key(&index, |e| e.0.index)
+                    .ok()
+                    .map(|ping| RawLatency {
+                        total: Some(pongs[ping].1.saturating_sub(sent)),
+                        up: Duration::ZERO,
+                    });
+                return RawPing {
+                    index,
+                    sent,
+                    latency,
+                };
+            }
+
             let mut latency = latencies
                 .binary_search_by_key(&index, |e| e.index)
                 .ok()
-                .map(|ping| RawLatency {
-                    total: None,
-                    up: Duration::from_micros(to_client_time(latencies[ping].time))
-                        .saturating_sub(sent),
+                .map(|ping| {
+                    let at_server = Duration::from_micros(to_client_time(latencies[ping].time));
+                    clock_samples += 1;
+                    if at_server + CLOCK_TOLERANCE < sent {
+                        clock_errors += 1;
+                    }
+                    RawLatency {
+                        total: None,
+                        up: at_server.saturating_sub(sent),
+                    }
                 });
 
             latency.as_mut().map(|latency| {
@@ -564,6 +1344,9 @@ pub(crate) async fn test_async(
                     .map(|ping| {
                         let total = pongs[ping].1.saturating_sub(sent);
                         latency.total = Some(total);
+                        if latency.up > total + CLOCK_TOLERANCE {
+                            clock_errors += 1;
+                        }
                         // Ensure `up` stays below `total`
                         latency.up = latency.up.min(total);
                     });
@@ -638,33 +1421,72 @@ pub(crate) async fn test_async(
         stagger: config.stream_stagger,
         load_duration: config.load_duration,
         grace_duration: config.grace_duration,
+        initial_grace: config.initial_grace,
+        final_grace: config.final_grace,
         ping_interval: config.ping_interval,
         bandwidth_interval: config.throughput_interval,
+        ping_payload_size: config.ping_payload_size as u64,
+        incompressible: config.incompressible,
+        ping_jitter: config.ping_jitter,
+        idle_latency_samples: config.idle_latency_samples,
+        idle_latency_interval: config.idle_latency_interval,
+        warmup: config.warmup,
+        upload_rate: config.upload_rate,
+        icmp: config.icmp,
+        load_bytes: config.load_bytes,
+        total_rate: config.total_rate,
+        dscp: config.dscp,
     };
 
     if server_overload {
-        msg(&format!(
-            "Warning: Server overload detected during test. Result should be discarded."
+        warn("Server overload detected during test. Result should be discarded.");
+    }
+
+    if let Some(diagnostic) = server_diagnostic.as_ref() {
+        msg(&format!("Note: {diagnostic}"));
+    }
+
+    let clock_suspect = clock_errors * 100 > clock_samples;
+
+    if clock_suspect {
+        warn("Clock drift detected during test. Upload and download latency may be inaccurate.");
+    }
+
+    if duplicate_pings > 0 {
+        warn(&format!(
+            "{duplicate_pings} duplicated ping replies received. The network may be duplicating packets."
+        ));
+    }
+
+    if let Some(counters) = interface_counters
+        .as_ref()
+        .filter(|counters| !counters.is_zero())
+    {
+        warn(&format!(
+            "{}. Some loss may be at the local interface rather than along the path.",
+            counters.describe()
         ));
     }
 
     let load_termination_timeout = state.timeout.load(Ordering::SeqCst);
 
     if load_termination_timeout {
-        msg(&format!(
-            "Warning: Load termination timed out. There may be residual untracked traffic in the background."
-        ));
+        warn("Load termination timed out. There may be residual untracked traffic in the background.");
     }
 
     let start = start.duration_since(setup_start);
 
-    let raw_result = RawResult {
+    let mut raw_result = RawResult {
         version: RawHeader::default().version,
         generated_by: format!("Crusader {}", version()),
+        server_version,
+        server: server_name,
+        label: None,
         config: raw_config,
         ipv6: server.is_ipv6(),
         load_termination_timeout,
         server_overload,
+        server_diagnostic,
         server_latency: latency,
         start,
         duration,
@@ -672,8 +1494,20 @@ pub(crate) async fn test_async(
         pings,
         peer_pings: peer_latencies,
         test_data,
+        clock_suspect,
+        duplicate_pings,
+        coordination: config.coordination,
+        interface_counters,
+        partial,
+        missing_test_details: false,
+        missing_one_way_latency: false,
+        missing_loss_direction: false,
     };
 
+    if let Some(max_points) = config.max_throughput_points {
+        raw_result.downsample(max_points);
+    }
+
     Ok(raw_result)
 }
 
@@ -681,28 +1515,61 @@ pub fn save_raw(
     result: &RawResult,
     name: &str,
     root_path: &Path,
+    compression: Compression,
 ) -> Result<PathBuf, anyhow::Error> {
     std::fs::create_dir_all(root_path)?;
     let name = unique(name, "crr", root_path);
     let path = root_path.join(&name);
-    result.save(&path)?;
+    result.save_with(&path, compression)?;
     Ok(path)
 }
 
 fn setup_loaders(
     id: u64,
+    version: u64,
     server: SocketAddr,
-    count: u64,
-) -> Vec<JoinHandle<Result<Framed<TcpStream, LengthDelimitedCodec>, anyhow::Error>>> {
-    (0..count)
+    unix: Option<Arc<Path>>,
+    config: Config,
+) -> Vec<JoinHandle<Result<Framed<transport::Stream, LengthDelimitedCodec>, anyhow::Error>>> {
+    (0..config.streams)
         .map(|_| {
+            let unix = unix.clone();
             tokio::spawn(async move {
-                let stream = TcpStream::connect(server)
+                if let Some(path) = unix {
+                    let mut stream = Framed::new(connect_unix(&path).await?, codec());
+                    hello_combined(&mut stream, version).await?;
+                    send(&mut stream, &ClientMessage::Associate(id)).await?;
+                    return Ok(stream);
+                }
+
+                let addr = config.proxy.map_or(server, |proxy| proxy.address);
+                let socket = if addr.is_ipv4() {
+                    TcpSocket::new_v4()?
+                } else {
+                    TcpSocket::new_v6()?
+                };
+                if let Some(dscp) = config.dscp {
+                    set_dscp(SockRef::from(&socket), addr.is_ipv6(), dscp)?;
+                }
+                // The buffer sizes must be set before connecting for the TCP window scale to be picked.
+                if let Some(size) = config.socket_buffer_size {
+                    socket
+                        .set_send_buffer_size(size)
+                        .context("Failed to set socket send buffer size")?;
+                    socket
+                        .set_recv_buffer_size(size)
+                        .context("Failed to set socket receive buffer size")?;
+                }
+                let mut stream = socket
+                    .connect(addr)
                     .await
                     .context("Failed connect to server for throughput connection")?;
+                if let Some(proxy) = config.proxy {
+                    proxy.handshake(&mut stream, server).await?;
+                }
                 stream.set_nodelay(true)?;
-                let mut stream = Framed::new(stream, codec());
-                hello_combined(&mut stream).await?;
+                let mut stream = Framed::new(transport::Stream::from(stream), codec());
+                hello_combined(&mut stream, version).await?;
                 send(&mut stream, &ClientMessage::Associate(id)).await?;
 
                 Ok(stream)
@@ -711,10 +1578,43 @@ fn setup_loaders(
         .collect()
 }
 
+/// Waits on `future`, returning an error instead if a loader fails first.
+async fn or_loader_error<T>(
+    loader_error: &mut Receiver<anyhow::Error>,
+    future: impl Future<Output = T>,
+) -> Result<T, anyhow::Error> {
+    select_biased! {
+        error = loader_error.recv().fuse() => {
+            let error = error.unwrap_or_else(|| anyhow!("Loader stopped"));
+            Err(error.context("Lost connection to server during load phase"))
+        },
+        result = future.fuse() => Ok(result),
+    }
+}
+
+/// Spawns a loader task which reports its error on `loader_error` if it fails.
+fn spawn_loader<T: Send + 'static>(
+    loader_error: Sender<anyhow::Error>,
+    task: impl Future<Output = Result<T, anyhow::Error>> + Send + 'static,
+) -> JoinHandle<Result<T, anyhow::Error>> {
+    tokio::spawn(async move {
+        match task.await {
+            Ok(value) => Ok(value),
+            Err(error) => {
+                let _ = loader_error.try_send(error);
+                bail!("Loader failed")
+            }
+        }
+    })
+}
+
 fn upload_loaders(
+    test_state: Arc<State>,
     all_loaders: Arc<Semaphore>,
     id: u64,
+    version: u64,
     server: SocketAddr,
+    unix: Option<Arc<Path>>,
     group: u32,
     config: Config,
     stagger_offset: Duration,
@@ -722,15 +1622,22 @@ fn upload_loaders(
     state_rx: watch::Receiver<(TestState, Instant)>,
     state: TestState,
     done: Sender<TestStream>,
+    loader_error: Sender<anyhow::Error>,
+    pacer: Option<Arc<Pacer>>,
 ) {
-    let loaders = setup_loaders(id, server, config.streams);
+    let loaders = setup_loaders(id, version, server, unix, config);
+
+    let transferred = Arc::new(AtomicU64::new(0));
 
     for (i, loader) in loaders.into_iter().enumerate() {
         let mut state_rx = state_rx.clone();
         let data = data.clone();
+        let transferred = transferred.clone();
+        let pacer = pacer.clone();
         let all_loaders = all_loaders.clone();
         let done = done.clone();
-        tokio::spawn(async move {
+        let test_state = test_state.clone();
+        spawn_loader(loader_error.clone(), async move {
             let mut stream = loader.await??;
 
             let delay = config.stream_stagger * i as u32 + stagger_offset;
@@ -753,20 +1660,21 @@ fn upload_loaders(
             let reply: ServerMessage = receive(&mut stream).await?;
             match reply {
                 ServerMessage::WaitingForLoad => (),
-                _ => panic!("Unexpected message {:?}", reply),
+                ServerMessage::Rejected(reason) => bail!("Server rejected the test: {reason}"),
+                _ => bail!("Unexpected message {:?}", reply),
             };
 
             send(&mut stream, &ClientMessage::SendByte).await?;
 
             // Wait for a pending read byte
             {
-                let mut stream_rx = stream.get_mut().split().0;
+                let stream = stream.get_mut();
                 loop {
-                    let _ = stream_rx.read(&mut []).await?;
-                    match time::timeout(Duration::from_millis(10), stream_rx.peek(&mut [0])).await {
+                    let _ = stream.read(&mut []).await?;
+                    match time::timeout(Duration::from_millis(10), stream.peek(&mut [0])).await {
                         Ok(Ok(1)) => break,
                         Err(_) | Ok(Ok(_)) => (),
-                        Ok(Err(err)) => panic!("{:?}", err),
+                        Ok(Err(err)) => return Err(err.into()),
                     }
                 }
             }
@@ -777,13 +1685,18 @@ fn upload_loaders(
 
             time::sleep_until(start).await;
 
+            let stream_pacer = config.upload_rate.map(Pacer::new);
+            let pacers: Vec<_> = stream_pacer.iter().chain(pacer.as_deref()).collect();
+
             write_data(
                 stream.into_inner(),
                 data.as_ref(),
                 start + config.load_duration,
+                Some(&test_state.upload_bytes),
+                &pacers,
+                config.load_bytes.map(|limit| (&*transferred, limit)),
             )
-            .await
-            .unwrap();
+            .await?;
 
             done.send(test_stream).await?;
             Ok::<(), anyhow::Error>(())
@@ -814,18 +1727,24 @@ fn download_loaders(
     state: Arc<State>,
     all_loaders: Arc<Semaphore>,
     id: u64,
+    version: u64,
     server: SocketAddr,
+    unix: Option<Arc<Path>>,
     group: u32,
     config: Config,
     setup_start: Instant,
     state_rx: watch::Receiver<(TestState, Instant)>,
     test_state: TestState,
+    loader_error: Sender<anyhow::Error>,
+    pacer: Option<Arc<Pacer>>,
 ) -> (
     Arc<Semaphore>,
     Vec<JoinHandle<Result<Vec<(u64, u64)>, anyhow::Error>>>,
 ) {
     let semaphore = Arc::new(Semaphore::new(0));
-    let loaders = setup_loaders(id, server, config.streams);
+    let loaders = setup_loaders(id, version, server, unix, config);
+
+    let transferred = Arc::new(AtomicU64::new(0));
 
     let loaders = loaders
         .into_iter()
@@ -835,12 +1754,13 @@ fn download_loaders(
             let state = state.clone();
             let semaphore = semaphore.clone();
             let all_loaders = all_loaders.clone();
+            let transferred = transferred.clone();
+            let pacer = pacer.clone();
 
-            tokio::spawn(async move {
+            spawn_loader(loader_error.clone(), async move {
                 let mut stream = loader.await??;
 
-                let mut buffer = Vec::with_capacity(512 * 1024);
-                buffer.extend((0..buffer.capacity()).map(|_| 0));
+                let mut buffer = vec![0; config.chunk_size.unwrap_or(DEFAULT_READ_SIZE)];
 
                 let delay = config.stream_stagger * i as u32;
 
@@ -862,7 +1782,10 @@ fn download_loaders(
                 let reply: ServerMessage = receive(&mut stream).await?;
                 match reply {
                     ServerMessage::WaitingForByte => (),
-                    _ => panic!("Unexpected message {:?}", reply),
+                    ServerMessage::Rejected(reason) => {
+                        bail!("Server rejected the test: {reason}")
+                    }
+                    _ => bail!("Unexpected message {:?}", reply),
                 };
 
                 stream.get_mut().write_u8(1).await?;
@@ -870,7 +1793,7 @@ fn download_loaders(
                 let reply: ServerMessage = receive(&mut stream).await?;
                 match reply {
                     ServerMessage::WaitingForLoad => (),
-                    _ => panic!("Unexpected message {:?}", reply),
+                    _ => bail!("Unexpected message {:?}", reply),
                 };
 
                 let stream = stream.into_inner();
@@ -918,6 +1841,9 @@ fn download_loaders(
                     bytes,
                     start + MEASURE_DELAY + config.load_duration,
                     reading_done_rx,
+                    Some(&state.download_bytes),
+                    config.load_bytes.map(|limit| (&*transferred, limit)),
+                    pacer.as_deref(),
                 )
                 .await?;
 
@@ -958,65 +1884,173 @@ pub(crate) fn unique(name: &str, ext: &str, root_path: &Path) -> String {
     }
 }
 
-pub fn test(
+/// Runs a test and returns the raw result without printing or saving anything.
+pub fn run_test(
+    config: Config,
+    host: Option<&str>,
+    latency_peers: &[Option<&str>],
+    msg: Arc<dyn Fn(&str) + Send + Sync>,
+) -> Result<RawResult, String> {
+    run_test_with_progress(config, host, latency_peers, msg, None)
+}
+
+/// Like `run_test`, but calls `progress` with live throughput samples while the test runs.
+pub fn run_test_with_progress(
     config: Config,
-    plot: PlotConfig,
     host: Option<&str>,
-    latency_peer_server: Option<Option<&str>>,
-    out_name: &str,
-) -> Result<(), anyhow::Error> {
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let result = rt.block_on(test_async(
+    latency_peers: &[Option<&str>],
+    msg: Arc<dyn Fn(&str) + Send + Sync>,
+    progress: Option<Progress>,
+) -> Result<RawResult, String> {
+    let rt = tokio::runtime::Runtime::new().map_err(|error| error.to_string())?;
+    rt.block_on(test_async(
         config,
         host,
-        latency_peer_server,
-        Arc::new(|msg| println!("{}", with_time(msg))),
-    ));
+        latency_peers,
+        msg,
+        None,
+        progress,
+        None,
+    ))
+    .map_err(|error| format!("{:?}", error))
+}
+
+/// Runs a test, stopping the loads on the server if interrupted by Ctrl-C.
+fn run_test_interruptible(
+    config: Config,
+    host: Option<&str>,
+    latency_peers: &[Option<&str>],
+    msg: Msg,
+    progress: Option<Progress>,
+    warn: Option<Msg>,
+) -> Result<RawResult, anyhow::Error> {
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async {
+        let (session_tx, session_rx) = watch::channel(None);
+
+        let mut test = Box::pin(
+            test_async(
+                config,
+                host,
+                latency_peers,
+                msg.clone(),
+                Some(&session_tx),
+                progress,
+                warn,
+            )
+            .fuse(),
+        );
+
+        select! {
+            result = test => result,
+            result = signal::ctrl_c().fuse() => {
+                result.context("Failed to listen for Ctrl-C")?;
+                msg("Interrupted, stopping the test");
+                let session = session_rx.borrow().clone();
+                if let Some(session) = session {
+                    time::timeout(Duration::from_secs(1), abort_session(session)).await.ok();
+                }
+                bail!("Test interrupted")
+            },
+        }
+    })
+}
+
+pub fn test(
+    config: Config,
+    plot: PlotConfig,
+    host: Option<&str>,
+    latency_peers: &[Option<&str>],
+    save: Option<(&str, &Path)>,
+    compression: Compression,
+    json: bool,
+    tui: bool,
+) -> Result<TestResult, anyhow::Error> {
+    let tui = tui.then(|| Arc::new(Tui::default()));
+    let msg: Msg = match tui.clone() {
+        Some(tui) => Arc::new(move |msg| tui.msg(msg)),
+        // Keep stdout clean for the JSON summary
+        None => Arc::new(move |msg| {
+            if json {
+                eprintln!("{}", with_time(msg));
+            } else {
+                println!("{}", with_time(msg));
+            }
+        }),
+    };
+    let progress = tui
+        .clone()
+        .map(|tui| -> Progress { Arc::new(move |sample| tui.progress(sample)) });
+    let warn = tui
+        .clone()
+        .map(|tui| -> Msg { Arc::new(move |warning| tui.warning(warning)) });
+    let result = run_test_interruptible(config, host, latency_peers, msg.clone(), progress, warn);
+    if let Some(tui) = &tui {
+        tui.finish();
+    }
     let result = match result {
         Ok(result) => result,
         Err(error) => {
-            println!("{}", with_time(&format!("Client failed")));
+            msg("Client failed");
             return Err(error);
         }
     };
-    let out_name = timed(out_name);
     let test_result = result.to_test_result();
-    print!("\n{}", test_result.summary()?);
-    println!("{}", with_time("Writing data..."));
-    let path = Path::new("crusader-results");
-    let raw = save_raw(&result, &out_name, path)?;
-    println!(
-        "{}",
-        with_time(&format!("Saved raw data as {}", raw.display()))
-    );
-    let plot = save_graph(&plot, &test_result, &out_name, path)?;
-    println!(
-        "{}",
-        with_time(&format!("Saved plot as {}", path.join(plot).display()))
-    );
-    Ok(())
+    if json {
+        eprint!("\n{}", test_result.summary()?);
+    } else {
+        print!("\n{}", test_result.summary()?);
+    }
+    if let Some((out_name, path)) = save {
+        let out_name = timed(out_name);
+        msg("Writing data...");
+        let raw = save_raw(&result, &out_name, path, compression)?;
+        msg(&format!("Saved raw data as {}", raw.display()));
+        let plot = save_graph(&plot, &test_result, &out_name, path)?;
+        msg(&format!("Saved plot as {}", path.join(plot).display()));
+    }
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&test_result.json_summary())
+                .context("Failed to serialize summary")?
+        );
+    }
+    Ok(test_result)
 }
 
 pub fn test_callback(
     config: Config,
     host: Option<&str>,
-    latency_peer_server: Option<Option<&str>>,
+    latency_peers: &[Option<&str>],
     msg: Arc<dyn Fn(&str) + Send + Sync>,
+    progress: Option<Progress>,
     done: Box<dyn FnOnce(Option<Result<RawResult, String>>) + Send>,
 ) -> oneshot::Sender<()> {
     let (tx, rx) = oneshot::channel();
     let host = host.map(|host| host.to_string());
-    let latency_peer_server = latency_peer_server.map(|host| host.map(|host| host.to_string()));
+    let latency_peers: Vec<_> = latency_peers
+        .iter()
+        .map(|host| host.map(|host| host.to_string()))
+        .collect();
     thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
 
         done(rt.block_on(async move {
+            let (session_tx, session_rx) = watch::channel(None);
+
             let mut result = task::spawn(async move {
                 test_async(
                     config,
                     host.as_deref(),
-                    latency_peer_server.as_ref().map(|host| host.as_deref()),
+                    &latency_peers
+                        .iter()
+                        .map(|host| host.as_deref())
+                        .collect::<Vec<_>>(),
                     msg,
+                    Some(&session_tx),
+                    progress,
+                    None,
                 )
                 .await
                 .map_err(|error| format!("{:?}", error))
@@ -1029,6 +2063,10 @@ pub fn test_callback(
                 },
                 result = rx.fuse() => {
                     result.ok();
+                    let session = session_rx.borrow().clone();
+                    if let Some(session) = session {
+                        time::timeout(Duration::from_secs(1), abort_session(session)).await.ok();
+                    }
                     None
                 },
             }
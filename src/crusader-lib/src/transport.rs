@@ -0,0 +1,358 @@
+//! Connections to the server over TCP, or over a Unix domain socket for local benchmarking.
+//!
+//! Unix domain sockets have no IP address, so the loopback address stands in for it where one
+//! is needed. They have no UDP either, so pings are carried as frames on a separate stream.
+
+#[cfg(feature = "client")]
+use bytes::Bytes;
+#[cfg(feature = "client")]
+use futures::{SinkExt, StreamExt};
+#[cfg(feature = "client")]
+use std::path::Path;
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+#[cfg(feature = "client")]
+use tokio::sync::Mutex;
+#[cfg(unix)]
+use tokio::{
+    io::Interest,
+    net::{unix, UnixStream},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{tcp, TcpStream, UdpSocket},
+};
+#[cfg(feature = "client")]
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+pub(crate) enum ReadHalf {
+    Tcp(tcp::OwnedReadHalf),
+    #[cfg(unix)]
+    Unix(unix::OwnedReadHalf),
+}
+
+pub(crate) enum WriteHalf {
+    Tcp(tcp::OwnedWriteHalf),
+    #[cfg(unix)]
+    Unix(unix::OwnedWriteHalf),
+}
+
+/// Sends and receives latency pings, as UDP packets or as frames on a stream.
+pub(crate) enum PingSocket {
+    Udp(UdpSocket),
+    #[cfg(feature = "client")]
+    Stream(Box<PingStream>),
+}
+
+#[cfg(feature = "client")]
+pub(crate) struct PingStream {
+    rx: Mutex<FramedRead<ReadHalf, LengthDelimitedCodec>>,
+    tx: Mutex<FramedWrite<WriteHalf, LengthDelimitedCodec>>,
+}
+
+macro_rules! each {
+    ($value:expr, $type:ident, $inner:ident => $body:expr) => {
+        match $value {
+            $type::Tcp($inner) => $body,
+            #[cfg(unix)]
+            $type::Unix($inner) => $body,
+        }
+    };
+}
+
+/// Returns the path of a Unix domain socket if `server` names one instead of a host.
+#[cfg(feature = "client")]
+pub(crate) fn unix_path(server: &str) -> Option<&Path> {
+    server.contains('/').then(|| Path::new(server))
+}
+
+/// The address standing in for a Unix domain socket.
+fn unix_addr() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)
+}
+
+#[cfg(all(feature = "client", unix))]
+pub(crate) async fn connect_unix(path: &Path) -> Result<Stream, anyhow::Error> {
+    use anyhow::Context;
+
+    let stream = UnixStream::connect(path)
+        .await
+        .with_context(|| format!("Failed to connect to Unix domain socket {}", path.display()))?;
+    Ok(Stream::Unix(stream))
+}
+
+#[cfg(all(feature = "client", not(unix)))]
+pub(crate) async fn connect_unix(_path: &Path) -> Result<Stream, anyhow::Error> {
+    anyhow::bail!("Unix domain sockets are unsupported on this platform")
+}
+
+/// Peeks at pending data without removing it, as `UnixStream` has no `peek`.
+#[cfg(unix)]
+async fn unix_peek(stream: &UnixStream, buf: &mut [u8]) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    loop {
+        stream.readable().await?;
+        let result = stream.try_io(Interest::READABLE, || {
+            // SAFETY: `buf` is a valid, exclusively borrowed buffer of `buf.len()` bytes for the
+            // duration of the call, and the file descriptor stays open as `stream` is borrowed.
+            let read = unsafe {
+                libc::recv(
+                    stream.as_raw_fd(),
+                    buf.as_mut_ptr().cast(),
+                    buf.len(),
+                    libc::MSG_PEEK,
+                )
+            };
+            if read < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(read as usize)
+            }
+        });
+        match result {
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+            result => return result,
+        }
+    }
+}
+
+impl PingSocket {
+    /// Binds a UDP socket to `local` which exchanges pings with `server`.
+    pub(crate) async fn udp(local: SocketAddr, server: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(server).await?;
+        Ok(PingSocket::Udp(socket))
+    }
+
+    /// Carries pings as frames on a stream which has already been set up to echo them.
+    #[cfg(feature = "client")]
+    pub(crate) fn stream(
+        rx: FramedRead<ReadHalf, LengthDelimitedCodec>,
+        tx: FramedWrite<WriteHalf, LengthDelimitedCodec>,
+    ) -> Self {
+        PingSocket::Stream(Box::new(PingStream {
+            rx: Mutex::new(rx),
+            tx: Mutex::new(tx),
+        }))
+    }
+
+    pub(crate) async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            PingSocket::Udp(socket) => socket.send(buf).await,
+            #[cfg(feature = "client")]
+            PingSocket::Stream(stream) => {
+                stream
+                    .tx
+                    .lock()
+                    .await
+                    .send(Bytes::copy_from_slice(buf))
+                    .await?;
+                Ok(buf.len())
+            }
+        }
+    }
+
+    /// Receives a ping, truncating it to `buf` like an oversized UDP packet.
+    pub(crate) async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            PingSocket::Udp(socket) => socket.recv(buf).await,
+            #[cfg(feature = "client")]
+            PingSocket::Stream(stream) => {
+                let frame = stream
+                    .rx
+                    .lock()
+                    .await
+                    .next()
+                    .await
+                    .unwrap_or_else(|| Err(io::ErrorKind::UnexpectedEof.into()))?;
+                let len = frame.len().min(buf.len());
+                buf[..len].copy_from_slice(&frame[..len]);
+                Ok(len)
+            }
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream::Tcp(stream)
+    }
+}
+
+impl Stream {
+    pub(crate) fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nodelay(nodelay),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(()),
+        }
+    }
+
+    pub(crate) fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_linger(linger),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(()),
+        }
+    }
+
+    pub(crate) fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr(),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(unix_addr()),
+        }
+    }
+
+    /// Returns the IP address of the peer, or `None` for a Unix domain socket.
+    pub(crate) fn peer_ip(&self) -> io::Result<Option<IpAddr>> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().map(|addr| Some(addr.ip())),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(None),
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.local_addr(),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(unix_addr()),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    pub(crate) async fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.peek(buf).await,
+            #[cfg(unix)]
+            Stream::Unix(stream) => unix_peek(stream, buf).await,
+        }
+    }
+
+    pub(crate) async fn readable(&self) -> io::Result<()> {
+        each!(self, Stream, stream => stream.readable().await)
+    }
+
+    pub(crate) async fn writable(&self) -> io::Result<()> {
+        each!(self, Stream, stream => stream.writable().await)
+    }
+
+    pub(crate) fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        each!(self, Stream, stream => stream.try_read(buf))
+    }
+
+    pub(crate) fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        each!(self, Stream, stream => stream.try_write(buf))
+    }
+
+    pub(crate) fn into_split(self) -> (ReadHalf, WriteHalf) {
+        match self {
+            Stream::Tcp(stream) => {
+                let (rx, tx) = stream.into_split();
+                (ReadHalf::Tcp(rx), WriteHalf::Tcp(tx))
+            }
+            #[cfg(unix)]
+            Stream::Unix(stream) => {
+                let (rx, tx) = stream.into_split();
+                (ReadHalf::Unix(rx), WriteHalf::Unix(tx))
+            }
+        }
+    }
+}
+
+impl ReadHalf {
+    pub(crate) async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ReadHalf::Tcp(half) => half.peek(buf).await,
+            #[cfg(unix)]
+            ReadHalf::Unix(half) => unix_peek(half.as_ref(), buf).await,
+        }
+    }
+
+    /// Joins the halves back into a stream, returning `None` if they're from different streams.
+    pub(crate) fn reunite(self, other: WriteHalf) -> Option<Stream> {
+        match (self, other) {
+            (ReadHalf::Tcp(rx), WriteHalf::Tcp(tx)) => rx.reunite(tx).ok().map(Stream::Tcp),
+            #[cfg(unix)]
+            (ReadHalf::Unix(rx), WriteHalf::Unix(tx)) => rx.reunite(tx).ok().map(Stream::Unix),
+            #[cfg(unix)]
+            _ => None,
+        }
+    }
+}
+
+impl WriteHalf {
+    /// Joins the halves back into a stream, returning `None` if they're from different streams.
+    pub(crate) fn reunite(self, other: ReadHalf) -> Option<Stream> {
+        other.reunite(self)
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        each!(self.get_mut(), Stream, stream => Pin::new(stream).poll_read(cx, buf))
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        each!(self.get_mut(), Stream, stream => Pin::new(stream).poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        each!(self.get_mut(), Stream, stream => Pin::new(stream).poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        each!(self.get_mut(), Stream, stream => Pin::new(stream).poll_shutdown(cx))
+    }
+}
+
+impl AsyncRead for ReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        each!(self.get_mut(), ReadHalf, half => Pin::new(half).poll_read(cx, buf))
+    }
+}
+
+impl AsyncWrite for WriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        each!(self.get_mut(), WriteHalf, half => Pin::new(half).poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        each!(self.get_mut(), WriteHalf, half => Pin::new(half).poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        each!(self.get_mut(), WriteHalf, half => Pin::new(half).poll_shutdown(cx))
+    }
+}
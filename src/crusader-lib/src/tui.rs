@@ -0,0 +1,153 @@
+//! A fixed-size live display of a running test, redrawn in place with ANSI escape codes.
+//!
+//! Messages only replace the last line of the display, so warnings are kept and printed below it
+//! once the test is done.
+
+use crate::test::ProgressSample;
+use crate::with_time;
+use parking_lot::Mutex;
+use std::io::{self, Write};
+
+/// The number of lines in the display.
+const LINES: usize = 6;
+
+/// The width of the gauges in characters.
+const GAUGE_WIDTH: usize = 30;
+
+/// The longest message shown, so lines don't wrap and break the redraw on narrow terminals.
+const MESSAGE_WIDTH: usize = 64;
+
+#[derive(Default)]
+struct State {
+    sample: Option<ProgressSample>,
+    message: String,
+    warnings: Vec<String>,
+    /// The highest throughput seen, which the throughput gauges are scaled to.
+    peak: f64,
+    drawn: bool,
+    finished: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct Tui {
+    state: Mutex<State>,
+}
+
+fn gauge(fraction: f64) -> String {
+    let filled = (fraction.clamp(0.0, 1.0) * GAUGE_WIDTH as f64).round() as usize;
+    format!(
+        "[{}{}]",
+        "#".repeat(filled),
+        "-".repeat(GAUGE_WIDTH - filled)
+    )
+}
+
+impl Tui {
+    pub(crate) fn msg(&self, msg: &str) {
+        let mut state = self.state.lock();
+        if state.finished {
+            println!("{}", with_time(msg));
+            return;
+        }
+        state.message = msg.chars().take(MESSAGE_WIDTH).collect();
+        Self::draw(&mut state);
+    }
+
+    /// Shows a warning like a message and keeps it to print below the display once the test is done.
+    pub(crate) fn warning(&self, warning: &str) {
+        let msg = format!("Warning: {warning}");
+        {
+            let mut state = self.state.lock();
+            if !state.finished {
+                state.warnings.push(with_time(&msg));
+            }
+        }
+        self.msg(&msg);
+    }
+
+    pub(crate) fn progress(&self, sample: ProgressSample) {
+        let mut state = self.state.lock();
+        if state.finished {
+            return;
+        }
+        state.peak = state.peak.max(sample.download).max(sample.upload);
+        state.sample = Some(sample);
+        Self::draw(&mut state);
+    }
+
+    /// Stops updating the display and prints the warnings shown during the test below it.
+    /// Later messages are printed normally.
+    pub(crate) fn finish(&self) {
+        let mut state = self.state.lock();
+        if state.drawn {
+            print!("\x1b[?25h");
+        }
+        for warning in &state.warnings {
+            println!("{warning}");
+        }
+        io::stdout().flush().ok();
+        state.finished = true;
+    }
+
+    fn draw(state: &mut State) {
+        let phase = match state.sample {
+            Some(sample) => format!(
+                "{:<16}{:.1} s of {:.1} s",
+                sample.load.map(|load| load.name()).unwrap_or("Idle"),
+                sample.elapsed.as_secs_f64(),
+                sample.duration.as_secs_f64()
+            ),
+            None => "Starting".to_owned(),
+        };
+        let fraction = state.sample.map_or(0.0, |sample| {
+            sample.elapsed.as_secs_f64() / sample.duration.as_secs_f64().max(0.001)
+        });
+        let throughput = |name: &str, mbps: f64| {
+            format!(
+                "{name:<10}{mbps:>10.2} Mbps  {}",
+                gauge(mbps / state.peak.max(f64::EPSILON))
+            )
+        };
+        let latency = match state.sample.and_then(|sample| sample.latency) {
+            Some(latency) => format!("{:>10.1} ms", latency.as_secs_f64() * 1000.0),
+            None => format!("{:>10}", "-"),
+        };
+
+        let lines: [String; LINES] = [
+            format!("{:<10}{phase}", "Phase:"),
+            format!(
+                "{:<10}{} {:3.0}%",
+                "Progress:",
+                gauge(fraction),
+                fraction.min(1.0) * 100.0
+            ),
+            throughput(
+                "Download:",
+                state.sample.map_or(0.0, |sample| sample.download),
+            ),
+            throughput("Upload:", state.sample.map_or(0.0, |sample| sample.upload)),
+            format!("{:<10}{latency}", "Latency:"),
+            state.message.clone(),
+        ];
+
+        let mut out = String::new();
+        if state.drawn {
+            // Move back up to the start of the display
+            out.push_str(&format!("\x1b[{LINES}A"));
+        } else {
+            // Hide the cursor
+            out.push_str("\x1b[?25l");
+        }
+        for line in lines {
+            out.push_str("\x1b[2K");
+            out.push_str(&line);
+            out.push('\n');
+        }
+
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(out.as_bytes()).ok();
+        stdout.flush().ok();
+
+        state.drawn = true;
+    }
+}
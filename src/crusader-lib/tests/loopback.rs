@@ -0,0 +1,264 @@
+//! Runs tests against a server on the loopback interface. The client connects through a local
+//! HTTP proxy which counts the connections and the bytes passing through them.
+
+use crusader_lib::serve::{serve_until, ServerLimits};
+use crusader_lib::test::{run_test, server_status, test_callback};
+use crusader_lib::{Config, ConfigBuilder, Proxy, ProxyKind};
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// An upper bound on the bytes of the messages setting up a load, which aren't counted as load.
+const SETUP_BYTES: u64 = 1024;
+
+/// A server which runs until dropped.
+struct Server {
+    port: u16,
+    _stop: oneshot::Sender<()>,
+}
+
+impl Server {
+    fn start() -> Self {
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let (started_tx, started_rx) = mpsc::channel();
+        let stop = serve_until(
+            port,
+            Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            false,
+            ServerLimits::default(),
+            Box::new(|_| ()),
+            Box::new(move |result| started_tx.send(result).unwrap()),
+            Box::new(|| ()),
+        )
+        .unwrap();
+        started_rx.recv().unwrap().unwrap();
+
+        Server { port, _stop: stop }
+    }
+
+    /// A quick test through `proxy` with a single 2 second load.
+    fn config(&self, proxy: &CountingProxy) -> ConfigBuilder {
+        ConfigBuilder::from(Config::fast())
+            .port(self.port)
+            .proxy(Some(proxy.proxy()))
+            .load_duration(Duration::from_secs(2))
+            .streams(2)
+    }
+}
+
+/// The bytes passed through a proxied connection in each direction.
+#[derive(Default)]
+struct Connection {
+    up: AtomicU64,
+    down: AtomicU64,
+    closed: AtomicBool,
+}
+
+struct CountingProxy {
+    address: SocketAddr,
+    connections: Arc<Mutex<Vec<Arc<Connection>>>>,
+}
+
+impl CountingProxy {
+    fn start() -> Self {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(Mutex::new(Vec::new()));
+
+        let connections_ = connections.clone();
+        thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                let connection = Arc::new(Connection::default());
+                connections_.lock().unwrap().push(connection.clone());
+                thread::spawn(move || {
+                    tunnel(client, &connection).ok();
+                    connection.closed.store(true, Ordering::SeqCst);
+                });
+            }
+        });
+
+        CountingProxy {
+            address,
+            connections,
+        }
+    }
+
+    fn proxy(&self) -> Proxy {
+        Proxy {
+            kind: ProxyKind::Http,
+            address: self.address,
+        }
+    }
+
+    /// The connections made so far, in the order they were accepted.
+    fn connections(&self) -> Vec<Arc<Connection>> {
+        self.connections.lock().unwrap().clone()
+    }
+
+    /// Waits for every connection to close, returning whether they did in time.
+    fn wait_closed(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            let connections = self.connections();
+            if connections
+                .iter()
+                .all(|connection| connection.closed.load(Ordering::SeqCst))
+            {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        false
+    }
+}
+
+/// Answers an HTTP `CONNECT` request and copies the data between the client and the target.
+fn tunnel(mut client: TcpStream, connection: &Connection) -> std::io::Result<()> {
+    // Read the request a byte at a time so no tunneled data is consumed
+    let mut request = Vec::new();
+    let mut byte = [0];
+    while !request.ends_with(b"\r\n\r\n") {
+        client.read_exact(&mut byte)?;
+        request.push(byte[0]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let target = request.split_whitespace().nth(1).unwrap_or_default();
+
+    let server = TcpStream::connect(target)?;
+    client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+
+    let client_ = client.try_clone()?;
+    let server_ = server.try_clone()?;
+    thread::scope(|scope| {
+        scope.spawn(|| copy(client_, server, &connection.up));
+        copy(server_, client, &connection.down);
+    });
+    Ok(())
+}
+
+fn copy(mut from: TcpStream, mut to: TcpStream, bytes: &AtomicU64) {
+    let mut buffer = vec![0; 64 * 1024];
+    while let Ok(len @ 1..) = from.read(&mut buffer) {
+        if to.write_all(&buffer[..len]).is_err() {
+            break;
+        }
+        bytes.fetch_add(len as u64, Ordering::SeqCst);
+    }
+    to.shutdown(Shutdown::Write).ok();
+}
+
+#[test]
+fn download_bytes_match_transferred() {
+    let server = Server::start();
+    let proxy = CountingProxy::start();
+
+    let result = run_test(
+        server.config(&proxy).build(),
+        Some("127.0.0.1"),
+        &[],
+        Arc::new(|_| ()),
+    )
+    .unwrap();
+    assert!(proxy.wait_closed(Duration::from_secs(10)));
+
+    // The first connection is the control connection, the rest carry the loads
+    let mut transferred: Vec<u64> = proxy.connections()[1..]
+        .iter()
+        .map(|connection| connection.down.load(Ordering::SeqCst))
+        .collect();
+    let mut counted: Vec<u64> = result
+        .stream_groups
+        .iter()
+        .flat_map(|group| &group.streams)
+        .map(|stream| stream.data.last().unwrap().bytes)
+        .collect();
+    transferred.sort();
+    counted.sort();
+    assert_eq!(counted.len(), transferred.len());
+
+    // Only the messages setting up each load aren't counted
+    for (counted, transferred) in counted.into_iter().zip(transferred) {
+        assert!(counted > 0);
+        assert!((counted..=counted + SETUP_BYTES).contains(&transferred));
+    }
+}
+
+#[test]
+fn download_only_opens_no_other_streams() {
+    let server = Server::start();
+    let proxy = CountingProxy::start();
+
+    let config = server
+        .config(&proxy)
+        .download(true)
+        .upload(false)
+        .bidirectional(false)
+        .build();
+    let result = run_test(config, Some("127.0.0.1"), &[], Arc::new(|_| ())).unwrap();
+    assert!(proxy.wait_closed(Duration::from_secs(10)));
+
+    assert!(!result.stream_groups.is_empty());
+    assert!(result
+        .stream_groups
+        .iter()
+        .all(|group| group.download && !group.both));
+
+    // Only the control connection and one connection per download stream are made
+    let connections = proxy.connections();
+    assert_eq!(connections.len() as u64, 1 + config.streams);
+    for connection in &connections[1..] {
+        assert!(connection.up.load(Ordering::SeqCst) <= SETUP_BYTES);
+    }
+}
+
+#[test]
+fn abort_stops_loads() {
+    let server = Server::start();
+    let proxy = CountingProxy::start();
+
+    let config = server
+        .config(&proxy)
+        .load_duration(Duration::from_secs(60))
+        .build();
+    let (done_tx, done_rx) = mpsc::channel();
+    let abort = test_callback(
+        config,
+        Some("127.0.0.1"),
+        &[],
+        Arc::new(|_| ()),
+        None,
+        Box::new(move |result| done_tx.send(result.is_none()).unwrap()),
+    );
+
+    // Wait for the loads to be running
+    let start = Instant::now();
+    while server_status("127.0.0.1", server.port)
+        .unwrap()
+        .throughput_mbps
+        == 0.0
+    {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    abort.send(()).unwrap();
+    let aborted = done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(aborted);
+
+    // The server stops the loads and frees the client instead of loading for the full minute
+    assert!(proxy.wait_closed(Duration::from_secs(5)));
+    let start = Instant::now();
+    while server_status("127.0.0.1", server.port).unwrap().clients > 0 {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(50));
+    }
+}
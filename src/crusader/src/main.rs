@@ -2,12 +2,15 @@ use anyhow::Context;
 use clap::{Parser, Subcommand};
 use clap_num::si_number;
 #[cfg(feature = "client")]
-use crusader_lib::file_format::RawResult;
+use crusader_lib::file_format::{Compression, Coordination, RawResult};
 #[cfg(feature = "client")]
-use crusader_lib::test::PlotConfig;
+use crusader_lib::plot::{JsonPhase, JsonSummary, Thresholds};
+#[cfg(feature = "client")]
+use crusader_lib::test::{run_test, save_raw, timed, PlotConfig};
 use crusader_lib::{protocol, version};
 #[cfg(feature = "client")]
-use crusader_lib::{with_time, Config};
+use crusader_lib::{with_time, Config, ConfigBuilder, Interface, Proxy};
+use std::net::IpAddr;
 #[cfg(feature = "client")]
 use std::path::PathBuf;
 use std::process;
@@ -15,11 +18,20 @@ use std::process;
 use {
     anyhow::anyhow,
     std::fs::OpenOptions,
-    std::io::{BufWriter, Write},
+    std::io::{BufWriter, IsTerminal, Write},
     std::path::Path,
+    std::sync::Arc,
     std::time::Duration,
 };
 
+/// Parses a number of seconds, rejecting values which aren't a valid duration.
+fn seconds(value: &str) -> Result<f64, String> {
+    let seconds: f64 = value.parse().map_err(|error| format!("{error}"))?;
+    std::time::Duration::try_from_secs_f64(seconds)
+        .map_err(|_| "must be a non-negative number of seconds".to_owned())?;
+    Ok(seconds)
+}
+
 #[derive(Parser)]
 #[command(version = version())]
 struct Cli {
@@ -62,10 +74,351 @@ impl PlotArgs {
             width: self.plot_width,
             height: self.plot_height,
             title: self.plot_title.clone(),
+            time_range: None,
         }
     }
 }
 
+#[cfg(feature = "client")]
+#[derive(clap::Args)]
+struct TestArgs {
+    server: Option<String>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", help = "Run a download test")]
+    download: Option<bool>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", help = "Run an upload test")]
+    upload: Option<bool>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", help = "Run a test doing both download and upload")]
+    bidirectional: Option<bool>,
+    #[arg(
+        long,
+        long_help = "Run a test only measuring latency. The duration is specified by `initial_grace_duration`, \
+            or `grace_duration` if it's not set"
+    )]
+    idle: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["download", "upload", "bidirectional", "idle", "streams", "stream_stagger", "load_duration", "grace_duration", "initial_grace_duration", "final_grace_duration", "warmup", "upload_rate", "latency_sample_interval", "throughput_sample_interval"],
+        long_help = "Run a quick download test using a single stream for 5 seconds with 0.25 seconds of grace. \
+        Finishes in about 10 seconds"
+    )]
+    fast: bool,
+    #[arg(long, default_value_t = protocol::PORT, help = "Specifies the TCP and UDP port used by the server")]
+    port: u16,
+    #[arg(
+        long,
+        conflicts_with = "ipv6",
+        help = "Only connect to the server using IPv4"
+    )]
+    ipv4: bool,
+    #[arg(long, help = "Only connect to the server using IPv6")]
+    ipv6: bool,
+    #[arg(
+        long,
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "The number of TCP connections used to generate traffic in a single direction"
+    )]
+    streams: u64,
+    #[arg(
+        long,
+        default_value_t = 0.0,
+        value_name = "SECONDS",
+        value_parser = seconds,
+        help = "The delay between the start of each stream"
+    )]
+    stream_stagger: f64,
+    #[arg(
+        long,
+        default_value_t = 10.0,
+        value_name = "SECONDS",
+        value_parser = seconds,
+        help = "The duration in which traffic is generated"
+    )]
+    load_duration: f64,
+    #[arg(
+        long,
+        default_value_t = 2.0,
+        value_name = "SECONDS",
+        value_parser = seconds,
+        help = "The idle time between each test"
+    )]
+    grace_duration: f64,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = seconds,
+        help = "The idle time before the first test [default: grace duration]"
+    )]
+    initial_grace_duration: Option<f64>,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = seconds,
+        help = "The idle time after the last test [default: grace duration]"
+    )]
+    final_grace_duration: Option<f64>,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = seconds,
+        help = "The start of each load excluded from the average throughput [default: 20% of the load duration, up to 2 seconds]"
+    )]
+    warmup: Option<f64>,
+    #[arg(
+        long,
+        value_name = "MBPS",
+        help = "Pace each upload stream to this rate instead of saturating the link"
+    )]
+    upload_rate: Option<f64>,
+    #[arg(
+        long,
+        value_name = "MBPS",
+        help = "Cap the total throughput of all streams to this rate. Both directions of the bidirectional test share the cap"
+    )]
+    total_rate: Option<f64>,
+    #[arg(
+        long,
+        value_name = "BYTES",
+        help = "End each load after transferring this many bytes in its direction. The load duration then limits how long it can take"
+    )]
+    load_bytes: Option<u64>,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Record the error and drop counters of this local network interface during the test. Linux only"
+    )]
+    iface: Option<String>,
+    #[arg(
+        long,
+        default_value_t = 5,
+        value_name = "MILLISECONDS",
+        help = "How often to measure latency during the test. 0 disables latency measurement"
+    )]
+    latency_sample_interval: u64,
+    #[arg(
+        long,
+        default_value_t = protocol::PING_SIZE as u64,
+        value_parser = clap::value_parser!(u64).range(protocol::PING_SIZE as u64..=protocol::MAX_PING_SIZE as u64),
+        value_name = "BYTES",
+        help = "The size of the UDP payload used to measure latency"
+    )]
+    ping_size: u64,
+    #[arg(
+        long,
+        default_value_t = 0,
+        value_name = "MILLISECONDS",
+        help = "The maximum random delay added to each latency sample, so they are not perfectly periodic"
+    )]
+    latency_sample_jitter: u64,
+    #[arg(
+        long,
+        default_value_t = crusader_lib::IDLE_LATENCY_SAMPLES,
+        value_parser = clap::value_parser!(u32).range(2..),
+        value_name = "SAMPLES",
+        help = "The number of samples used to measure the idle latency before and after the test"
+    )]
+    idle_latency_samples: u32,
+    #[arg(
+        long,
+        default_value_t = crusader_lib::IDLE_LATENCY_INTERVAL.as_millis() as u64,
+        value_parser = clap::value_parser!(u64).range(1..),
+        value_name = "MILLISECONDS",
+        help = "The interval between the idle latency samples"
+    )]
+    idle_latency_interval: u64,
+    #[arg(
+        long,
+        default_value_t = 1.0,
+        value_name = "SECONDS",
+        value_parser = seconds,
+        help = "How long to wait for UDP replies from the server when measuring idle latency"
+    )]
+    setup_timeout: f64,
+    #[arg(
+        long,
+        default_value_t = crusader_lib::CONNECT_TIMEOUT.as_secs_f64(),
+        value_name = "SECONDS",
+        value_parser = seconds,
+        help = "How long to wait for each attempt to connect to the server"
+    )]
+    connect_timeout: f64,
+    #[arg(
+        long,
+        default_value_t = 0,
+        value_name = "COUNT",
+        help = "How many times to retry connecting to the server, with an increasing delay between attempts"
+    )]
+    connect_retries: u32,
+    #[arg(long, default_value_t = 60, value_name = "MILLISECONDS")]
+    throughput_sample_interval: u64,
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "The TCP send and receive buffer size of the throughput connections [default: OS default]"
+    )]
+    socket_buffer_size: Option<u32>,
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "The size of each read and write on the throughput connections [default: 512 KiB reads, 128 KiB writes]"
+    )]
+    chunk_size: Option<u64>,
+    #[arg(
+        long,
+        help = "Upload fresh random data on each run instead of a fixed pattern, to detect links which compress traffic"
+    )]
+    incompressible: bool,
+    #[arg(
+        long,
+        value_name = "POINTS",
+        value_parser = clap::value_parser!(u64).range(4..),
+        help = "Reduce the stored throughput samples of each stream to this count, preserving peaks"
+    )]
+    max_throughput_points: Option<u64>,
+    #[arg(
+        long,
+        value_name = "URL",
+        conflicts_with_all = ["idle", "latency_peer", "latency_peer_address"],
+        long_help = "Connect to the server through an HTTP CONNECT (`http://host:port`) \
+            or SOCKS5 (`socks5://host:port`) proxy. \
+            Latency can't be measured as UDP doesn't pass through the proxy"
+    )]
+    proxy: Option<String>,
+    #[arg(
+        long,
+        conflicts_with = "proxy",
+        long_help = "Measure latency with ICMP echo requests instead of UDP, \
+            for when the server's UDP port is blocked. \
+            Only round-trip latency is available. \
+            Requires root or `CAP_NET_RAW` unless unprivileged ICMP sockets are allowed. \
+            Unsupported on Windows"
+    )]
+    icmp: bool,
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u8).range(0..=63),
+        long_help = "Mark the load and latency traffic with this DSCP value, \
+            such as 46 for Expedited Forwarding, to test how the network treats prioritized traffic. \
+            Only traffic sent by the client is marked, so the upload and the pings to the server are, \
+            but the download and the ping replies from the server aren't"
+    )]
+    dscp: Option<u8>,
+    #[arg(
+        long,
+        value_name = "ID",
+        requires = "group_size",
+        long_help = "Join a group of clients which start their tests together. \
+            The server waits up to 5 minutes for `--group-size` clients using the same group ID to join"
+    )]
+    group: Option<u64>,
+    #[arg(
+        long,
+        value_name = "CLIENTS",
+        requires = "group",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "The number of clients in the group"
+    )]
+    group_size: Option<u32>,
+    #[command(flatten)]
+    plot: PlotArgs,
+    #[arg(
+        long,
+        long_help = "Specifies another server (peer) which will also measure the latency to the server independently of the client. \
+            Can be repeated to measure the latency from several peers at once"
+    )]
+    latency_peer_address: Vec<String>,
+    #[arg(
+        long,
+        help = "Use another server (peer) which will also measure the latency to the server independently of the client"
+    )]
+    latency_peer: bool,
+    #[arg(
+        long,
+        help = "The filename prefix used for the test result raw data and plot filenames"
+    )]
+    out_name: Option<String>,
+    #[arg(
+        long,
+        default_value = "crusader-results",
+        value_name = "DIRECTORY",
+        help = "The directory where the test result raw data and plot are saved"
+    )]
+    output_dir: PathBuf,
+    #[arg(
+        long,
+        long_help = "Print a JSON summary of the result to stdout. \
+            Throughput is in Mbps, latency in milliseconds and packet loss is a fraction. \
+            Other output is written to stderr"
+    )]
+    json: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["out_name", "output_dir"],
+        help = "Don't save the raw data and plot, only print the summary"
+    )]
+    no_save: bool,
+    #[arg(
+        long,
+        conflicts_with = "no_save",
+        help = "Save the raw data without compression. Saving is faster, but the files are much larger"
+    )]
+    no_compression: bool,
+    #[arg(
+        long,
+        value_name = "MILLISECONDS",
+        help = "Exit with status 1 if the latency of any test exceeds this"
+    )]
+    max_latency: Option<f64>,
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        help = "Exit with status 1 if the packet loss of any test exceeds this"
+    )]
+    max_loss: Option<f64>,
+    #[arg(
+        long,
+        value_name = "MBPS",
+        help = "Exit with status 1 if the download throughput is below this"
+    )]
+    min_download: Option<f64>,
+    #[arg(
+        long,
+        value_name = "MBPS",
+        help = "Exit with status 1 if the upload throughput is below this"
+    )]
+    min_upload: Option<f64>,
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        value_name = "COUNT",
+        conflicts_with_all = ["json", "max_latency", "max_loss", "min_download", "min_upload"],
+        long_help = "Run the test this many times and print the mean, standard deviation, \
+            minimum and maximum of each metric across the runs. No plots are saved"
+    )]
+    repeat: Option<u64>,
+    #[arg(
+        long,
+        requires = "repeat",
+        help = "Save the raw data of each repeated test run"
+    )]
+    save_each: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["json", "repeat"],
+        long_help = "Only check that the server is reachable over TCP and UDP and measure the idle latency, \
+            without running the test. Exits with status 1 if the check fails"
+    )]
+    check: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["json", "repeat", "check"],
+        help = "Show a live display of the phase, throughput and latency, updated in place"
+    )]
+    tui: bool,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     #[command(about = "Runs the server")]
@@ -74,75 +427,103 @@ enum Commands {
         port: u16,
         #[arg(long, help = "Allow use and discovery as a peer")]
         peer: bool,
+        #[arg(
+            long,
+            value_name = "ADDRESS",
+            help = "Only listen on the specified IP address. Local discovery is disabled when set"
+        )]
+        bind: Option<IpAddr>,
+        #[arg(
+            long,
+            value_name = "SECONDS",
+            value_parser = seconds,
+            help = "Reject tests with a load duration longer than this"
+        )]
+        max_load_duration: Option<f64>,
+        #[arg(long, help = "Reject tests using more streams than this per direction")]
+        max_streams: Option<u32>,
+        #[arg(long, help = "Reject clients when this many are already being served")]
+        max_clients: Option<usize>,
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Also accept clients on a Unix domain socket, for local benchmarking"
+        )]
+        unix: Option<std::path::PathBuf>,
     },
     #[command(
         long_about = "Runs a test client against a specified server and saves the result to the current directory. \
         By default this does a download test, an upload test, and a test doing both download and upload while measuring the latency to the server"
     )]
     #[cfg(feature = "client")]
-    Test {
+    Test(Box<TestArgs>),
+    #[cfg(feature = "client")]
+    #[command(about = "Monitors the latency to a server until interrupted")]
+    Monitor {
         server: Option<String>,
-        #[arg(long, help = "Run a download test")]
-        download: bool,
-        #[arg(long, help = "Run an upload test")]
-        upload: bool,
-        #[arg(long, help = "Run a test doing both download and upload")]
-        bidirectional: bool,
-        #[arg(
-            long,
-            long_help = "Run a test only measuring latency. The duration is specified by `grace_duration`"
-        )]
-        idle: bool,
         #[arg(long, default_value_t = protocol::PORT, help = "Specifies the TCP and UDP port used by the server")]
         port: u16,
         #[arg(
             long,
-            default_value_t = 8,
-            help = "The number of TCP connections used to generate traffic in a single direction"
+            default_value_t = 100,
+            value_parser = clap::value_parser!(u64).range(1..),
+            value_name = "MILLISECONDS",
+            help = "The interval between latency samples"
         )]
-        streams: u64,
+        interval: u64,
         #[arg(
             long,
-            default_value_t = 0.0,
+            default_value_t = 60.0,
             value_name = "SECONDS",
-            help = "The delay between the start of each stream"
+            value_parser = seconds,
+            help = "The duration of samples kept in memory"
         )]
-        stream_stagger: f64,
+        history: f64,
         #[arg(
             long,
-            default_value_t = 10.0,
-            value_name = "SECONDS",
-            help = "The duration in which traffic is generated"
+            help = "Reconnect when the connection to the server is lost, recording the outage as packet loss"
         )]
-        load_duration: f64,
+        reconnect: bool,
         #[arg(
             long,
-            default_value_t = 2.0,
-            value_name = "SECONDS",
-            help = "The idle time between each test"
+            value_name = "PATH",
+            help = "Appends each latency sample to a CSV file"
         )]
-        grace_duration: f64,
-        #[arg(long, default_value_t = 5, value_name = "MILLISECONDS")]
-        latency_sample_interval: u64,
-        #[arg(long, default_value_t = 60, value_name = "MILLISECONDS")]
-        throughput_sample_interval: u64,
-        #[command(flatten)]
-        plot: PlotArgs,
+        log: Option<PathBuf>,
+    },
+    #[cfg(feature = "client")]
+    #[command(
+        about = "Runs a download test with each stream count to find where the throughput stops improving"
+    )]
+    Sweep {
+        server: Option<String>,
+        #[arg(long, default_value_t = protocol::PORT, help = "Specifies the TCP and UDP port used by the server")]
+        port: u16,
         #[arg(
             long,
-            long_help = "Specifies another server (peer) which will also measure the latency to the server independently of the client"
+            value_delimiter = ',',
+            default_value = "1,2,4,8,16",
+            value_parser = clap::value_parser!(u64).range(1..),
+            value_name = "COUNTS",
+            help = "The comma separated stream counts to test"
         )]
-        latency_peer_address: Option<String>,
+        streams: Vec<u64>,
         #[arg(
             long,
-            help = "Use another server (peer) which will also measure the latency to the server independently of the client"
+            default_value_t = 5.0,
+            value_name = "SECONDS",
+            value_parser = seconds,
+            help = "The duration of each download test"
         )]
-        latency_peer: bool,
+        load_duration: f64,
         #[arg(
             long,
-            help = "The filename prefix used for the test result raw data and plot filenames"
+            default_value_t = 1.0,
+            value_name = "SECONDS",
+            value_parser = seconds,
+            help = "The idle time before and after each download test"
         )]
-        out_name: Option<String>,
+        grace_duration: f64,
     },
     #[cfg(feature = "client")]
     #[command(about = "Plots a previous result")]
@@ -156,12 +537,29 @@ enum Commands {
     Remote {
         #[arg(
             long,
-            default_value_t = protocol::PORT + 1,
+            default_value_t = crusader_lib::remote::REMOTE_PORT,
             help = "Specifies the HTTP port used by the server"
         )]
         port: u16,
+        #[arg(
+            long,
+            long_help = "Requires this token to run tests, so others reaching the server can't use it. \
+                Web clients pass it by opening the page with `?token=<TOKEN>`, \
+                API requests either the same way or as a bearer token"
+        )]
+        token: Option<String>,
+    },
+    #[cfg(feature = "client")]
+    #[command(about = "Shows the number of clients and the current throughput of a server")]
+    Status {
+        server: String,
+        #[arg(long, default_value_t = protocol::PORT, help = "Specifies the TCP port used by the server")]
+        port: u16,
     },
     #[cfg(feature = "client")]
+    #[command(about = "Lists the servers on the local network")]
+    Discover,
+    #[cfg(feature = "client")]
     #[command(about = "Converts a result file to JSON")]
     Export {
         data: PathBuf,
@@ -181,60 +579,235 @@ fn run() -> Result<(), anyhow::Error> {
 
     match &cli.command {
         #[cfg(feature = "client")]
-        &Commands::Test {
-            ref server,
-            download,
-            upload,
-            bidirectional,
-            idle,
-            throughput_sample_interval,
-            latency_sample_interval,
-            ref plot,
-            port,
-            streams,
-            stream_stagger,
-            grace_duration,
-            load_duration,
-            ref latency_peer_address,
-            latency_peer,
-            ref out_name,
-        } => {
-            let mut config = Config {
+        Commands::Test(args) => {
+            let TestArgs {
+                ref server,
+                download,
+                upload,
+                bidirectional,
+                idle,
+                fast,
+                throughput_sample_interval,
+                latency_sample_interval,
+                ping_size,
+                latency_sample_jitter,
+                idle_latency_samples,
+                idle_latency_interval,
+                setup_timeout,
+                connect_timeout,
+                connect_retries,
+                socket_buffer_size,
+                chunk_size,
+                incompressible,
+                max_throughput_points,
+                ref proxy,
+                icmp,
+                dscp,
+                group,
+                group_size,
+                ref plot,
                 port,
+                ipv4,
+                ipv6,
                 streams,
-                stream_stagger: Duration::from_secs_f64(stream_stagger),
-                grace_duration: Duration::from_secs_f64(grace_duration),
-                load_duration: Duration::from_secs_f64(load_duration),
-                download: !idle,
-                upload: !idle,
-                bidirectional: !idle,
-                ping_interval: Duration::from_millis(latency_sample_interval),
-                throughput_interval: Duration::from_millis(throughput_sample_interval),
+                stream_stagger,
+                grace_duration,
+                initial_grace_duration,
+                final_grace_duration,
+                load_duration,
+                warmup,
+                upload_rate,
+                total_rate,
+                load_bytes,
+                ref iface,
+                ref latency_peer_address,
+                latency_peer,
+                ref out_name,
+                ref output_dir,
+                json,
+                no_save,
+                no_compression,
+                max_latency,
+                max_loss,
+                min_download,
+                min_upload,
+                repeat,
+                save_each,
+                check,
+                tui,
+            } = **args;
+            let proxy = proxy.as_deref().map(Proxy::parse).transpose()?;
+            let interface = iface.as_deref().map(Interface::new).transpose()?;
+            let coordination = group
+                .zip(group_size)
+                .map(|(id, clients)| Coordination { id, clients });
+
+            let builder = if fast {
+                ConfigBuilder::from(Config::fast())
+            } else {
+                Config::builder()
+                    .streams(streams)
+                    .stream_stagger(Duration::from_secs_f64(stream_stagger))
+                    .grace_duration(Duration::from_secs_f64(grace_duration))
+                    .initial_grace(initial_grace_duration.map(Duration::from_secs_f64))
+                    .final_grace(final_grace_duration.map(Duration::from_secs_f64))
+                    .load_duration(Duration::from_secs_f64(load_duration))
+                    .download(!idle)
+                    .upload(!idle)
+                    .bidirectional(!idle)
+                    .ping_interval(Duration::from_millis(latency_sample_interval))
+                    .throughput_interval(Duration::from_millis(throughput_sample_interval))
             };
 
-            if download || upload || bidirectional {
-                if idle {
-                    println!("Cannot run `idle` test with a load test");
+            let mut config = builder
+                .port(port)
+                .ping_payload_size(ping_size as usize)
+                .ping_jitter(Duration::from_millis(latency_sample_jitter))
+                .force_ipv4(ipv4)
+                .force_ipv6(ipv6)
+                .setup_timeout(Duration::from_secs_f64(setup_timeout))
+                .connect_timeout(Duration::from_secs_f64(connect_timeout))
+                .connect_retries(connect_retries)
+                .socket_buffer_size(socket_buffer_size)
+                .chunk_size(chunk_size.map(|size| size as usize))
+                .incompressible(incompressible)
+                .max_throughput_points(max_throughput_points.map(|points| points as usize))
+                .proxy(proxy)
+                .coordination(coordination)
+                .idle_latency_samples(idle_latency_samples)
+                .idle_latency_interval(Duration::from_millis(idle_latency_interval))
+                .warmup(warmup.map(Duration::from_secs_f64))
+                .upload_rate(upload_rate)
+                .total_rate(total_rate)
+                .load_bytes(load_bytes)
+                .interface(interface)
+                .icmp(icmp)
+                .dscp(dscp)
+                .build();
+
+            let directions = [download, upload, bidirectional];
+            if directions.contains(&Some(true)) && idle {
+                println!("Cannot run `idle` test with a load test");
+                process::exit(1);
+            }
+            if !idle && directions.iter().any(|direction| direction.is_some()) {
+                // Explicitly enabling a direction disables the others unless they're also given
+                let enabled = directions.contains(&Some(true));
+                config.download = download.unwrap_or(!enabled);
+                config.upload = upload.unwrap_or(!enabled);
+                config.bidirectional = bidirectional.unwrap_or(!enabled);
+
+                if !config.download && !config.upload && !config.bidirectional {
+                    println!(
+                        "At least one test direction is required. Use `--idle` to only measure latency"
+                    );
                     process::exit(1);
                 }
-                config.download = download;
-                config.upload = upload;
-                config.bidirectional = bidirectional;
             }
 
-            crusader_lib::test::test(
+            let latency_peers: Vec<_> = if latency_peer_address.is_empty() {
+                latency_peer.then_some(None).into_iter().collect()
+            } else {
+                latency_peer_address
+                    .iter()
+                    .map(|address| Some(address.as_str()))
+                    .collect()
+            };
+
+            if check {
+                return check_server(config, server.as_deref());
+            }
+
+            if tui && !std::io::stdout().is_terminal() {
+                println!("The live display requires a terminal");
+                process::exit(1);
+            }
+
+            let compression = if no_compression {
+                Compression::None
+            } else {
+                Compression::default()
+            };
+
+            if let Some(repeat) = repeat {
+                return repeat_test(
+                    config,
+                    server.as_deref(),
+                    &latency_peers,
+                    repeat,
+                    save_each
+                        .then(|| (out_name.as_deref().unwrap_or("test"), output_dir.as_path())),
+                    compression,
+                );
+            }
+
+            let result = crusader_lib::test::test(
                 config,
                 plot.config(),
                 server.as_deref(),
-                (latency_peer || latency_peer_address.is_some())
-                    .then_some(latency_peer_address.as_deref()),
-                out_name.as_deref().unwrap_or("test"),
-            )
+                &latency_peers,
+                (!no_save).then(|| (out_name.as_deref().unwrap_or("test"), output_dir.as_path())),
+                compression,
+                json,
+                tui,
+            )?;
+
+            let violations = result.threshold_violations(&Thresholds {
+                max_latency,
+                max_loss,
+                min_download,
+                min_upload,
+            });
+            if !violations.is_empty() {
+                for violation in violations {
+                    eprintln!("Threshold not met: {}", violation);
+                }
+                process::exit(1);
+            }
+
+            Ok(())
         }
-        &Commands::Serve { port, peer } => crusader_lib::serve::serve(port, peer),
+        &Commands::Serve {
+            port,
+            peer,
+            bind,
+            max_load_duration,
+            max_streams,
+            max_clients,
+            ref unix,
+        } => crusader_lib::serve::serve(
+            port,
+            bind,
+            peer,
+            crusader_lib::serve::ServerLimits {
+                max_load_duration: max_load_duration.map(std::time::Duration::from_secs_f64),
+                max_streams,
+                max_clients,
+            },
+            unix.clone(),
+        ),
 
         #[cfg(feature = "client")]
-        Commands::Remote { port } => crusader_lib::remote::run(*port),
+        Commands::Remote { port, token } => crusader_lib::remote::run(*port, token.clone()),
+
+        #[cfg(feature = "client")]
+        &Commands::Monitor {
+            ref server,
+            port,
+            interval,
+            history,
+            reconnect,
+            ref log,
+        } => crusader_lib::latency::monitor(
+            crusader_lib::latency::Config {
+                port,
+                ping_interval: Duration::from_millis(interval),
+                reconnect,
+                log: log.clone(),
+            },
+            server.as_deref(),
+            Duration::from_secs_f64(history),
+        ),
 
         #[cfg(feature = "client")]
         Commands::Plot { data, plot } => {
@@ -255,6 +828,42 @@ fn run() -> Result<(), anyhow::Error> {
             Ok(())
         }
         #[cfg(feature = "client")]
+        &Commands::Sweep {
+            ref server,
+            port,
+            ref streams,
+            load_duration,
+            grace_duration,
+        } => {
+            let config = Config::builder()
+                .port(port)
+                .download(true)
+                .upload(false)
+                .bidirectional(false)
+                .load_duration(Duration::from_secs_f64(load_duration))
+                .grace_duration(Duration::from_secs_f64(grace_duration))
+                .build();
+            sweep(config, server.as_deref(), streams)
+        }
+        #[cfg(feature = "client")]
+        &Commands::Status { ref server, port } => {
+            let status = crusader_lib::test::server_status(server, port)?;
+            println!("Clients: {}", status.clients);
+            println!("Throughput: {:.2} Mbps", status.throughput_mbps);
+            Ok(())
+        }
+        #[cfg(feature = "client")]
+        Commands::Discover => {
+            let servers = crusader_lib::discovery::find_servers(Duration::from_secs(2))?;
+            if servers.is_empty() {
+                println!("No servers found");
+            }
+            for server in servers {
+                println!("{} (version {})", server.at, server.software_version);
+            }
+            Ok(())
+        }
+        #[cfg(feature = "client")]
         Commands::Export {
             data,
             output,
@@ -280,6 +889,211 @@ fn run() -> Result<(), anyhow::Error> {
     }
 }
 
+#[cfg(feature = "client")]
+fn check_server(config: Config, server: Option<&str>) -> Result<(), anyhow::Error> {
+    let result = crusader_lib::test::check(config, server)?;
+
+    println!(
+        "\nServer {} is reachable at {} over IPv{}",
+        result.server,
+        result.address,
+        if result.address.is_ipv6() { 6 } else { 4 }
+    );
+    println!("Protocol version: {}", result.protocol_version);
+    match &result.server_info {
+        Some(info) => {
+            println!("Server version: {}", info.version);
+            println!("Server uptime: {} s", info.uptime.as_secs());
+        }
+        None => println!("Server version: unknown, the server is too old to report it"),
+    }
+    match result.latency {
+        Some(latency) => println!("Idle latency: {:.2} ms", latency.as_secs_f64() * 1000.0),
+        None => println!("Idle latency: unavailable through the proxy"),
+    }
+
+    let tests: Vec<_> = [
+        (config.download, "download"),
+        (config.upload, "upload"),
+        (config.bidirectional, "bidirectional"),
+    ]
+    .into_iter()
+    .filter(|test| test.0)
+    .map(|test| test.1)
+    .collect();
+    if tests.is_empty() {
+        println!("Test: idle");
+    } else {
+        println!(
+            "Tests: {} with {} streams loading for {:.2} s each",
+            tests.join(", "),
+            config.streams,
+            config.load_duration.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "client")]
+fn repeat_test(
+    config: Config,
+    server: Option<&str>,
+    latency_peers: &[Option<&str>],
+    count: u64,
+    save: Option<(&str, &Path)>,
+    compression: Compression,
+) -> Result<(), anyhow::Error> {
+    let msg: Arc<dyn Fn(&str) + Send + Sync> = Arc::new(|msg| println!("{}", with_time(msg)));
+    let mut summaries = Vec::new();
+    for run in 1..=count {
+        msg(&format!("Test run {} of {}", run, count));
+        let result = run_test(config, server, latency_peers, msg.clone()).map_err(|error| {
+            msg("Client failed");
+            anyhow!(error)
+        })?;
+        if let Some((out_name, output_dir)) = save {
+            let raw = save_raw(&result, &timed(out_name), output_dir, compression)?;
+            msg(&format!("Saved raw data as {}", raw.display()));
+        }
+        summaries.push(result.to_test_result().json_summary());
+    }
+    print!("\n{}", repeat_summary(&summaries));
+    Ok(())
+}
+
+/// Throughput within this fraction of the maximum is considered to have plateaued.
+#[cfg(feature = "client")]
+const PLATEAU: f64 = 0.95;
+
+#[cfg(feature = "client")]
+fn sweep(config: Config, server: Option<&str>, streams: &[u64]) -> Result<(), anyhow::Error> {
+    let msg: Arc<dyn Fn(&str) + Send + Sync> = Arc::new(|msg| println!("{}", with_time(msg)));
+
+    let mut streams = streams.to_vec();
+    streams.sort_unstable();
+    streams.dedup();
+
+    let mut results = Vec::new();
+    for count in streams {
+        msg(&format!("Testing with {} streams", count));
+        let config = ConfigBuilder::from(config).streams(count).build();
+        let result = run_test(config, server, &[], msg.clone()).map_err(|error| {
+            msg("Client failed");
+            anyhow!(error)
+        })?;
+        let throughput = result
+            .to_test_result()
+            .json_summary()
+            .download
+            .and_then(|phase| phase.throughput_mbps)
+            .ok_or(anyhow!("No download throughput was measured"))?;
+        results.push((count, throughput));
+    }
+    print!("\n{}", sweep_summary(&results));
+    Ok(())
+}
+
+#[cfg(feature = "client")]
+fn sweep_summary(results: &[(u64, f64)]) -> String {
+    let mut output = format!("{:>7}  {:>15}\n", "Streams", "Throughput");
+    for (streams, throughput) in results {
+        output.push_str(&format!("{:>7}  {:>10.2} Mbps\n", streams, throughput));
+    }
+
+    let max = results
+        .iter()
+        .map(|(_, throughput)| *throughput)
+        .fold(0.0, f64::max);
+    if let Some((streams, _)) = results
+        .iter()
+        .find(|(_, throughput)| *throughput >= max * PLATEAU)
+    {
+        output.push_str(&format!(
+            "\nThroughput plateaus at {} stream{}, within {:.0}% of the maximum\n",
+            streams,
+            if *streams == 1 { "" } else { "s" },
+            (1.0 - PLATEAU) * 100.0
+        ));
+    }
+    output
+}
+
+#[cfg(feature = "client")]
+type PhaseGetter = fn(&JsonSummary) -> Option<&JsonPhase>;
+
+#[cfg(feature = "client")]
+fn repeat_summary(summaries: &[JsonSummary]) -> String {
+    let mut metrics: Vec<(String, Vec<f64>)> = vec![(
+        "Idle latency (ms)".to_owned(),
+        summaries.iter().map(|s| s.idle_latency_ms).collect(),
+    )];
+
+    let phases: [(&str, PhaseGetter); 4] = [
+        ("Download", |s| s.download.as_ref()),
+        ("Upload", |s| s.upload.as_ref()),
+        ("Bidirectional", |s| s.bidirectional.as_ref()),
+        ("Idle", |s| s.idle.as_ref()),
+    ];
+    for (name, phase) in phases {
+        let phases: Vec<&JsonPhase> = summaries.iter().filter_map(phase).collect();
+        let mut metric = |label: &str, get: fn(&JsonPhase) -> Option<f64>| {
+            let values: Vec<f64> = phases.iter().filter_map(|phase| get(phase)).collect();
+            if !values.is_empty() {
+                metrics.push((format!("{} {}", name, label), values));
+            }
+        };
+        metric("throughput (Mbps)", |p| p.throughput_mbps);
+        metric("download throughput (Mbps)", |p| p.download_throughput_mbps);
+        metric("upload throughput (Mbps)", |p| p.upload_throughput_mbps);
+        metric("latency (ms)", |p| p.latency.as_ref().map(|l| l.total_ms));
+        metric("jitter (ms)", |p| {
+            p.latency.as_ref().and_then(|l| l.jitter_ms)
+        });
+        metric("packet loss down (%)", |p| {
+            p.packet_loss.as_ref().map(|l| l.down * 100.0)
+        });
+        metric("packet loss up (%)", |p| {
+            p.packet_loss.as_ref().map(|l| l.up * 100.0)
+        });
+    }
+
+    let width = metrics
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let mut output = format!(
+        "-- Statistics over {} runs --\n{:width$}  {:>10} {:>10} {:>10} {:>10}\n",
+        summaries.len(),
+        "",
+        "Mean",
+        "Std dev",
+        "Min",
+        "Max",
+    );
+    for (name, values) in metrics {
+        let count = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / count;
+        let variance = if values.len() > 1 {
+            values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1.0)
+        } else {
+            0.0
+        };
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        output += &format!(
+            "{:width$}  {:>10.2} {:>10.2} {:>10.2} {:>10.2}\n",
+            name,
+            mean,
+            variance.sqrt(),
+            min,
+            max,
+        );
+    }
+    output
+}
+
 fn main() {
     env_logger::init();
 
@@ -287,7 +1101,7 @@ fn main() {
     crusader_lib::plot::register_fonts();
 
     if let Err(error) = run() {
-        println!("Error: {:?}", error);
+        eprintln!("Error: {:?}", error);
         process::exit(1);
     }
 }